@@ -0,0 +1,106 @@
+// frame_latency と frames_in_flight の組み合わせごとに、描画のスループットを比べる計測用の例
+// 垂直同期で頭打ちにならないよう Immediate (なければ Mailbox) にして、同じ星空を FRAMES フレーム描く時間を測る
+// cargo run --release --example frames_in_flight_bench
+use std::time::Instant;
+
+use rust_pentagram::{create_seeded_star_instances, Instance, WgpuState, WgpuStateConfig};
+use winit::{
+    event::{Event, WindowEvent},
+    event_loop::EventLoop,
+    window::{Window, WindowBuilder},
+};
+
+// (frame_latency, frames_in_flight)
+const SETTINGS: [(u32, u32); 4] = [(1, 1), (2, 1), (2, 2), (3, 3)];
+// 計測前に描いて、パイプラインの準備やドライバの立ち上がりを済ませておくフレーム数
+const WARMUP_FRAMES: u32 = 60;
+const FRAMES: u32 = 600;
+// GPU側の負荷を上げて、CPUとGPUの待ち合わせの差が出やすくする
+const STAR_COUNT: usize = 20_000;
+
+fn create_state<'window>(window: &'window Window, instances: &[Instance], setting: (u32, u32)) -> WgpuState<'window> {
+    let (frame_latency, frames_in_flight) = setting;
+    let config = WgpuStateConfig::default()
+        .with_frame_latency(frame_latency)
+        .with_frames_in_flight(frames_in_flight);
+    let mut state = pollster::block_on(WgpuState::with_config(window, config)).expect("Failed to initialize the renderer");
+    if !state.set_present_mode(wgpu::PresentMode::Immediate) && !state.set_present_mode(wgpu::PresentMode::Mailbox) {
+        println!("Immediate and Mailbox are not supported; the results are capped by vsync");
+    }
+    state.set_instances(instances).expect("Failed to upload the instances");
+    state
+}
+
+fn main() {
+    env_logger::init();
+
+    let event_loop = EventLoop::new().expect("Event Loop Error");
+    let window = WindowBuilder::new()
+        .with_title("frames_in_flight benchmark")
+        .build(&event_loop)
+        .unwrap();
+    let instances: Vec<_> = (0..)
+        .flat_map(|seed| create_seeded_star_instances(seed, 0.5))
+        .take(STAR_COUNT)
+        .collect();
+
+    let mut setting_index = 0;
+    let mut state = Some(create_state(&window, &instances, SETTINGS[setting_index]));
+    let mut frame = 0;
+    let mut started_at = Instant::now();
+    let mut results = Vec::new();
+
+    event_loop
+        .run(|event, control_flow| {
+            let Event::WindowEvent { ref event, .. } = event else {
+                return;
+            };
+            match event {
+                WindowEvent::CloseRequested => control_flow.exit(),
+                WindowEvent::RedrawRequested => {
+                    let Some(current) = &mut state else {
+                        return;
+                    };
+                    window.request_redraw();
+                    current.update();
+                    if let Err(error) = current.render() {
+                        log::error!("{error:?}");
+                        control_flow.exit();
+                        return;
+                    }
+                    frame += 1;
+                    if frame == WARMUP_FRAMES {
+                        started_at = Instant::now();
+                    }
+                    if frame < WARMUP_FRAMES + FRAMES {
+                        return;
+                    }
+
+                    // 積んだフレームが描き終わるまで待ってから止める
+                    if let Some(device) = &current.device {
+                        device.poll(wgpu::Maintain::Wait);
+                    }
+                    results.push((SETTINGS[setting_index], started_at.elapsed()));
+                    state = None;
+                    setting_index += 1;
+                    frame = 0;
+                    if setting_index == SETTINGS.len() {
+                        for ((frame_latency, frames_in_flight), elapsed) in &results {
+                            let seconds = elapsed.as_secs_f64();
+                            println!(
+                                "frame_latency={frame_latency} frames_in_flight={frames_in_flight}: \
+                                 {FRAMES} frames in {seconds:.3}s ({:.1} fps, {:.3} ms/frame)",
+                                FRAMES as f64 / seconds,
+                                seconds * 1000.0 / FRAMES as f64,
+                            );
+                        }
+                        control_flow.exit();
+                        return;
+                    }
+                    state = Some(create_state(&window, &instances, SETTINGS[setting_index]));
+                }
+                _ => {}
+            }
+        })
+        .unwrap();
+}
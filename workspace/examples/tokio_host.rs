@@ -0,0 +1,48 @@
+// 既に tokio ランタイムを持つホストから WgpuState を組み込む例
+// native_new (pollster でブロックする) を使わず、new を直接 .await する
+use rust_pentagram::WgpuState;
+use winit::{
+    event::{Event, WindowEvent},
+    event_loop::EventLoop,
+    window::WindowBuilder,
+};
+
+// winit のイベントループはメインスレッドで回す必要があるため current_thread を使う
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    env_logger::init();
+
+    let event_loop = EventLoop::new().expect("Event Loop Error");
+    let window = WindowBuilder::new()
+        .with_title("Pentagrams on tokio")
+        .build(&event_loop)
+        .unwrap();
+
+    let mut state = WgpuState::new(&window).await;
+
+    event_loop
+        .run(move |event, control_flow| {
+            if let Event::WindowEvent { ref event, window_id } = event {
+                if window_id != state.window.id() || state.input(event) {
+                    return;
+                }
+                match event {
+                    WindowEvent::CloseRequested => control_flow.exit(),
+                    WindowEvent::Resized(physical_size) => state.resize(*physical_size),
+                    WindowEvent::RedrawRequested => {
+                        state.window.request_redraw();
+                        state.update();
+                        match state.render() {
+                            Ok(_) | Err(wgpu::SurfaceError::Timeout) => {}
+                            Err(error) => {
+                                log::error!("{error:?}");
+                                control_flow.exit();
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .unwrap();
+}
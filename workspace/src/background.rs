@@ -0,0 +1,197 @@
+use wgpu::util::DeviceExt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BackgroundMode {
+    // 単色でクリアする (背景用のパイプラインは使わない)
+    Solid([f32; 4]),
+    // 上から下への縦方向のグラデーション
+    LinearGradient { top: [f32; 4], bottom: [f32; 4] },
+    // 画面中心から縁への放射状のグラデーション
+    RadialGradient { center: [f32; 4], edge: [f32; 4] },
+}
+
+impl Default for BackgroundMode {
+    fn default() -> Self {
+        Self::Solid([0.0, 0.0, 0.0, 0.0])
+    }
+}
+
+impl BackgroundMode {
+    // 星を描く前にサーフェスをクリアする色
+    pub fn clear_color(&self) -> wgpu::Color {
+        match self {
+            Self::Solid([r, g, b, a]) => wgpu::Color {
+                r: *r as f64,
+                g: *g as f64,
+                b: *b as f64,
+                a: *a as f64,
+            },
+            _ => wgpu::Color::TRANSPARENT,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BackgroundUniforms {
+    pub mode: u32,
+    pub padding: [u32; 3],
+    pub color_a: [f32; 4],
+    pub color_b: [f32; 4],
+}
+
+impl BackgroundUniforms {
+    // 単色は上下が同じ色の縦方向のグラデーションとして表す
+    // 普段はクリアだけで済ませて背景パスを描かず、aspect_lock で帯の内側だけを塗るときに使う
+    pub fn from_mode(mode: &BackgroundMode) -> Self {
+        let (mode, color_a, color_b) = match *mode {
+            BackgroundMode::Solid(color) => (0, color, color),
+            BackgroundMode::LinearGradient { top, bottom } => (0, top, bottom),
+            BackgroundMode::RadialGradient { center, edge } => (1, center, edge),
+        };
+        Self {
+            mode,
+            padding: [0; 3],
+            color_a,
+            color_b,
+        }
+    }
+}
+
+// 星の前に画面全体へグラデーションを描くための専用パイプライン
+pub struct Background {
+    pub mode: BackgroundMode,
+    pub uniform_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub render_pipeline: wgpu::RenderPipeline,
+}
+
+impl Background {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, mode: BackgroundMode) -> Self {
+        let uniforms = BackgroundUniforms::from_mode(&mode);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Background Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("background_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Background Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("./background.wgsl").into()),
+        });
+        let mut constants = std::collections::HashMap::new();
+        constants.insert(
+            String::from("encodeSrgb"),
+            if crate::uniform::shader_encodes_srgb(format) { 1.0 } else { 0.0 },
+        );
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Background Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vertexMain"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fragmentMain"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            mode,
+            uniform_buffer,
+            bind_group,
+            render_pipeline,
+        }
+    }
+
+    pub fn set_mode(&mut self, queue: &wgpu::Queue, mode: BackgroundMode) {
+        self.mode = mode;
+        let uniforms = BackgroundUniforms::from_mode(&mode);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
+
+    pub fn is_gradient(&self) -> bool {
+        !matches!(self.mode, BackgroundMode::Solid(_))
+    }
+
+    // グラデーションを描く前段のパス。この後の星のパスは LoadOp::Load で重ねる
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        self.draw_letterboxed(encoder, view, wgpu::Color::TRANSPARENT, None);
+    }
+
+    // 全体を bar_color でクリアしてから、viewport (x, y, 幅, 高さ のピクセル) の内側だけに背景を描く
+    pub fn draw_letterboxed(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        bar_color: wgpu::Color,
+        viewport: Option<[u32; 4]>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Background Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(bar_color),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        if let Some([x, y, width, height]) = viewport {
+            render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
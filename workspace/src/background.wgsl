@@ -0,0 +1,54 @@
+struct BackgroundUniforms {
+    // 0: 縦方向のグラデーション, 1: 放射状のグラデーション
+    mode: u32,
+    colorA: vec4<f32>,
+    colorB: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@binding(0) @group(0) var<uniform> background: BackgroundUniforms;
+
+// true の場合、サーフェスは sRGB でないフォーマットなので、線形の色に sRGB のガンマを掛けてから出力する
+// 星のシェーダ (src/shader.wgsl) と同じく、パイプライン作成時にサーフェスのフォーマットから設定される
+override encodeSrgb: bool = false;
+
+// 画面全体を覆う1枚の三角形
+@vertex
+fn vertexMain(@builtin(vertex_index) vertexIdx: u32) -> VertexOutput {
+    let x = f32(i32(vertexIdx & 1u) * 4 - 1);
+    let y = f32(i32(vertexIdx >> 1u) * 4 - 1);
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>(x, y) * 0.5 + 0.5;
+    return out;
+}
+
+@fragment
+fn fragmentMain(in: VertexOutput) -> @location(0) vec4<f32> {
+    var t: f32;
+    if background.mode == 0u {
+        // 上 (colorA) から下 (colorB) へ
+        t = 1.0 - in.uv.y;
+    } else {
+        // 中心 (colorA) から縁 (colorB) へ
+        t = clamp(length(in.uv - 0.5) * 2.0, 0.0, 1.0);
+    }
+    let color = mix(background.colorA, background.colorB, t);
+    if encodeSrgb {
+        return vec4<f32>(linearToSrgb(color.rgb), color.a);
+    }
+    return color;
+}
+
+// 線形の色を sRGB の伝達関数で符号化する
+fn linearToSrgb(color: vec3<f32>) -> vec3<f32> {
+    let clamped = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+    let low = clamped * 12.92;
+    let high = 1.055 * pow(clamped, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, clamped <= vec3<f32>(0.0031308));
+}
@@ -0,0 +1,177 @@
+// 星空を見る2Dカメラ
+// 表示中心・拡大率・回転とキャンバスのアスペクト比から、星空の座標をクリップ空間に移す行列を作る
+// 実行中はドラッグで移動、マウスホイールでカーソルを中心に拡大・縮小、Q / E キーで回転できる
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Camera2D {
+    // 画面中心に映す星空の座標
+    pub center: [f32; 2],
+    pub zoom: f32,
+    // カメラの回転角 (ラジアン、反時計回り)。星空は逆向きに回って見える
+    pub rotation: f32,
+    // キャンバスの 幅 / 高さ
+    pub aspect: f32,
+}
+
+impl Default for Camera2D {
+    fn default() -> Self {
+        Self {
+            center: [0.0, 0.0],
+            zoom: 1.0,
+            rotation: 0.0,
+            aspect: 1.0,
+        }
+    }
+}
+
+impl Camera2D {
+    // zoom_at で寄れる・引ける範囲
+    pub const MIN_ZOOM: f32 = 0.1;
+    pub const MAX_ZOOM: f32 = 50.0;
+
+    pub fn set_aspect(&mut self, width: u32, height: u32) {
+        self.aspect = width.max(1) as f32 / height.max(1) as f32;
+    }
+
+    // -1.0〜1.0 の正方形の空間が短い辺いっぱいに収まるように、長い辺の方向を広げたときの画面の半分の大きさ
+    // 星空を切り取らずに全体を映す (長い辺の両端には -1.0〜1.0 の外側が映る)
+    fn half_extent(&self) -> (f32, f32) {
+        let (half_width, half_height) = if self.aspect >= 1.0 {
+            (self.aspect, 1.0)
+        } else {
+            (1.0, 1.0 / self.aspect)
+        };
+        (half_width / self.zoom, half_height / self.zoom)
+    }
+
+    // 星空の座標からクリップ空間への正射影行列 (列優先、深度は wgpu の 0.0〜1.0)
+    pub fn view_matrix(&self) -> [[f32; 4]; 4] {
+        let (half_width, half_height) = self.half_extent();
+        let (sin, cos) = self.rotation.sin_cos();
+        let [x, y] = self.center;
+        [
+            [cos / half_width, -sin / half_height, 0.0, 0.0],
+            [sin / half_width, cos / half_height, 0.0, 0.0],
+            [0.0, 0.0, 0.5, 0.0],
+            [
+                -(cos * x + sin * y) / half_width,
+                (sin * x - cos * y) / half_height,
+                0.5,
+                1.0,
+            ],
+        ]
+    }
+
+    // キャンバスのクリップ座標 (-1.0〜1.0、y は上向き) に映っている星空の座標 (view_matrix の逆変換)
+    pub fn clip_to_world(&self, clip: [f32; 2]) -> [f32; 2] {
+        let (half_width, half_height) = self.half_extent();
+        let mut world = self.center;
+        Self::rotate_into(&mut world, self.rotation, [clip[0] * half_width, clip[1] * half_height]);
+        world
+    }
+
+    // 画面上の向き (カメラの回転前) で表した移動量だけ表示中心を動かす
+    pub fn pan(&mut self, delta: [f32; 2]) {
+        Self::rotate_into(&mut self.center, self.rotation, delta);
+    }
+
+    // クリップ座標 clip に映っている星空の点を動かさずに、拡大率を factor 倍する (MIN_ZOOM〜MAX_ZOOM に収める)
+    pub fn zoom_at(&mut self, clip: [f32; 2], factor: f32) {
+        let before = self.clip_to_world(clip);
+        self.zoom = (self.zoom * factor).clamp(Self::MIN_ZOOM, Self::MAX_ZOOM);
+        let after = self.clip_to_world(clip);
+        self.center[0] += before[0] - after[0];
+        self.center[1] += before[1] - after[1];
+    }
+
+    // 画面中心を軸にカメラを angle (ラジアン) だけ反時計回りに回す
+    pub fn rotate(&mut self, angle: f32) {
+        self.rotation = (self.rotation + angle).rem_euclid(std::f32::consts::TAU);
+    }
+
+    // delta を rotation だけ回転させて point に加える
+    fn rotate_into(point: &mut [f32; 2], rotation: f32, delta: [f32; 2]) {
+        let (sin, cos) = rotation.sin_cos();
+        point[0] += cos * delta[0] - sin * delta[1];
+        point[1] += sin * delta[0] + cos * delta[1];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cameras() -> Vec<Camera2D> {
+        let mut cameras = Vec::new();
+        for aspect in [0.5, 1.0, 16.0 / 9.0] {
+            for (center, zoom, rotation) in [([0.0, 0.0], 1.0, 0.0), ([0.3, -0.7], 2.5, 0.8), ([-1.2, 0.4], 0.4, -2.0)] {
+                cameras.push(Camera2D { center, zoom, rotation, aspect });
+            }
+        }
+        cameras
+    }
+
+    // view_matrix で星空の点をクリップ空間に移す (w は 1.0 のまま)
+    fn to_clip(camera: &Camera2D, [x, y]: [f32; 2]) -> [f32; 2] {
+        let m = camera.view_matrix();
+        [m[0][0] * x + m[1][0] * y + m[3][0], m[0][1] * x + m[1][1] * y + m[3][1]]
+    }
+
+    fn assert_close(a: [f32; 2], b: [f32; 2]) {
+        assert!((a[0] - b[0]).abs() < 1e-4 && (a[1] - b[1]).abs() < 1e-4, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn clip_to_world_inverts_view_matrix() {
+        for camera in cameras() {
+            for world in [[0.0, 0.0], [0.5, -0.25], [-0.9, 0.8]] {
+                assert_close(camera.clip_to_world(to_clip(&camera, world)), world);
+            }
+            for clip in [[0.0, 0.0], [1.0, 1.0], [-0.6, 0.3]] {
+                assert_close(to_clip(&camera, camera.clip_to_world(clip)), clip);
+            }
+            // 表示中心は画面中心に映る
+            assert_close(to_clip(&camera, camera.center), [0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn unit_square_fits_the_short_side() {
+        for (width, height) in [(800, 400), (400, 400), (1080, 1920)] {
+            let mut camera = Camera2D::default();
+            camera.set_aspect(width, height);
+            // 星空の四隅はすべて画面に映り、短い辺の方向ではちょうど端に来る
+            let corner = to_clip(&camera, [1.0, 1.0]);
+            assert!(corner[0] <= 1.0 + 1e-6 && corner[1] <= 1.0 + 1e-6, "{corner:?}");
+            assert!((corner[0].max(corner[1]) - 1.0).abs() < 1e-6, "{corner:?}");
+            // 1ピクセル分のクリップ座標の差は、world_units_per_pixel の長さに当たる
+            let pixel = camera.clip_to_world([2.0 / width as f32, 0.0])[0] - camera.clip_to_world([0.0, 0.0])[0];
+            assert!((pixel - crate::uniform::world_units_per_pixel(width, height, camera.zoom)).abs() < 1e-7);
+        }
+    }
+
+    #[test]
+    fn zoom_at_keeps_the_point_under_the_cursor() {
+        for mut camera in cameras() {
+            let clip = [0.4, -0.6];
+            let world = camera.clip_to_world(clip);
+            camera.zoom_at(clip, 1.5);
+            assert_close(camera.clip_to_world(clip), world);
+            camera.zoom_at(clip, 1e6);
+            assert_eq!(camera.zoom, Camera2D::MAX_ZOOM);
+            assert_close(camera.clip_to_world(clip), world);
+        }
+    }
+
+    #[test]
+    fn pan_and_rotate_move_the_view() {
+        let mut camera = Camera2D::default();
+        camera.rotate(std::f32::consts::FRAC_PI_2);
+        // 90° 回したカメラで画面の右へ動かすと、星空の上向きに中心が動く
+        camera.pan([0.5, 0.0]);
+        assert_close(camera.center, [0.0, 0.5]);
+        assert_close(to_clip(&camera, [0.0, 0.5]), [0.0, 0.0]);
+        camera.rotate(-std::f32::consts::FRAC_PI_2);
+        assert!(camera.rotation.abs() < 1e-6 || (camera.rotation - std::f32::consts::TAU).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,85 @@
+// アニメーションの時刻から作る、個別に止めたり再開したりできる時刻 (星の移動と自転を別々に止めるのに使う)
+// 止めていた長さを差し引いて進むので、再開しても値が跳ばない
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PausableClock {
+    // 止めた時点のアニメーションの時刻。None なら進んでいる
+    paused_at: Option<f32>,
+    // これまでに止めていた長さの合計 (秒)
+    offset: f32,
+}
+
+impl PausableClock {
+    // アニメーションの時刻 time におけるこの時計の時刻
+    pub fn at(&self, time: f32) -> f32 {
+        self.paused_at.unwrap_or(time) - self.offset
+    }
+
+    // アニメーションの時刻 time の時点で止める。止めている間は pause しても変わらない
+    pub fn pause(&mut self, time: f32) {
+        self.paused_at.get_or_insert(time);
+    }
+
+    // アニメーションの時刻 time の時点から、止めたときの値の続きで進める
+    pub fn resume(&mut self, time: f32) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.offset += time - paused_at;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+}
+
+// 1フレーム分のフレーム間隔の平均とアニメーションの時刻の進め方
+// interval を max_delta で切り詰めてから、前の平均 previous に smoothing の重みで加えた新しい平均を返し、時刻はその平均だけ進める
+// フレーム間隔がばらついても星は一定の速さで動き、一度だけ遅れたフレーム (ウィンドウのドラッグなど) でも跳ばない
+// clamp_time_gaps が false なら、max_delta を超えて空いた分はそのまま足して壁時計に追いつかせる
+// 戻り値は (新しい平均, アニメーションの時刻を進める秒数)
+pub(crate) fn frame_step(previous: f64, interval: f64, max_delta: f64, smoothing: f64, clamp_time_gaps: bool) -> (f64, f64) {
+    let smoothed = previous + (interval.min(max_delta) - previous) * smoothing;
+    let gap = if clamp_time_gaps {
+        0.0
+    } else {
+        (interval - max_delta).max(0.0)
+    };
+    (smoothed, smoothed + gap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: f64 = 1.0 / 60.0;
+    const MAX_DELTA: f64 = 1.0 / 30.0;
+
+    #[test]
+    fn long_gap_advances_at_most_max_delta() {
+        for smoothing in [0.1, 0.5, 1.0] {
+            let mut smoothed = FRAME;
+            let mut time = 0.0;
+            // 10秒止まった後に、通常のフレームが続く
+            for interval in std::iter::once(10.0).chain(std::iter::repeat_n(FRAME, 600)) {
+                let (next, advance) = frame_step(smoothed, interval, MAX_DELTA, smoothing, true);
+                assert!(advance <= MAX_DELTA + 1e-12, "advanced {advance} in one frame");
+                smoothed = next;
+                time += advance;
+            }
+            // 空白のうち時刻に加わるのは max_delta までで、残りは捨てられる
+            assert!(time <= 600.0 * FRAME + MAX_DELTA + 1e-9, "{time}");
+        }
+    }
+
+    #[test]
+    fn unclamped_gap_catches_up_with_the_wall_clock() {
+        let (_, advance) = frame_step(FRAME, 10.0, MAX_DELTA, 0.5, false);
+        assert!((advance - (10.0 - MAX_DELTA) - (FRAME + (MAX_DELTA - FRAME) * 0.5)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn steady_frames_advance_by_the_frame_time() {
+        let (smoothed, advance) = frame_step(FRAME, FRAME, MAX_DELTA, 0.1, true);
+        assert!((smoothed - FRAME).abs() < 1e-12 && (advance - FRAME).abs() < 1e-12);
+    }
+}
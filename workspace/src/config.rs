@@ -0,0 +1,756 @@
+use crate::background::BackgroundMode;
+use crate::error::ConfigError;
+use crate::gradient::SpatialGradient;
+use crate::group::GroupParams;
+use crate::instance::InstanceParams;
+use crate::morph::{Easing, IntroEdge};
+use crate::outline::OutlineStyle;
+use crate::physics::PhysicsMode;
+use crate::uniform::UniformUpdateMode;
+
+#[derive(Debug, Clone)]
+pub struct WgpuStateConfig {
+    // SurfaceConfiguration の desired_maximum_frame_latency に渡す値
+    // 1 は入力から表示までの遅延が最小になるが、GPUに先行してフレームを積めないためスループットが落ちやすい
+    // 2〜3 は遅延が1〜2フレーム増える代わりに、負荷の揺れを吸収できてフレーム間隔が安定しやすい
+    pub frame_latency: u32,
+    // 順番に使い回す Uniforms のバッファの数 (1以上)
+    // 2 以上にすると、前のフレームがまだGPUで読まれている間も次のフレームの値を別のバッファへ書き込める
+    // frame_latency と同じ値にすると、積んだフレームがそれぞれ自分のバッファを持つ
+    pub frames_in_flight: u32,
+    // true の場合、星形の代わりに各インスタンスを四角形1枚で描き、フラグメントシェーダで丸くぼかした点にする
+    pub point_mode: bool,
+    // true の場合、星形を塗りつぶさずに輪郭線だけを描く (point_mode より優先)
+    // 線は頂点シェーダで画面空間の四角形に展開し、縁をぼかしてアンチエイリアスする
+    pub outline_mode: bool,
+    // 星の頂点の数 n と、輪郭で何個先の頂点に結ぶか m ({n/m} 星形多角形)
+    // m が大きいほど尖った星になる。1 < m < n で、n と互いに素でないと一筆書きの星にならない
+    pub star_points: u32,
+    pub star_skip: u32,
+    // true の場合、星形の基準の向きを上下反転する (最初の外側の頂点を -y 側ではなく +y 側に置く)
+    // π 回転なので巻き方向は変わらず、塗りつぶしの裏面カリングもそのまま効く
+    // 各星は initial_rotation と rotation_speed で回るので、向きが揃って見えるのはこれらが 0 の星だけ
+    pub inverted: bool,
+    // 星の先端の鋭さ。内側のくぼみの半径 r (0.0〜1.0) を r^tip_sharpness にずらす (0.0 より大きい値)
+    // 1.0 で {star_points/star_skip} そのまま、大きいほど針のように細く、小さいほど太く丸い星になる
+    // 1.0 以外では、外側の頂点とくぼみを交互に結んだ 2 * star_points 枚の三角形で塗り、輪郭線もくぼみを通る
+    pub tip_sharpness: f32,
+    // Some([points, skip]) の場合、各星を {star_points/star_skip} と {points/skip} の間で時間とともに行き来させる
+    // skip が 1 なら正多角形。星形は両方の輪郭を同じ数の点で取り直した扇状のメッシュで描き、頂点ごとに補間する
+    // 補間の割合は 0.5 + 0.5 * sin(time * shape_morph_rate)。塗りつぶしの星形のときだけ効く
+    pub shape_morph_target: Option<[u32; 2]>,
+    // 形の補間の速さ (ラジアン/秒)
+    pub shape_morph_rate: f32,
+    // 塗りつぶしの星形の各三角形を辺ごとに何回分割するか。0 で分割しない
+    // 三角形の数は (subdivisions + 1)^2 倍になる
+    pub subdivisions: u32,
+    // 星の大きさ (Instance の scale) で詳細度を切り替える境界 (昇順)。空の場合は全ての星を同じメッシュで描く
+    // 境界が n 個なら n + 1 段階のメッシュを作り、最も小さい星は分割なし、最も大きい星は subdivisions で分割したものを使う
+    // 塗りつぶしの星形のときだけ効く
+    pub lod_thresholds: Vec<f32>,
+    // 輪郭線の太さ (ピクセル)
+    pub line_width: f32,
+    // Some の場合、大きさ (scale) 1.0 の星の外接円の半径をこのピクセル数にする
+    // ウィンドウの大きさやカメラの拡大に関わらず画面上の大きさが変わらない。None の場合は星空の座標 (半径 1.0) のまま
+    pub radius_pixels: Option<f32>,
+    // Some の場合、塗りつぶしの星形の上に、外周の辺をなぞる縁取りをこの色と太さで重ねる (point_mode / outline_mode では描かない)
+    // 縁取りは補間前の星形の輪郭なので、shape_morph_target の形の補間には付いていかない
+    pub outline_style: Option<OutlineStyle>,
+    // Some の場合、星ごとの基本色 (既定の黄色) の代わりに、星の中心の位置でこのグラデーションから色を取る
+    // 星空全体で1つの色の流れになる。ドップラー効果やグループの色合いはその上に掛かる
+    pub spatial_gradient: Option<SpatialGradient>,
+    // true の場合、フラグメントシェーダが RGB にアルファを掛けた乗算済みアルファを出力し、ブレンドもそれに合わせる
+    // サーフェスの alpha_mode は対応していれば PreMultiplied を選ぶ。ページやデスクトップに重ねて合成するとき、
+    // ストレートアルファのまま PreMultiplied として合成されると星の縁が暗く縁取られる
+    // カスタムのフラグメントシェーダを使う場合は、シェーダ側で乗算する必要がある
+    pub premultiplied_alpha: bool,
+    // true の場合、run() はウィンドウを透過ありで作り、サーフェスも Opaque 以外の alpha_mode を選ぶ
+    // プラットフォームが透過に対応していなければ、背景は不透明なクリア色で描く
+    pub transparent_window: bool,
+    // true の場合はスクリーンセーバーとして動かす。run() はカーソルを隠した全画面のウィンドウを作り、
+    // キーやマウスボタンを押すか、ホイールを回すか、カーソルを少しでも大きく動かすとすぐに終了する
+    pub screensaver: bool,
+    // 毎フレームの Uniforms の書き込み方。OnChange では前のフレームから変わった範囲だけをGPUへ送る
+    pub uniform_updates: UniformUpdateMode,
+    // true の場合、サーフェスが Rgba16Float に対応していればそれを選び、星の中心を SDR の白 (1.0) より明るく出力する
+    // 対応していなければ警告を出して通常のフォーマットで描く
+    pub hdr: bool,
+    // 星を描くレンダーパスの結果をテクスチャに書き戻すかどうか
+    // サーフェスのテクスチャに Discard を指定すると表示される内容は未定義になるので、ほぼ常に Store のままでよい
+    // 解決 (resolve) 後に使わないマルチサンプルの中間テクスチャなどに描く場合は、Discard でメモリ帯域を節約できる
+    pub store_op: wgpu::StoreOp,
+    // 星の後ろに描く背景 (単色 / 縦グラデーション / 放射状グラデーション)
+    pub background: BackgroundMode,
+    // ウィンドウがフォーカスを失っている間、アニメーションの時間を止めるかどうか
+    // false の場合は描画だけ止め、復帰時にはその間の時間が経過した状態から再開する
+    pub freeze_time_when_unfocused: bool,
+    // true の場合、TIMESTAMP クエリでレンダーパスのGPU実行時間を計測して表示する
+    // アダプタが TIMESTAMP_QUERY に対応していない場合は無効になる。結果は完了を待たずに読み戻すので、数フレーム遅れて集計される
+    pub gpu_timing: bool,
+    // true の場合、グループと詳細度の段階の組ごとの描画引数を WgpuState::indirect_buffer に置き、draw_indexed_indirect で描く
+    // アダプタが間接描画と INDIRECT_FIRST_INSTANCE に対応していない場合は、通常の描画のまま
+    pub indirect: bool,
+    // false の場合、毎フレームの描画時間の集計と60フレームごとの表示を行わない (落ちたフレームの数は常に数える)
+    // デフォルトではデバッグビルドのみ有効
+    pub frame_stats_enabled: bool,
+    // true の場合、起動時に星形のインデックスの巻き方向を検証し、カリングで消える三角形を警告する
+    pub debug_geometry: bool,
+    // set_instances で受け付けるインスタンス数の上限
+    // None の場合はデバイスの max_buffer_size に収まる数を上限にする
+    pub max_instances: Option<usize>,
+    // population_rate が 0.0 より大きい場合、描く星の数を population_min から population_max の間で
+    // min + (max - min) * 0.5 * (1 + sin(時刻 * population_rate)) のように増減させる
+    // バッファは作り直さず、描画するインスタンスの範囲を先頭から切り詰めるだけなので、描く順番の後ろの星から消える
+    // population_max が None の場合は全ての星を上限にする
+    pub population_min: u32,
+    pub population_max: Option<u32>,
+    pub population_rate: f32,
+    // true の場合、古い・特殊なハードウェアでも初期化できるよう控えめな設定に揃える
+    // ネイティブでも GL バックエンドを優先し、PresentMode を Fifo に固定し、GPU時間の計測などの任意機能を使わない
+    pub safe_mode: bool,
+    // true の場合、ネイティブでは Vulkan バックエンドを明示的に選び、wgpu のバリデーションとデバッグラベルを有効にする
+    // Vulkan の検証レイヤー (Vulkan SDK など) が入っていないと効果が無く、Vulkan が見つからなければ通常のバックエンドに戻す
+    // Web では無視する
+    pub debug_validation: bool,
+    // 起動時に星空全体を透明から不透明へフェードインさせる秒数。0.0 で無効
+    pub startup_fade_secs: f32,
+    // Some の場合、アニメーションの時刻をこの値 (秒) に固定した静止画として描く
+    // run() はイベント待ちになり、大きさの変更や入力で見た目が変わったときだけ描き直す
+    pub static_time: Option<f32>,
+    // true の場合、set_time で時刻が固定されていて補間中の morph_to も無いときは、static_time と同じくイベント待ちにする
+    // 時刻の固定を解くと次の描画から毎フレームの再描画に戻る。on_render で毎フレーム描き変える場合は false のままにする
+    pub idle_when_static: bool,
+    // WgpuState::smoothed_delta の指数移動平均で、新しいフレーム間隔に掛ける重み (0.0 より大きく 1.0 以下)
+    // アニメーションの時刻は毎フレームこの平均だけ進む。小さいほど動きが滑らかになり、1.0 で平均を取らない
+    pub delta_smoothing: f32,
+    // 平均に加える前にフレーム間隔を切り詰める上限 (秒)
+    pub max_delta: f32,
+    // true の場合、アニメーションの時刻も1フレームで max_delta 秒までしか進めない
+    // 初期化の読み込みやサスペンドなどで前のフレームから長く空いても、星が一気に飛ばずに続きから動く
+    // false の場合は max_delta を超えて空いた分をそのまま足し、アニメーションの時刻を壁時計に追いつかせる
+    pub clamp_time_gaps: bool,
+    // 起動時に星が画面外の intro_edge から本来の位置へ減速しながら飛んでくる秒数。0.0 で無効
+    pub intro_duration: f32,
+    pub intro_edge: IntroEdge,
+    // 複数のモニタにまたがる1枚の星空を描くときの、仮想キャンバス全体の大きさ (ピクセル)
+    // None の場合はこのウィンドウだけでキャンバス全体になる
+    pub viewport_total: Option<[u32; 2]>,
+    // 仮想キャンバスの中でこのウィンドウが受け持つ領域の左上の位置 (ピクセル)
+    pub viewport_offset: [u32; 2],
+    // Some の場合、星空をこの縦横比 (幅 / 高さ) の、サーフェスの中央に収まる最大の矩形だけに描き、
+    // 残りの上下または左右の帯を letterbox_color で塗る。ウィンドウの形に関わらず同じ範囲が映る
+    pub aspect_lock: Option<f32>,
+    pub letterbox_color: [f32; 4],
+    // Some([幅, 高さ]) の場合、星はこの大きさのテクスチャに描き、最後にウィンドウ (aspect_lock の矩形) へ引き伸ばして写す
+    // 描画の重さがウィンドウの大きさに左右されなくなる。line_width や radius_pixels はこのテクスチャのピクセル数で数える
+    // テクスチャはウィンドウの大きさを変えても作り直さず、set_internal_resolution で大きさを変えたときだけ作り直す
+    pub internal_resolution: Option<[u32; 2]>,
+    // internal_resolution のテクスチャを引き伸ばすときのフィルタ。Nearest でドット絵のように、Linear で滑らかになる
+    pub upscale_filter: wgpu::FilterMode,
+    // true の場合、初期化の最後に WgpuState::warm_up でパイプラインを事前にコンパイルさせ、最初のフレームの引っかかりを防ぐ
+    // 完了までスレッドを待たせるため、初期化そのものは少し遅くなる
+    pub warm_up_pipeline: bool,
+    // 反時計回りに自転する星の割合 (0.0〜1.0)。残りは時計回りに自転する
+    pub spin_bias: f32,
+    // 星空を生成するとき、星の先端を画面端 (-1.0〜1.0) からさらにこの距離だけ内側に収める。0.0〜1.0 未満
+    // 0.0 でも星ごとの大きさの分は内側に置くので、最初のフレームで端の星が欠けることはない
+    pub spawn_margin: f32,
+    // 星空を生成するときに、星ごとの大きさ・移動の速さ・自転の速さを引く範囲
+    // 実行中は [ ] で大きさ、- = で移動の速さ、, . で自転の速さを変えて作り直せる
+    pub instance_params: InstanceParams,
+    // 0.0 より大きい場合、生成する星ごとに中心をこの半径以内でランダムにずらして歪ませる (星の半径を 1.0 とする)
+    // 塗りつぶしの星形のときだけ見た目に効く。0.0〜1.0 未満
+    pub center_jitter: f32,
+    // WgpuState::morph_to でインスタンス配置を切り替えるときの補間の緩急
+    pub morph_easing: Easing,
+    // 星空を連続したインスタンスの範囲ごとに分け、色合いと速さを変える。空の場合は全体で1グループ
+    pub groups: Vec<GroupParams>,
+    // 星が画面端に達したときの振る舞い。実行中は P キーで切り替えられる
+    pub physics_mode: PhysicsMode,
+    // 0.0 より大きい場合、この距離より近い星どうしを1ピクセル幅の線で結ぶ (星空の座標、画面の縦横が 2.0)
+    // 線の色は link_color で、アルファは近いほど強く、link_distance ちょうどで 0.0 になる
+    // 毎フレームCPUで組を探して頂点を作り直すので、星の数が多いほど重くなる
+    pub link_distance: f32,
+    pub link_color: [f32; 4],
+    // true の場合、座標を確かめるため星の後ろに 0.1 ごとの目盛り線と、原点を通る軸 (x 軸は赤、y 軸は緑) を描く
+    // 線は星空の座標 (-1.0〜1.0) で引くのでカメラに合わせて動き、±1.0 の縁は少し明るくする
+    pub debug_grid: bool,
+    // true の場合、毎フレームCPUで各星が画面 (カメラで拡大・移動した後の表示範囲) に掛かるかを調べ、映るものだけを描く
+    // インスタンスバッファはそのままで、映る星の連続した範囲ごとに描画を分ける。拡大して星の多くが画面外にあるときに効く
+    pub viewport_culling: bool,
+    // 0.0 以外の場合、カーソルの近くの星をカーソルの方へ加速させる (負の値では押しのける)。0.01 前後が目安
+    // 毎フレームCPUで速度に加速度を足して軌道を曲げるので、カーソルが離れても曲がった向きのまま進む。A キーで切り替えられる
+    pub attractor_strength: f32,
+    // true の場合、毎フレームCPUで重なった星どうしを探し、ビリヤードの球のように弾き合わせる (星の半径は scale)
+    // 質量は半径の2乗に比例する。link_distance と同じマス目で近い組だけを調べるが、星の数が多いほど重くなる
+    pub collisions: bool,
+    // 衝突の反発係数。1.0 で完全弾性衝突、0.0 でぶつかった方向の速度が打ち消し合う。0.0〜1.0
+    pub restitution: f32,
+    // 同梱の shader.wgsl の代わりに使うWGSL。
+    // 頂点側は `vertexMain` (輪郭線の描画時は `vertexLine`) を @vertex、フラグメント側は `fragmentMain`
+    // (ポイント描画時は `fragmentPoint`、輪郭線の描画時は `fragmentLine`) を @fragment のエントリポイントとして宣言し、
+    // 同梱シェーダと同じ頂点レイアウトと uniform のバインディングを使う必要がある
+    pub vertex_shader: Option<String>,
+    pub fragment_shader: Option<String>,
+}
+
+impl Default for WgpuStateConfig {
+    fn default() -> Self {
+        Self {
+            frame_latency: 2,
+            frames_in_flight: 1,
+            point_mode: false,
+            outline_mode: false,
+            star_points: 5,
+            star_skip: 2,
+            inverted: false,
+            tip_sharpness: 1.0,
+            shape_morph_target: None,
+            shape_morph_rate: 1.0,
+            subdivisions: 0,
+            lod_thresholds: Vec::new(),
+            line_width: 2.0,
+            radius_pixels: None,
+            outline_style: None,
+            spatial_gradient: None,
+            premultiplied_alpha: false,
+            transparent_window: false,
+            screensaver: false,
+            uniform_updates: UniformUpdateMode::OnChange,
+            hdr: false,
+            store_op: wgpu::StoreOp::Store,
+            background: BackgroundMode::default(),
+            freeze_time_when_unfocused: true,
+            gpu_timing: false,
+            indirect: false,
+            frame_stats_enabled: cfg!(debug_assertions),
+            debug_geometry: false,
+            max_instances: None,
+            population_min: 0,
+            population_max: None,
+            population_rate: 0.0,
+            safe_mode: false,
+            debug_validation: false,
+            startup_fade_secs: 0.0,
+            static_time: None,
+            idle_when_static: false,
+            delta_smoothing: 0.1,
+            max_delta: 0.25,
+            clamp_time_gaps: false,
+            intro_duration: 0.0,
+            intro_edge: IntroEdge::default(),
+            viewport_total: None,
+            viewport_offset: [0, 0],
+            aspect_lock: None,
+            letterbox_color: [0.0, 0.0, 0.0, 1.0],
+            internal_resolution: None,
+            upscale_filter: wgpu::FilterMode::Nearest,
+            warm_up_pipeline: false,
+            spin_bias: 0.5,
+            spawn_margin: 0.0,
+            instance_params: InstanceParams::default(),
+            center_jitter: 0.0,
+            morph_easing: Easing::default(),
+            groups: Vec::new(),
+            physics_mode: PhysicsMode::default(),
+            link_distance: 0.0,
+            link_color: [0.6, 0.8, 1.0, 0.5],
+            debug_grid: false,
+            viewport_culling: false,
+            attractor_strength: 0.0,
+            collisions: false,
+            restitution: 1.0,
+            vertex_shader: None,
+            fragment_shader: None,
+        }
+    }
+}
+
+impl WgpuStateConfig {
+    // デフォルトの設定で初期化に失敗する環境向けに、互換性を優先したセーフモードの設定を作る
+    pub fn safe() -> Self {
+        Self {
+            safe_mode: true,
+            gpu_timing: false,
+            indirect: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_indirect(mut self, indirect: bool) -> Self {
+        self.indirect = indirect;
+        self
+    }
+
+    pub fn with_frame_latency(mut self, frame_latency: u32) -> Self {
+        self.frame_latency = frame_latency;
+        self
+    }
+
+    pub fn with_frames_in_flight(mut self, frames_in_flight: u32) -> Self {
+        self.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    pub fn with_point_mode(mut self, point_mode: bool) -> Self {
+        self.point_mode = point_mode;
+        self
+    }
+
+    pub fn with_outline_mode(mut self, outline_mode: bool) -> Self {
+        self.outline_mode = outline_mode;
+        self
+    }
+
+    pub fn with_star(mut self, points: u32, skip: u32) -> Self {
+        self.star_points = points;
+        self.star_skip = skip;
+        self
+    }
+
+    pub fn with_inverted(mut self, inverted: bool) -> Self {
+        self.inverted = inverted;
+        self
+    }
+
+    pub fn with_tip_sharpness(mut self, tip_sharpness: f32) -> Self {
+        self.tip_sharpness = tip_sharpness;
+        self
+    }
+
+    pub fn with_subdivisions(mut self, subdivisions: u32) -> Self {
+        self.subdivisions = subdivisions;
+        self
+    }
+
+    pub fn with_lod_thresholds(mut self, lod_thresholds: Vec<f32>) -> Self {
+        self.lod_thresholds = lod_thresholds;
+        self
+    }
+
+    pub fn with_line_width(mut self, line_width: f32) -> Self {
+        self.line_width = line_width;
+        self
+    }
+
+    pub fn with_radius_pixels(mut self, radius_pixels: Option<f32>) -> Self {
+        self.radius_pixels = radius_pixels;
+        self
+    }
+
+    pub fn with_outline_style(mut self, outline_style: Option<OutlineStyle>) -> Self {
+        self.outline_style = outline_style;
+        self
+    }
+
+    pub fn with_spatial_gradient(mut self, spatial_gradient: Option<SpatialGradient>) -> Self {
+        self.spatial_gradient = spatial_gradient;
+        self
+    }
+
+    pub fn with_premultiplied_alpha(mut self, premultiplied_alpha: bool) -> Self {
+        self.premultiplied_alpha = premultiplied_alpha;
+        self
+    }
+
+    pub fn with_transparent_window(mut self, transparent_window: bool) -> Self {
+        self.transparent_window = transparent_window;
+        self
+    }
+
+    pub fn with_screensaver(mut self, screensaver: bool) -> Self {
+        self.screensaver = screensaver;
+        self
+    }
+
+    pub fn with_uniform_updates(mut self, uniform_updates: UniformUpdateMode) -> Self {
+        self.uniform_updates = uniform_updates;
+        self
+    }
+
+    pub fn with_hdr(mut self, hdr: bool) -> Self {
+        self.hdr = hdr;
+        self
+    }
+
+    pub fn with_background(mut self, background: BackgroundMode) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn with_freeze_time_when_unfocused(mut self, freeze: bool) -> Self {
+        self.freeze_time_when_unfocused = freeze;
+        self
+    }
+
+    pub fn with_gpu_timing(mut self, gpu_timing: bool) -> Self {
+        self.gpu_timing = gpu_timing;
+        self
+    }
+
+    pub fn with_frame_stats_enabled(mut self, enabled: bool) -> Self {
+        self.frame_stats_enabled = enabled;
+        self
+    }
+
+    pub fn with_debug_geometry(mut self, debug_geometry: bool) -> Self {
+        self.debug_geometry = debug_geometry;
+        self
+    }
+
+    pub fn with_safe_mode(mut self, safe_mode: bool) -> Self {
+        self.safe_mode = safe_mode;
+        self
+    }
+
+    pub fn with_debug_validation(mut self, debug_validation: bool) -> Self {
+        self.debug_validation = debug_validation;
+        self
+    }
+
+    pub fn with_max_instances(mut self, max_instances: usize) -> Self {
+        self.max_instances = Some(max_instances);
+        self
+    }
+
+    pub fn with_population_pulse(mut self, min: u32, max: Option<u32>, rate: f32) -> Self {
+        self.population_min = min;
+        self.population_max = max;
+        self.population_rate = rate;
+        self
+    }
+
+    pub fn with_startup_fade_secs(mut self, secs: f32) -> Self {
+        self.startup_fade_secs = secs;
+        self
+    }
+
+    pub fn with_static_time(mut self, time: f32) -> Self {
+        self.static_time = Some(time);
+        self
+    }
+
+    pub fn with_idle_when_static(mut self, idle_when_static: bool) -> Self {
+        self.idle_when_static = idle_when_static;
+        self
+    }
+
+    pub fn with_delta_smoothing(mut self, smoothing: f32, max_delta: f32) -> Self {
+        self.delta_smoothing = smoothing;
+        self.max_delta = max_delta;
+        self
+    }
+
+    pub fn with_clamp_time_gaps(mut self, clamp_time_gaps: bool) -> Self {
+        self.clamp_time_gaps = clamp_time_gaps;
+        self
+    }
+
+    pub fn with_intro(mut self, duration: f32, edge: IntroEdge) -> Self {
+        self.intro_duration = duration;
+        self.intro_edge = edge;
+        self
+    }
+
+    pub fn with_viewport(mut self, offset: [u32; 2], total: [u32; 2]) -> Self {
+        self.viewport_offset = offset;
+        self.viewport_total = Some(total);
+        self
+    }
+
+    pub fn with_aspect_lock(mut self, aspect_lock: Option<f32>) -> Self {
+        self.aspect_lock = aspect_lock;
+        self
+    }
+
+    pub fn with_letterbox_color(mut self, letterbox_color: [f32; 4]) -> Self {
+        self.letterbox_color = letterbox_color;
+        self
+    }
+
+    pub fn with_internal_resolution(mut self, internal_resolution: Option<[u32; 2]>, filter: wgpu::FilterMode) -> Self {
+        self.internal_resolution = internal_resolution;
+        self.upscale_filter = filter;
+        self
+    }
+
+    pub fn with_warm_up_pipeline(mut self, warm_up: bool) -> Self {
+        self.warm_up_pipeline = warm_up;
+        self
+    }
+
+    pub fn with_spin_bias(mut self, spin_bias: f32) -> Self {
+        self.spin_bias = spin_bias;
+        self
+    }
+
+    pub fn with_spawn_margin(mut self, spawn_margin: f32) -> Self {
+        self.spawn_margin = spawn_margin;
+        self
+    }
+
+    pub fn with_instance_params(mut self, instance_params: InstanceParams) -> Self {
+        self.instance_params = instance_params;
+        self
+    }
+
+    pub fn with_morph_easing(mut self, easing: Easing) -> Self {
+        self.morph_easing = easing;
+        self
+    }
+
+    pub fn with_groups(mut self, groups: Vec<GroupParams>) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    pub fn with_physics_mode(mut self, mode: PhysicsMode) -> Self {
+        self.physics_mode = mode;
+        self
+    }
+
+    pub fn with_store_op(mut self, store_op: wgpu::StoreOp) -> Self {
+        self.store_op = store_op;
+        self
+    }
+
+    pub fn with_shape_morph(mut self, points: u32, skip: u32, rate: f32) -> Self {
+        self.shape_morph_target = Some([points, skip]);
+        self.shape_morph_rate = rate;
+        self
+    }
+
+    pub fn with_center_jitter(mut self, center_jitter: f32) -> Self {
+        self.center_jitter = center_jitter;
+        self
+    }
+
+    pub fn with_links(mut self, distance: f32, color: [f32; 4]) -> Self {
+        self.link_distance = distance;
+        self.link_color = color;
+        self
+    }
+
+    pub fn with_debug_grid(mut self, debug_grid: bool) -> Self {
+        self.debug_grid = debug_grid;
+        self
+    }
+
+    pub fn with_viewport_culling(mut self, viewport_culling: bool) -> Self {
+        self.viewport_culling = viewport_culling;
+        self
+    }
+
+    pub fn with_attractor_strength(mut self, attractor_strength: f32) -> Self {
+        self.attractor_strength = attractor_strength;
+        self
+    }
+
+    pub fn with_collisions(mut self, collisions: bool) -> Self {
+        self.collisions = collisions;
+        self
+    }
+
+    pub fn with_restitution(mut self, restitution: f32) -> Self {
+        self.restitution = restitution;
+        self
+    }
+
+    pub fn with_vertex_shader(mut self, source: String) -> Self {
+        self.vertex_shader = Some(source);
+        self
+    }
+
+    pub fn with_fragment_shader(mut self, source: String) -> Self {
+        self.fragment_shader = Some(source);
+        self
+    }
+}
+
+impl WgpuStateConfig {
+    // GPUの初期化より前に、各フィールドの値が制約を満たしているかを調べる
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.frame_latency == 0 {
+            return Err(ConfigError::FrameLatency(self.frame_latency));
+        }
+        if self.frames_in_flight == 0 {
+            return Err(ConfigError::FramesInFlight(self.frames_in_flight));
+        }
+        if self.star_points < 3 {
+            return Err(ConfigError::StarPoints(self.star_points));
+        }
+        if !(self.star_skip > 1 && self.star_skip < self.star_points) {
+            return Err(ConfigError::StarSkip { points: self.star_points, skip: self.star_skip });
+        }
+        if !(self.tip_sharpness.is_finite() && self.tip_sharpness > 0.0) {
+            return Err(ConfigError::TipSharpness(self.tip_sharpness));
+        }
+        if let Some([points, skip]) = self.shape_morph_target {
+            if !(points >= 3 && skip >= 1 && skip < points) {
+                return Err(ConfigError::ShapeMorphTarget { points, skip });
+            }
+        }
+        if !self.shape_morph_rate.is_finite() {
+            return Err(ConfigError::ShapeMorphRate(self.shape_morph_rate));
+        }
+        // インデックスは u16 なので、分割後の頂点数がその範囲に収まる必要がある
+        if crate::vertex::subdivided_vertex_count(self.fill_triangle_count(), self.subdivisions) > u16::MAX as u64 + 1 {
+            return Err(ConfigError::Subdivisions { points: self.star_points, subdivisions: self.subdivisions });
+        }
+        let ascending = self.lod_thresholds.windows(2).all(|pair| pair[0] < pair[1]);
+        if !ascending || self.lod_thresholds.iter().any(|threshold| !(threshold.is_finite() && *threshold > 0.0)) {
+            return Err(ConfigError::LodThresholds(self.lod_thresholds.clone()));
+        }
+        if !(self.line_width.is_finite() && self.line_width > 0.0) {
+            return Err(ConfigError::LineWidth(self.line_width));
+        }
+        if let Some(radius_pixels) = self.radius_pixels.filter(|radius| !(radius.is_finite() && *radius > 0.0)) {
+            return Err(ConfigError::RadiusPixels(radius_pixels));
+        }
+        if let Some(style) = self.outline_style.filter(|style| !(style.width.is_finite() && style.width > 0.0)) {
+            return Err(ConfigError::OutlineWidth(style.width));
+        }
+        if let Some(gradient) = self.spatial_gradient.as_ref().filter(|gradient| !gradient.is_valid()) {
+            return Err(ConfigError::SpatialGradient(gradient.stops.clone()));
+        }
+        if let Some(max_instances @ 0) = self.max_instances {
+            return Err(ConfigError::MaxInstances(max_instances));
+        }
+        if !(self.population_rate.is_finite() && self.population_rate >= 0.0) {
+            return Err(ConfigError::PopulationRate(self.population_rate));
+        }
+        if let Some(population_max) = self.population_max.filter(|max| *max < self.population_min) {
+            return Err(ConfigError::PopulationRange(self.population_min, population_max));
+        }
+        if !(self.startup_fade_secs.is_finite() && self.startup_fade_secs >= 0.0) {
+            return Err(ConfigError::StartupFadeSecs(self.startup_fade_secs));
+        }
+        if let Some(time) = self.static_time.filter(|time| !time.is_finite()) {
+            return Err(ConfigError::StaticTime(time));
+        }
+        if !(self.delta_smoothing > 0.0 && self.delta_smoothing <= 1.0) {
+            return Err(ConfigError::DeltaSmoothing(self.delta_smoothing));
+        }
+        if !(self.max_delta.is_finite() && self.max_delta > 0.0) {
+            return Err(ConfigError::MaxDelta(self.max_delta));
+        }
+        if !(self.intro_duration.is_finite() && self.intro_duration >= 0.0) {
+            return Err(ConfigError::IntroDuration(self.intro_duration));
+        }
+        if let Some(aspect_lock) = self.aspect_lock.filter(|aspect| !(aspect.is_finite() && *aspect > 0.0)) {
+            return Err(ConfigError::AspectLock(aspect_lock));
+        }
+        if let Some(resolution) = self.internal_resolution.filter(|resolution| resolution.contains(&0)) {
+            return Err(ConfigError::InternalResolution(resolution));
+        }
+        if !(0.0..=1.0).contains(&self.spin_bias) {
+            return Err(ConfigError::SpinBias(self.spin_bias));
+        }
+        if !(0.0..1.0).contains(&self.spawn_margin) {
+            return Err(ConfigError::SpawnMargin(self.spawn_margin));
+        }
+        if !self.instance_params.is_valid() {
+            return Err(ConfigError::InstanceParams(self.instance_params));
+        }
+        if !(0.0..1.0).contains(&self.center_jitter) {
+            return Err(ConfigError::CenterJitter(self.center_jitter));
+        }
+        for (group, params) in self.groups.iter().enumerate() {
+            if !params.speed_scale.is_finite() {
+                return Err(ConfigError::GroupSpeedScale { group, speed_scale: params.speed_scale });
+            }
+        }
+        if !(self.link_distance.is_finite() && self.link_distance >= 0.0) {
+            return Err(ConfigError::LinkDistance(self.link_distance));
+        }
+        if !self.attractor_strength.is_finite() {
+            return Err(ConfigError::AttractorStrength(self.attractor_strength));
+        }
+        if !(0.0..=1.0).contains(&self.restitution) {
+            return Err(ConfigError::Restitution(self.restitution));
+        }
+        if let Some(total) = self.viewport_total {
+            let offset = self.viewport_offset;
+            if total[0] == 0 || total[1] == 0 || offset[0] >= total[0] || offset[1] >= total[1] {
+                return Err(ConfigError::Viewport { offset, total });
+            }
+        }
+        Ok(())
+    }
+
+    // 形の補間 (shape_morph_target) を使って星形を描くかどうか
+    pub(crate) fn shape_morph_enabled(&self) -> bool {
+        self.shape_morph_target.is_some() && !self.point_mode && !self.outline_mode
+    }
+
+    // 分割前の塗りつぶしの星形の三角形の数
+    pub(crate) fn fill_triangle_count(&self) -> u32 {
+        match self.shape_morph_target {
+            Some([points, _]) => crate::vertex::morph_sample_count(self.star_points, points),
+            None if self.sharpened() => 2 * self.star_points,
+            None => self.star_points,
+        }
+    }
+
+    // tip_sharpness で内側のくぼみをずらした形を使うか
+    pub(crate) fn sharpened(&self) -> bool {
+        self.tip_sharpness != 1.0
+    }
+
+    // 星形のメッシュを作る詳細度の段階の数
+    pub(crate) fn lod_count(&self) -> usize {
+        if self.point_mode || self.outline_mode {
+            1
+        } else {
+            self.lod_thresholds.len() + 1
+        }
+    }
+
+    // level 段目 (0 が最も小さい星) のメッシュの分割数
+    pub(crate) fn lod_subdivisions(&self, level: usize) -> u32 {
+        match self.lod_thresholds.len() {
+            0 => self.subdivisions,
+            thresholds => self.subdivisions * level as u32 / thresholds as u32,
+        }
+    }
+
+    // scale の星を描く段階
+    pub(crate) fn lod_level(&self, scale: f32) -> usize {
+        let level = self.lod_thresholds.iter().filter(|threshold| scale >= **threshold).count();
+        level.min(self.lod_count() - 1)
+    }
+
+    // 星形のパイプラインでアルファブレンドするか
+    // ポイント描画と輪郭線は縁を半透明にぼかし、起動時のフェードは不透明度で星を浮かび上がらせる
+    // REPLACE のままだと不透明度が捨てられてフェードが見えないので、フェードを使うときもブレンドする
+    // グループの tint の A も不透明度に掛かるので、1 未満のグループがあればブレンドする
+    pub(crate) fn alpha_blending(&self) -> bool {
+        self.point_mode
+            || self.outline_mode
+            || self.startup_fade_secs > 0.0
+            || self.groups.iter().any(|group| group.tint[3] < 1.0)
+    }
+
+    // 塗りつぶしの星形に outline_style の縁取りを重ねるか
+    pub(crate) fn outline_stroke_enabled(&self) -> bool {
+        self.outline_style.is_some() && !self.point_mode && !self.outline_mode
+    }
+
+    pub(crate) fn vertex_entry_point(&self) -> &'static str {
+        if self.outline_mode {
+            "vertexLine"
+        } else {
+            "vertexMain"
+        }
+    }
+
+    pub(crate) fn fragment_entry_point(&self) -> &'static str {
+        if self.outline_mode {
+            "fragmentLine"
+        } else if self.point_mode {
+            "fragmentPoint"
+        } else {
+            "fragmentMain"
+        }
+    }
+}
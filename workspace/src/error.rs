@@ -0,0 +1,260 @@
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum WgpuStateError {
+    // カスタムシェーダに、必要な段 (@vertex / @fragment) のエントリポイントが宣言されていない
+    MissingEntryPoint(String),
+    // WGSLのパースや検証に失敗した
+    ShaderCompilation(String),
+    // シェーダとパイプライン (頂点レイアウトやバインディング) が噛み合わない
+    PipelineCreation(String),
+    // インスタンス数が max_instances (またはデバイスのバッファサイズ上限) を超えている
+    TooManyInstances { requested: usize, max: usize },
+    // set_instances_from_iter のイテレータが、先に伝えた数より少ないインスタンスしか返さなかった
+    InstanceCountMismatch { expected: usize, actual: usize },
+    // WgpuStateConfig の値が不正
+    InvalidConfig(ConfigError),
+    // サーフェスの対応フォーマット・PresentMode・アルファモードのいずれかが空で、サーフェスを設定できない
+    IncompatibleSurface { adapter: String, missing: &'static str },
+    // ウィンドウからサーフェスを作れなかった
+    CreateSurface(String),
+    // 候補のどのバックエンドにも、サーフェスに描けるアダプタが見つからない
+    NoAdapter,
+    // アダプタから、要求した機能と上限を満たすデバイスを得られなかった
+    RequestDevice(wgpu::RequestDeviceError),
+}
+
+impl fmt::Display for WgpuStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingEntryPoint(entry_point) => {
+                write!(f, "Custom shader does not declare the `{entry_point}` entry point")
+            }
+            Self::ShaderCompilation(message) => write!(f, "Shader compilation failed: {message}"),
+            Self::PipelineCreation(message) => write!(f, "Render pipeline creation failed: {message}"),
+            Self::TooManyInstances { requested, max } => {
+                write!(f, "Requested {requested} instances, but at most {max} are allowed")
+            }
+            Self::InstanceCountMismatch { expected, actual } => {
+                write!(f, "Expected {expected} instances from the iterator, but it yielded {actual}")
+            }
+            Self::InvalidConfig(error) => write!(f, "Invalid configuration: {error}"),
+            Self::IncompatibleSurface { adapter, missing } => {
+                write!(f, "Surface reports no supported {missing} on adapter \"{adapter}\"")
+            }
+            Self::CreateSurface(message) => write!(f, "Failed to create a surface for the window: {message}"),
+            Self::NoAdapter => write!(f, "No suitable graphics adapter found"),
+            Self::RequestDevice(error) => write!(f, "Failed to request a device from the adapter: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for WgpuStateError {}
+
+impl From<ConfigError> for WgpuStateError {
+    fn from(error: ConfigError) -> Self {
+        Self::InvalidConfig(error)
+    }
+}
+
+// WgpuStateConfig::validate が返す、フィールドごとの制約違反
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    // frame_latency は1以上
+    FrameLatency(u32),
+    // frames_in_flight は1以上
+    FramesInFlight(u32),
+    // star_points は3以上
+    StarPoints(u32),
+    // star_skip は 1 < skip < star_points
+    StarSkip { points: u32, skip: u32 },
+    // tip_sharpness は正の有限な値
+    TipSharpness(f32),
+    // 分割後の星形の頂点数が u16 のインデックスに収まらない
+    Subdivisions { points: u32, subdivisions: u32 },
+    // shape_morph_target は points が3以上で 1 <= skip < points
+    ShapeMorphTarget { points: u32, skip: u32 },
+    // shape_morph_rate は有限な値
+    ShapeMorphRate(f32),
+    // lod_thresholds は正の有限な値の狭義の昇順
+    LodThresholds(Vec<f32>),
+    // line_width は正の有限な値
+    LineWidth(f32),
+    // radius_pixels は正の有限な値
+    RadiusPixels(f32),
+    // outline_style の太さは正の有限な値
+    OutlineWidth(f32),
+    // spatial_gradient は 2〜MAX_GRADIENT_STOPS 個の色で、位置が 0.0〜1.0 の昇順
+    SpatialGradient(Vec<(f32, [f32; 3])>),
+    // max_instances は1以上
+    MaxInstances(usize),
+    // population_rate は0以上の有限な値
+    PopulationRate(f32),
+    // population_min は population_max 以下 (min, max)
+    PopulationRange(u32, u32),
+    // startup_fade_secs は0以上の有限な値
+    StartupFadeSecs(f32),
+    // static_time は有限な値
+    StaticTime(f32),
+    // delta_smoothing は 0.0 より大きく 1.0 以下
+    DeltaSmoothing(f32),
+    // max_delta は正の有限な値
+    MaxDelta(f32),
+    // intro_duration は0以上の有限な値
+    IntroDuration(f32),
+    // aspect_lock は正の有限な値
+    AspectLock(f32),
+    // internal_resolution の幅と高さは1以上
+    InternalResolution([u32; 2]),
+    // spin_bias は 0.0〜1.0
+    SpinBias(f32),
+    // spawn_margin は 0.0 以上 1.0 未満
+    SpawnMargin(f32),
+    // instance_params の範囲は有限で最小 <= 最大、大きさは 0.0 より大きく 1.0 未満、速さは 0.0 以上
+    InstanceParams(crate::instance::InstanceParams),
+    // center_jitter は 0.0 以上 1.0 未満
+    CenterJitter(f32),
+    // groups の speed_scale は有限な値
+    GroupSpeedScale { group: usize, speed_scale: f32 },
+    // link_distance は0以上の有限な値
+    LinkDistance(f32),
+    // attractor_strength は有限な値
+    AttractorStrength(f32),
+    // restitution は 0.0〜1.0
+    Restitution(f32),
+    // viewport_total は縦横とも1以上で、viewport_offset はその内側
+    Viewport { offset: [u32; 2], total: [u32; 2] },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FrameLatency(value) => write!(f, "frame_latency must be at least 1 (got {value})"),
+            Self::FramesInFlight(value) => write!(f, "frames_in_flight must be at least 1 (got {value})"),
+            Self::StarPoints(value) => write!(f, "star_points must be at least 3 (got {value})"),
+            Self::StarSkip { points, skip } => {
+                write!(f, "star_skip must satisfy 1 < skip < star_points (got {{{points}/{skip}}})")
+            }
+            Self::TipSharpness(value) => write!(f, "tip_sharpness must be a positive finite number (got {value})"),
+            Self::Subdivisions { points, subdivisions } => write!(
+                f,
+                "subdivisions {subdivisions} produce too many vertices for a {points}-point star (limit is 65536)"
+            ),
+            Self::ShapeMorphTarget { points, skip } => write!(
+                f,
+                "shape_morph_target must have at least 3 points and satisfy 1 <= skip < points (got {{{points}/{skip}}})"
+            ),
+            Self::ShapeMorphRate(value) => write!(f, "shape_morph_rate must be a finite number (got {value})"),
+            Self::LodThresholds(values) => write!(
+                f,
+                "lod_thresholds must be positive finite numbers in strictly ascending order (got {values:?})"
+            ),
+            Self::LineWidth(value) => write!(f, "line_width must be a positive finite number (got {value})"),
+            Self::RadiusPixels(value) => write!(f, "radius_pixels must be a positive finite number (got {value})"),
+            Self::OutlineWidth(value) => write!(f, "outline_style width must be a positive finite number (got {value})"),
+            Self::SpatialGradient(stops) => write!(
+                f,
+                "spatial_gradient needs 2 to {} stops with finite colors and ascending offsets in 0.0..=1.0 (got {stops:?})",
+                crate::gradient::MAX_GRADIENT_STOPS
+            ),
+            Self::MaxInstances(value) => write!(f, "max_instances must be at least 1 (got {value})"),
+            Self::PopulationRate(value) => {
+                write!(f, "population_rate must be a non-negative finite number (got {value})")
+            }
+            Self::PopulationRange(min, max) => {
+                write!(f, "population_min ({min}) must not be greater than population_max ({max})")
+            }
+            Self::StartupFadeSecs(value) => {
+                write!(f, "startup_fade_secs must be a non-negative finite number (got {value})")
+            }
+            Self::StaticTime(value) => write!(f, "static_time must be a finite number (got {value})"),
+            Self::DeltaSmoothing(value) => {
+                write!(f, "delta_smoothing must be greater than 0.0 and at most 1.0 (got {value})")
+            }
+            Self::MaxDelta(value) => write!(f, "max_delta must be a positive finite number (got {value})"),
+            Self::IntroDuration(value) => {
+                write!(f, "intro_duration must be a non-negative finite number (got {value})")
+            }
+            Self::AspectLock(value) => write!(f, "aspect_lock must be a positive finite number (got {value})"),
+            Self::InternalResolution([width, height]) => {
+                write!(f, "internal_resolution must be at least 1x1 (got {width}x{height})")
+            }
+            Self::SpinBias(value) => write!(f, "spin_bias must be between 0.0 and 1.0 (got {value})"),
+            Self::SpawnMargin(value) => {
+                write!(f, "spawn_margin must be at least 0.0 and less than 1.0 (got {value})")
+            }
+            Self::InstanceParams(params) => write!(
+                f,
+                "instance_params ranges must be finite and ordered, with scale in (0.0, 1.0) and non-negative speeds (got {params:?})"
+            ),
+            Self::CenterJitter(value) => {
+                write!(f, "center_jitter must be at least 0.0 and less than 1.0 (got {value})")
+            }
+            Self::GroupSpeedScale { group, speed_scale } => {
+                write!(f, "groups[{group}].speed_scale must be a finite number (got {speed_scale})")
+            }
+            Self::LinkDistance(value) => {
+                write!(f, "link_distance must be a non-negative finite number (got {value})")
+            }
+            Self::AttractorStrength(value) => {
+                write!(f, "attractor_strength must be a finite number (got {value})")
+            }
+            Self::Restitution(value) => write!(f, "restitution must be between 0.0 and 1.0 (got {value})"),
+            Self::Viewport { offset, total } => write!(
+                f,
+                "viewport_offset {offset:?} must lie inside a non-empty viewport_total {total:?}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// オフスクリーンでの書き出し (render_at / capture_hires) の失敗
+#[derive(Debug)]
+pub enum CaptureError {
+    // デバイスやキュー、サーフェスの設定が揃っていない
+    NotReady,
+    // デバイスが失われた (ドライバのリセットなど)。WgpuState を作り直すまで書き出せない
+    DeviceLost(String),
+    // 書き出し用のテクスチャやバッファの作成、描画やコピーのコマンドが検証かメモリ確保に失敗した
+    Gpu(String),
+    // 読み出し用のバッファをマップできなかった
+    Map(wgpu::BufferAsyncError),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotReady => write!(f, "The renderer is not ready to capture a frame"),
+            Self::DeviceLost(message) => write!(f, "The device was lost during capture: {message}"),
+            Self::Gpu(message) => write!(f, "Capture commands failed: {message}"),
+            Self::Map(error) => write!(f, "Failed to map the capture buffer: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+// SceneConfig の保存・読み込みの失敗
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Failed to access the scene file: {error}"),
+            Self::Parse(error) => write!(f, "Failed to parse the scene: {error}"),
+            Self::Serialize(error) => write!(f, "Failed to serialize the scene: {error}"),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for SceneError {}
@@ -0,0 +1,118 @@
+use crate::state::WgpuState;
+use crate::vertex::Vertex;
+
+// SVG の width / height に付ける単位。レーザーカッターなどに渡す場合は Mm にする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SvgUnit {
+    Px,
+    Mm,
+}
+
+impl SvgUnit {
+    fn suffix(self) -> &'static str {
+        match self {
+            Self::Px => "px",
+            Self::Mm => "mm",
+        }
+    }
+}
+
+// 頂点とインデックスの三角形を1つずつ <path> にした SVG を作る
+// NDC の -1.0..=1.0 を 0..=size に写し、SVG に合わせて y を下向きにする
+pub fn mesh_to_svg(vertices: &[Vertex], indices: &[u16], size: f32, unit: SvgUnit) -> String {
+    let unit = unit.suffix();
+    let to_svg = |index: u16| {
+        let [x, y] = vertices[index as usize].position;
+        [(x + 1.0) * 0.5 * size, (1.0 - y) * 0.5 * size]
+    };
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{size}{unit}\" height=\"{size}{unit}\" viewBox=\"0 0 {size} {size}\">\n"
+    );
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(to_svg);
+        svg.push_str(&format!(
+            "  <path d=\"M {} {} L {} {} L {} {} Z\" fill=\"black\" stroke=\"none\"/>\n",
+            a[0], a[1], b[0], b[1], c[0], c[1]
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// 頂点とインデックスを Wavefront OBJ の形式にする。z は 0 で、幅が size になるよう中心を原点のまま拡大する
+pub fn mesh_to_obj(vertices: &[Vertex], indices: &[u16], size: f32) -> String {
+    let scale = size * 0.5;
+    let mut obj = String::from("o star\n");
+    for vertex in vertices {
+        let [x, y] = vertex.position;
+        obj.push_str(&format!("v {} {} 0\n", x * scale, y * scale));
+    }
+    // OBJ の頂点番号は 1 から始まる
+    for triangle in indices.chunks_exact(3) {
+        obj.push_str(&format!("f {} {} {}\n", triangle[0] + 1, triangle[1] + 1, triangle[2] + 1));
+    }
+    obj
+}
+
+impl WgpuState<'_> {
+    // state_config の {star_points/star_skip} を分割せずに塗りつぶした星形のメッシュ
+    // 描画モード (点・輪郭) や形の補間に関係なく、常に星形そのものを返す。GPU は使わない
+    pub fn star_mesh(&self) -> (Vec<Vertex>, Vec<u16>) {
+        Self::create_star_vertices(&self.state_config, 0)
+    }
+
+    // star_mesh を一辺 size (unit 単位) の SVG として書き出す
+    pub fn export_svg(&self, path: impl AsRef<std::path::Path>, size: f32, unit: SvgUnit) -> std::io::Result<()> {
+        let (vertices, indices) = self.star_mesh();
+        std::fs::write(path, mesh_to_svg(&vertices, &indices, size, unit))
+    }
+
+    // star_mesh を幅 size の OBJ として書き出す
+    pub fn export_obj(&self, path: impl AsRef<std::path::Path>, size: f32) -> std::io::Result<()> {
+        let (vertices, indices) = self.star_mesh();
+        std::fs::write(path, mesh_to_obj(&vertices, &indices, size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WgpuStateConfig;
+
+    // WgpuState::star_mesh と同じ、分割しない星形のメッシュ
+    fn star_mesh(points: u32, skip: u32) -> (Vec<Vertex>, Vec<u16>) {
+        WgpuState::create_star_vertices(&WgpuStateConfig::default().with_star(points, skip), 0)
+    }
+
+    #[test]
+    fn svg_has_one_triangle_per_star_point() {
+        for (points, skip) in [(5, 2), (7, 3), (6, 2)] {
+            let (vertices, indices) = star_mesh(points, skip);
+            let svg = mesh_to_svg(&vertices, &indices, 100.0, SvgUnit::Mm);
+            let paths: Vec<&str> = svg.lines().filter(|line| line.trim_start().starts_with("<path")).collect();
+            assert_eq!(paths.len(), points as usize);
+            for path in paths {
+                let d = path.split("d=\"").nth(1).and_then(|rest| rest.split('"').next()).unwrap();
+                let numbers = d.split_whitespace().filter(|token| token.parse::<f32>().is_ok()).count();
+                assert_eq!(numbers, 6, "{path}");
+            }
+            assert!(svg.contains("width=\"100mm\""));
+        }
+    }
+
+    #[test]
+    fn obj_lines_match_star_mesh() {
+        for (points, skip) in [(5, 2), (7, 3), (6, 2)] {
+            let (vertices, indices) = star_mesh(points, skip);
+            let obj = mesh_to_obj(&vertices, &indices, 2.0);
+            assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), vertices.len());
+            assert_eq!(obj.lines().filter(|line| line.starts_with("f ")).count(), indices.len() / 3);
+            // 面の頂点番号は 1 から始まり、頂点の数を超えない
+            let faces = obj.lines().filter_map(|line| line.strip_prefix("f "));
+            for index in faces.flat_map(str::split_whitespace).map(|index| index.parse::<usize>().unwrap()) {
+                assert!((1..=vertices.len()).contains(&index));
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::state::FrameStats;
+
+// 読み戻し用バッファのマップの進み具合
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MapState {
+    // 次のフレームのクエリ結果をコピーできる
+    Idle,
+    // このフレームでクエリ結果のコピーを積んだので、submit の後にマップを始める
+    Copied,
+    // map_async の完了待ち。この間はバッファに書き込めないのでコピーを積まない
+    Pending,
+    // マップが済んで読める
+    Ready,
+    // マップに失敗した
+    Failed,
+}
+
+// TIMESTAMP クエリでレンダーパスの実際のGPU実行時間を計測する
+// FrameStats の Instant による計測はCPU側の記録・送信時間しか表さない
+// 結果は GPU の完了を待たずに非同期でマップし、1フレーム以上後に読む。マップ中のフレームは計測しない
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // map_async のコールバックから書き換えるので共有する
+    map_state: std::sync::Arc<std::sync::Mutex<MapState>>,
+    // タイムスタンプ1刻みあたりのナノ秒
+    period: f32,
+    pub stats: FrameStats,
+}
+
+impl GpuTimer {
+    const QUERY_COUNT: u32 = 2;
+    const BUFFER_SIZE: u64 = Self::QUERY_COUNT as u64 * std::mem::size_of::<u64>() as u64;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("GPU Timer Query Set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: Self::QUERY_COUNT,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Resolve Buffer"),
+            size: Self::BUFFER_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer: Self::create_readback_buffer(device),
+            map_state: std::sync::Arc::new(std::sync::Mutex::new(MapState::Idle)),
+            period: queue.get_timestamp_period(),
+            stats: FrameStats::new(),
+        }
+    }
+
+    fn create_readback_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Timer Readback Buffer"),
+            size: Self::BUFFER_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn map_state(&self) -> MapState {
+        self.map_state.lock().map_or(MapState::Failed, |state| *state)
+    }
+
+    fn set_map_state(&self, state: MapState) {
+        if let Ok(mut current) = self.map_state.lock() {
+            *current = state;
+        }
+    }
+
+    pub fn timestamp_writes(&self) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        }
+    }
+
+    // レンダーパスの後に呼び、クエリ結果を読み戻し用バッファへコピーするコマンドを積む
+    // 前のフレームの結果をまだマップしている間は、マップ中のバッファに書き込めないので何もしない
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.map_state() != MapState::Idle {
+            return;
+        }
+        encoder.resolve_query_set(&self.query_set, 0..Self::QUERY_COUNT, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, Self::BUFFER_SIZE);
+        self.set_map_state(MapState::Copied);
+    }
+
+    // submit の後に毎フレーム呼ぶ。GPUの完了は待たずに、このフレームでコピーした結果のマップを始め、
+    // 前のフレームまでに始めたマップが済んでいれば読んで stats に記録する。記録したら true
+    pub fn read_back(&mut self, device: &wgpu::Device) -> bool {
+        device.poll(wgpu::Maintain::Poll);
+        match self.map_state() {
+            MapState::Copied => {
+                self.set_map_state(MapState::Pending);
+                let map_state = self.map_state.clone();
+                self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                    if let Ok(mut state) = map_state.lock() {
+                        *state = if result.is_ok() { MapState::Ready } else { MapState::Failed };
+                    }
+                });
+            }
+            MapState::Ready => {
+                let elapsed_ticks = {
+                    let data = self.readback_buffer.slice(..).get_mapped_range();
+                    let timestamps: &[u64] = bytemuck::cast_slice(&data);
+                    timestamps[1].saturating_sub(timestamps[0])
+                };
+                self.readback_buffer.unmap();
+                self.set_map_state(MapState::Idle);
+                self.stats.update(elapsed_ticks as f64 * self.period as f64 / 1_000_000_000.0);
+                return true;
+            }
+            MapState::Failed => {
+                // 失敗したマップはバッファをマップしていない状態に戻すので、unmap は検証エラーになる
+                // 作り直して次のフレームから計測をやり直す
+                log::warn!("Failed to map the GPU timer readback buffer; recreating it");
+                self.readback_buffer = Self::create_readback_buffer(device);
+                self.set_map_state(MapState::Idle);
+            }
+            MapState::Idle | MapState::Pending => {}
+        }
+        false
+    }
+
+    pub fn display_stats(&self) {
+        println!("GPU Min Time: {} sec", self.stats.min_time);
+        println!("GPU Max Time: {} sec", self.stats.max_time);
+        println!("GPU Average Time: {} sec", self.stats.average_time());
+        println!("----------------------------------");
+    }
+}
@@ -0,0 +1,62 @@
+// spatial_gradient で Uniforms に詰められる色の数の上限
+pub const MAX_GRADIENT_STOPS: usize = 4;
+
+// 星の位置から spatial_gradient のどこの色を取るか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GradientAxis {
+    // 左端 (x = -1.0) が 0.0、右端 (x = 1.0) が 1.0
+    #[default]
+    Horizontal,
+    // 上端 (y = 1.0) が 0.0、下端 (y = -1.0) が 1.0 (BackgroundMode::LinearGradient と同じ向き)
+    Vertical,
+    // 画面中心が 0.0、中心から 1.0 離れた位置とその外側が 1.0
+    Radial,
+}
+
+impl GradientAxis {
+    // シェーダの Uniforms::gradientAxis に渡す値
+    pub fn as_uniform(self) -> u32 {
+        match self {
+            Self::Horizontal => 0,
+            Self::Vertical => 1,
+            Self::Radial => 2,
+        }
+    }
+}
+
+// 星の中心の位置 (星空全体の回転後、カメラを掛ける前の座標) で星の基本色を決めるグラデーション
+// stops は (位置 0.0〜1.0, 線形の RGB) を位置の昇順に 2〜MAX_GRADIENT_STOPS 個並べる
+// 最初の位置より手前は最初の色、最後の位置より先は最後の色になる
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpatialGradient {
+    pub stops: Vec<(f32, [f32; 3])>,
+    pub axis: GradientAxis,
+}
+
+impl SpatialGradient {
+    pub fn new(stops: Vec<(f32, [f32; 3])>, axis: GradientAxis) -> Self {
+        Self { stops, axis }
+    }
+
+    // 2〜MAX_GRADIENT_STOPS 個で、位置が 0.0〜1.0 の昇順、色が有限な値
+    pub(crate) fn is_valid(&self) -> bool {
+        (2..=MAX_GRADIENT_STOPS).contains(&self.stops.len())
+            && self.stops.iter().all(|(offset, color)| {
+                (0.0..=1.0).contains(offset) && color.iter().all(|channel| channel.is_finite())
+            })
+            && self.stops.windows(2).all(|pair| pair[0].0 <= pair[1].0)
+    }
+
+    // Uniforms の gradient_colors と gradient_offsets に詰める値。使わない分は最後の色と位置で埋める
+    pub(crate) fn uniform_stops(&self) -> ([[f32; 4]; MAX_GRADIENT_STOPS], [f32; MAX_GRADIENT_STOPS]) {
+        let mut colors = [[0.0; 4]; MAX_GRADIENT_STOPS];
+        let mut offsets = [1.0; MAX_GRADIENT_STOPS];
+        for index in 0..MAX_GRADIENT_STOPS {
+            if let Some((offset, [r, g, b])) = self.stops.get(index).or(self.stops.last()) {
+                colors[index] = [*r, *g, *b, 1.0];
+                offsets[index] = *offset;
+            }
+        }
+        (colors, offsets)
+    }
+}
@@ -0,0 +1,80 @@
+use wgpu::util::DeviceExt;
+
+use crate::link::LinkVertex;
+
+// 星空の範囲 (-1.0〜1.0) を何本の間隔に分けるか。間隔は 0.1 になる
+const GRID_STEPS: i32 = 20;
+const GRID_SPACING: f32 = 2.0 / GRID_STEPS as f32;
+const GRID_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 0.25];
+// 星空の範囲の縁 (±1.0)
+const BOUNDS_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 0.6];
+// x 軸 (y = 0) は赤、y 軸 (x = 0) は緑
+const X_AXIS_COLOR: [f32; 4] = [1.0, 0.3, 0.3, 0.9];
+const Y_AXIS_COLOR: [f32; 4] = [0.3, 1.0, 0.3, 0.9];
+
+// 座標を確かめるために星の後ろに描く目盛り線と原点を通る軸
+// 線は星空の座標で作るので、カメラで拡大・移動すると一緒に動く
+pub(crate) struct DebugGrid {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    vertex_count: u32,
+}
+
+impl DebugGrid {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        config: &wgpu::SurfaceConfiguration,
+        state_config: &crate::config::WgpuStateConfig,
+    ) -> Self {
+        let pipeline = crate::link::create_line_pipeline(
+            device,
+            uniform_bind_group_layout,
+            shader,
+            config,
+            state_config,
+            "Debug Grid Pipeline",
+            "vertexGrid",
+        );
+        let vertices = grid_vertices();
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Debug Grid Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        Self {
+            pipeline,
+            vertex_buffer,
+            vertex_count: vertices.len() as u32,
+        }
+    }
+
+    pub(crate) fn draw(&self, render_pass: &mut wgpu::RenderPass, uniform_bind_group: &wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, uniform_bind_group, &[0]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+// GRID_SPACING ごとの縦横の線を LineList の頂点の列にする。軸は最後に並べて、他の線の上に重なるようにする
+fn grid_vertices() -> Vec<LinkVertex> {
+    let mut vertices = Vec::new();
+    let mut line = |from: [f32; 2], to: [f32; 2], color: [f32; 4]| {
+        vertices.push(LinkVertex { position: from, color });
+        vertices.push(LinkVertex { position: to, color });
+    };
+    for step in 0..=GRID_STEPS {
+        if step == GRID_STEPS / 2 {
+            continue;
+        }
+        let offset = -1.0 + step as f32 * GRID_SPACING;
+        let color = if step == 0 || step == GRID_STEPS { BOUNDS_COLOR } else { GRID_COLOR };
+        line([offset, -1.0], [offset, 1.0], color);
+        line([-1.0, offset], [1.0, offset], color);
+    }
+    line([-1.0, 0.0], [1.0, 0.0], X_AXIS_COLOR);
+    line([0.0, -1.0], [0.0, 1.0], Y_AXIS_COLOR);
+    vertices
+}
@@ -0,0 +1,73 @@
+// 星空を連続したインスタンスの範囲ごとのグループに分け、グループごとに色合いと動きの速さを変える
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroupParams {
+    // このグループに属するインスタンスの数。前のグループの続きから数え、最後のグループは残りをすべて含む
+    pub count: usize,
+    // 星の色に掛ける色 (RGB) と不透明度 (A)
+    pub tint: [f32; 4],
+    // 移動と自転の速さの倍率
+    pub speed_scale: f32,
+}
+
+impl Default for GroupParams {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            tint: [1.0, 1.0, 1.0, 1.0],
+            speed_scale: 1.0,
+        }
+    }
+}
+
+// シェーダの GroupUniforms と同じ配置。グループごとに動的オフセットで切り替えて読む
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct GroupUniforms {
+    tint: [f32; 4],
+    speed_scale: f32,
+    padding: [f32; 3],
+}
+
+// groups が空の場合も、既定の値の1グループとして扱う
+pub(crate) fn effective_groups(groups: &[GroupParams]) -> Vec<GroupParams> {
+    if groups.is_empty() {
+        vec![GroupParams::default()]
+    } else {
+        groups.to_vec()
+    }
+}
+
+// instances の index 番目が属するグループ
+pub(crate) fn group_of(groups: &[GroupParams], index: usize) -> usize {
+    let mut end = 0;
+    for (group, params) in groups.iter().enumerate() {
+        end += params.count;
+        if index < end {
+            return group;
+        }
+    }
+    groups.len().saturating_sub(1)
+}
+
+// 各グループの値を、デバイスの動的オフセットの境界に揃えた間隔で並べたバッファを作る
+// 戻り値の2つ目は1グループあたりの間隔 (バイト)
+pub(crate) fn create_group_buffer(device: &wgpu::Device, groups: &[GroupParams]) -> (wgpu::Buffer, u32) {
+    use wgpu::util::DeviceExt;
+    let size = std::mem::size_of::<GroupUniforms>() as u32;
+    let stride = size.next_multiple_of(device.limits().min_uniform_buffer_offset_alignment);
+    let mut contents = vec![0u8; stride as usize * groups.len().max(1)];
+    for (params, chunk) in groups.iter().zip(contents.chunks_mut(stride as usize)) {
+        let uniforms = GroupUniforms {
+            tint: params.tint,
+            speed_scale: params.speed_scale,
+            padding: [0.0; 3],
+        };
+        chunk[..size as usize].copy_from_slice(bytemuck::bytes_of(&uniforms));
+    }
+    let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Group Uniform Buffer"),
+        contents: &contents,
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    (buffer, stride)
+}
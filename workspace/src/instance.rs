@@ -1,64 +1,398 @@
 use crate::state::WgpuState;
 
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Instance {
     position: [f32; 2],
     scale: f32,
     initial_rotation: f32,
     speed: [f32; 2],
     rotation_speed: f32,
+    // フラグメントのアルファに乗算する不透明度
+    // 星形の描画はブレンドが REPLACE なので、アルファブレンドを有効にしたときだけ見た目に効く
+    opacity: f32,
+    // 星形の中心 (ローカル座標の原点) をずらす量 (星の半径を 1.0 とするローカル座標)
+    // 頂点シェーダは中心からの距離に応じて外側ほど弱くずらすので、外側の頂点は動かず星が歪んで見える
+    center_offset: [f32; 2],
+    // 重なったときの描く順番。大きいほど後に描いて上に重なり、同じ値の中では元の並びの順に描く
+    // シェーダは読まないので、インスタンスバッファのレイアウトには含めない
+    #[cfg_attr(feature = "serde", serde(default))]
+    layer: u32,
+}
+
+impl Instance {
+    // position と speed は [f32; 2] のほか、glam フィーチャ有効時は glam::Vec2 も渡せる
+    pub fn new(
+        position: impl Into<[f32; 2]>,
+        scale: f32,
+        rotation: f32,
+        speed: impl Into<[f32; 2]>,
+        rotation_speed: f32,
+    ) -> Self {
+        Self {
+            position: position.into(),
+            scale,
+            initial_rotation: rotation,
+            speed: speed.into(),
+            rotation_speed,
+            opacity: 1.0,
+            center_offset: [0.0, 0.0],
+            layer: 0,
+        }
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
+    pub fn with_center_offset(mut self, center_offset: impl Into<[f32; 2]>) -> Self {
+        self.center_offset = center_offset.into();
+        self
+    }
+
+    pub fn with_layer(mut self, layer: u32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn layer(&self) -> u32 {
+        self.layer
+    }
+
+    pub(crate) fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    // 描いた星が収まる、中心からの半径 (星空の座標)
+    // 中心のずれで外にはみ出す分と、点描画の四角形の角 (ローカル座標で √2) までを含める
+    pub(crate) fn bounding_radius(&self) -> f32 {
+        let offset = self.center_offset[0].hypot(self.center_offset[1]);
+        self.scale * offset.max(1.0) * std::f32::consts::SQRT_2
+    }
+
+    pub(crate) fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    // 星形のローカル座標 local の点が time 秒時点で映る位置 (シェーダの transformLocal から引力・登場演出・星空全体の回転を除いたもの)
+    pub(crate) fn transform_local(&self, local: [f32; 2], mode: crate::physics::PhysicsMode, time: f32) -> [f32; 2] {
+        let rotation = self.initial_rotation + time * self.rotation_speed;
+        let [x, y] = self.position_at(mode, time);
+        let skew = (1.0 - local[0].hypot(local[1])).max(0.0);
+        let skewed = [
+            (local[0] + self.center_offset[0] * skew) * self.scale,
+            (local[1] + self.center_offset[1] * skew) * self.scale,
+        ];
+        // WGSL の mat2x2(c, -s, s, c) は列優先なので、この向きに回る
+        let (sin, cos) = rotation.sin_cos();
+        [
+            cos * skewed[0] + sin * skewed[1] + x,
+            -sin * skewed[0] + cos * skewed[1] + y,
+        ]
+    }
+
+    // time 秒時点で画面端の振る舞い mode のもとで映る位置 (星空全体の回転前)
+    pub(crate) fn position_at(&self, mode: crate::physics::PhysicsMode, time: f32) -> [f32; 2] {
+        [
+            mode.apply(self.position[0], self.speed[0], time).0,
+            mode.apply(self.position[1], self.speed[1], time).0,
+        ]
+    }
+
+    // time 秒時点で from の振る舞いのもとで画面に映っている位置と向きを、
+    // 時刻 0 の初期値に置き直す (どのモードでも -1.0〜1.0 の範囲はそのまま映るので、切り替えても星が飛ばない)
+    pub(crate) fn rebased(&self, from: crate::physics::PhysicsMode, time: f32) -> Instance {
+        let (x, speed_x) = from.apply(self.position[0], self.speed[0], time);
+        let (y, speed_y) = from.apply(self.position[1], self.speed[1], time);
+        Instance {
+            position: [x - speed_x * time, y - speed_y * time],
+            speed: [speed_x, speed_y],
+            ..*self
+        }
+    }
+
+    // time 秒時点で mode のもとで映る位置はそのままに、そこからの速度を speed に置き換える (衝突で向きを変えるときに使う)
+    pub(crate) fn redirected(&self, mode: crate::physics::PhysicsMode, time: f32, speed: [f32; 2]) -> Instance {
+        let [x, y] = self.position_at(mode, time);
+        Instance {
+            position: [x - speed[0] * time, y - speed[1] * time],
+            speed,
+            ..*self
+        }
+    }
+
+    // time 秒時点で mode のもとで映っている向きの速度 (跳ね返った後は符号が逆になる)
+    pub(crate) fn velocity_at(&self, mode: crate::physics::PhysicsMode, time: f32) -> [f32; 2] {
+        [
+            mode.apply(self.position[0], self.speed[0], time).1,
+            mode.apply(self.position[1], self.speed[1], time).1,
+        ]
+    }
+
+    // 各フィールドを t (0.0〜1.0) で線形補間する
+    pub fn lerp(&self, other: &Instance, t: f32) -> Instance {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        Instance {
+            position: [mix(self.position[0], other.position[0]), mix(self.position[1], other.position[1])],
+            scale: mix(self.scale, other.scale),
+            initial_rotation: mix(self.initial_rotation, other.initial_rotation),
+            speed: [mix(self.speed[0], other.speed[0]), mix(self.speed[1], other.speed[1])],
+            rotation_speed: mix(self.rotation_speed, other.rotation_speed),
+            opacity: mix(self.opacity, other.opacity),
+            center_offset: [
+                mix(self.center_offset[0], other.center_offset[0]),
+                mix(self.center_offset[1], other.center_offset[1]),
+            ],
+            // 順番は補間できないので、中間で切り替える
+            layer: if t < 0.5 { self.layer } else { other.layer },
+        }
+    }
+}
+
+// バッファ上の配置は [f32; 2] のまま、glam の型で読み書きする
+#[cfg(feature = "glam")]
+impl Instance {
+    pub fn position(&self) -> glam::Vec2 {
+        glam::Vec2::from(self.position)
+    }
+
+    pub fn set_position(&mut self, position: glam::Vec2) {
+        self.position = position.into();
+    }
+
+    pub fn speed(&self) -> glam::Vec2 {
+        glam::Vec2::from(self.speed)
+    }
+
+    pub fn set_speed(&mut self, speed: glam::Vec2) {
+        self.speed = speed.into();
+    }
+}
+
+// 原点に静止した等倍の星
+impl Default for Instance {
+    fn default() -> Self {
+        Self::new([0.0, 0.0], 1.0, 0.0, [0.0, 0.0], 0.0)
+    }
+}
+
+// 星空を生成するときに星ごとの値を引く範囲
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstanceParams {
+    // 大きさ (scale) の範囲 [最小, 最大]
+    pub scale: [f32; 2],
+    // 移動の速さの各軸の最大。x と y をそれぞれ -speed〜speed で引く
+    pub speed: f32,
+    // 自転の速さ (絶対値) の範囲 [最小, 最大]。向きは spin_bias で振り分ける
+    pub rotation_speed: [f32; 2],
+}
+
+impl Default for InstanceParams {
+    fn default() -> Self {
+        Self {
+            scale: [0.02, 0.05],
+            speed: 0.3,
+            rotation_speed: [0.5, 2.0],
+        }
+    }
+}
+
+impl InstanceParams {
+    // 大きさは 0.0 より大きく 1.0 未満、速さは 0.0 以上で、どの範囲も有限で最小 <= 最大
+    pub(crate) fn is_valid(&self) -> bool {
+        let ordered = |[min, max]: [f32; 2]| min.is_finite() && max.is_finite() && min <= max;
+        ordered(self.scale)
+            && self.scale[0] > 0.0
+            && self.scale[1] < 1.0
+            && self.speed.is_finite()
+            && self.speed >= 0.0
+            && ordered(self.rotation_speed)
+            && self.rotation_speed[0] >= 0.0
+    }
 }
 
 pub fn create_star_instances() -> Vec<Instance> {
-    use rand::Rng;
+    create_star_instances_with_spin_bias(0.5)
+}
 
-    let mut rng: Box<dyn rand::RngCore> = if cfg!(target_arch = "wasm32") {
+// spin_bias は反時計回りに回る星の割合 (0.0 ですべて時計回り、1.0 ですべて反時計回り)
+pub fn create_star_instances_with_spin_bias(spin_bias: f32) -> Vec<Instance> {
+    create_star_instances_with_rng(&mut field_rng(), spin_bias)
+}
+
+// 種を指定しないときに星空の配置に使う乱数生成器
+pub(crate) fn field_rng() -> Box<dyn rand::RngCore> {
+    if cfg!(target_arch = "wasm32") {
         // wasm32の場合はrandが使えないので、乱数を固定値にする
         use rand::SeedableRng;
         Box::new(rand::rngs::SmallRng::seed_from_u64(0))
     } else {
         // デスクトップの場合は乱数を初期化
         Box::new(rand::thread_rng())
-    };
+    }
+}
+
+// 同じ seed からは常に同じ配置を作る (wasm32 でも seed を変えれば別の配置になる)
+pub fn create_seeded_star_instances(seed: u64, spin_bias: f32) -> Vec<Instance> {
+    use rand::SeedableRng;
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(seed);
+    create_star_instances_with_rng(&mut rng, spin_bias)
+}
+
+// 乱数生成器をホスト側が持つ場合に使う (ネットワーク越しの同期などで、すべての乱数を1つの生成器から引くとき)
+// 同じ状態の生成器からは常に同じ配置になる
+pub fn create_star_instances_with_rng(rng: &mut impl rand::Rng, spin_bias: f32) -> Vec<Instance> {
+    create_star_instances_with_margin(rng, spin_bias, 0.0)
+}
+
+// 星の先端が画面端 (-1.0〜1.0) からさらに spawn_margin だけ内側に収まる範囲に配置する
+pub fn create_star_instances_with_margin(rng: &mut impl rand::Rng, spin_bias: f32, spawn_margin: f32) -> Vec<Instance> {
+    create_star_instances_with_params(rng, &InstanceParams::default(), spin_bias, spawn_margin)
+}
+
+// 大きさ・速さ・自転の速さを params の範囲から引く。既定の InstanceParams なら create_star_instances_with_margin と同じ配置になる
+pub fn create_star_instances_with_params(
+    rng: &mut impl rand::Rng,
+    params: &InstanceParams,
+    spin_bias: f32,
+    spawn_margin: f32,
+) -> Vec<Instance> {
     let mut instances = Vec::new();
     
     for _ in 0..WgpuState::STAR_INSTANCE_COUNT {
-        instances.push(Instance {
-            position: [
-                rng.gen_range(-0.9..0.9),
-                rng.gen_range(-0.9..0.9),
-            ],
-            scale: rng.gen_range(0.02..0.05),  // スケールを少し大きく
-            initial_rotation: rng.gen_range(0.0..std::f32::consts::PI * 2.0),
-            speed: [
-                rng.gen_range(-0.3..0.3),      // 移動速度を調整
-                rng.gen_range(-0.3..0.3),
-            ],
-            rotation_speed: rng.gen_range(0.5..2.0),  // 回転速度を調整
-        });
+        let star = random_star_layout(rng, params, spin_bias, spawn_margin);
+        instances.push(star.with_opacity(random_opacity(rng)));
     }
     instances
 }
 
-pub fn get_instance_buffer(device: &wgpu::Device,instances: &Vec<Instance>) -> wgpu::Buffer {
+// 配置 (位置・大きさ・向き・速さ) を position_rng から、明るさ (opacity) を color_rng から引く
+// 片方の生成器だけを別の状態にすれば、もう片方で決まる値はそのまま残る
+// spawn_margin は create_star_instances_with_margin と同じ
+pub fn create_star_instances_with_rngs(
+    position_rng: &mut impl rand::Rng,
+    color_rng: &mut impl rand::Rng,
+    spin_bias: f32,
+    spawn_margin: f32,
+) -> Vec<Instance> {
+    star_field_with_rngs(position_rng, color_rng, &InstanceParams::default(), spin_bias, spawn_margin)
+}
+
+// create_star_instances_with_rngs の、配置を params の範囲から引く形
+pub(crate) fn star_field_with_rngs(
+    position_rng: &mut impl rand::Rng,
+    color_rng: &mut impl rand::Rng,
+    params: &InstanceParams,
+    spin_bias: f32,
+    spawn_margin: f32,
+) -> Vec<Instance> {
+    (0..WgpuState::STAR_INSTANCE_COUNT)
+        .map(|_| random_star_layout(position_rng, params, spin_bias, spawn_margin).with_opacity(random_opacity(color_rng)))
+        .collect()
+}
+
+// 配置と明るさを別々の種から作る。同じ position_seed なら color_seed に関係なく同じ配置になる
+pub fn create_star_instances_with_seeds(position_seed: u64, color_seed: u64, spin_bias: f32) -> Vec<Instance> {
+    use rand::SeedableRng;
+    let mut position_rng = rand::rngs::SmallRng::seed_from_u64(position_seed);
+    let mut color_rng = rand::rngs::SmallRng::seed_from_u64(color_seed);
+    create_star_instances_with_rngs(&mut position_rng, &mut color_rng, spin_bias, 0.0)
+}
+
+// 星1つ分の配置を引く (明るさは 1.0 のまま)
+// 位置は大きさに合わせて、星の先端 (中心から scale) が画面端から spawn_margin 内側に収まる範囲に置く
+// 乱数を引く順番は位置が先のままにして、-1.0〜1.0 で引いた値を後から範囲に合わせて縮める
+fn random_star_layout(rng: &mut impl rand::Rng, params: &InstanceParams, spin_bias: f32, spawn_margin: f32) -> Instance {
+    let position = [rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)];
+    let scale = sample_range(rng, params.scale);
+    let extent = (1.0 - spawn_margin - scale).max(0.0);
+    Instance {
+        position: [position[0] * extent, position[1] * extent],
+        scale,
+        initial_rotation: rng.gen_range(0.0..std::f32::consts::PI * 2.0),
+        speed: [
+            sample_range(rng, [-params.speed, params.speed]),
+            sample_range(rng, [-params.speed, params.speed]),
+        ],
+        // 回転の速さは params の範囲のまま、向きだけを spin_bias の割合で振り分ける
+        rotation_speed: if rng.gen_bool(spin_bias.clamp(0.0, 1.0) as f64) {
+            sample_range(rng, params.rotation_speed)
+        } else {
+            -sample_range(rng, params.rotation_speed)
+        },
+        opacity: 1.0,
+        center_offset: [0.0, 0.0],
+        layer: 0,
+    }
+}
+
+// [最小, 最大) から一様に引く。幅が無ければ乱数を引かずに最小の値を返す
+fn sample_range(rng: &mut impl rand::Rng, [min, max]: [f32; 2]) -> f32 {
+    if min < max {
+        rng.gen_range(min..max)
+    } else {
+        min
+    }
+}
+
+// 星1つ分の明るさを引く
+pub(crate) fn random_opacity(rng: &mut impl rand::Rng) -> f32 {
+    rng.gen_range(0.4..1.0)
+}
+
+// 各星の中心を半径 amount 以内の一様な位置へランダムにずらす。amount が 0.0 以下なら何もしない
+// 配置の生成と同じ生成器で続けて引けば、同じ種からは常に同じ歪み方になる
+pub fn jitter_star_centers(instances: &mut [Instance], rng: &mut impl rand::Rng, amount: f32) {
+    if amount <= 0.0 {
+        return;
+    }
+    for instance in instances {
+        let angle = rng.gen_range(0.0..std::f32::consts::PI * 2.0);
+        let radius = amount * rng.gen_range(0.0f32..1.0).sqrt();
+        instance.center_offset = [radius * angle.cos(), radius * angle.sin()];
+    }
+}
+
+// インスタンスバッファに並べる1インスタンス分のデータと、その頂点属性の並び
+// Instance 以外の型を使う場合は WgpuState::set_instance_data で渡し、同じ shader_location の属性を読むカスタムの頂点シェーダと組み合わせる
+// (スロット 0 の頂点と 2 の補間先の頂点はシェーダの location 0 / 10 を使うので、それ以外の番号を割り当てる)
+pub trait InstanceData: bytemuck::Pod + bytemuck::Zeroable {
+    // true なら不透明度を持つ型としてアルファブレンドで描く。false のままなら不透明として REPLACE で上書きする
+    // (Instance は false のままで、WgpuState が渡されたインスタンスの opacity を見て切り替える)
+    const TRANSLUCENT: bool = false;
+
+    fn buffer_layout() -> wgpu::VertexBufferLayout<'static>;
+}
+
+impl InstanceData for Instance {
+    fn buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+        get_instance_buffer_layout()
+    }
+}
+
+pub fn get_instance_buffer<I: InstanceData>(device: &wgpu::Device, instances: &[I]) -> wgpu::Buffer {
     use wgpu::util::DeviceExt;
-    return device.create_buffer_init(
+    device.create_buffer_init(
         &wgpu::util::BufferInitDescriptor {
             label: None,
             contents: bytemuck::cast_slice(instances),
-            usage: wgpu::BufferUsages::VERTEX
+            // morph_to の補間中は毎フレーム書き換える
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST
         }
-    );
+    )
 }
 
 pub fn get_instance_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
-    static ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+    static ATTRIBUTES: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![
         2 => Float32x2,
         3 => Float32,
         4 => Float32,
         5 => Float32x2,
-        6 => Float32
+        6 => Float32,
+        7 => Float32,
+        9 => Float32x2
     ];
 
     wgpu::VertexBufferLayout {
@@ -66,4 +400,49 @@ pub fn get_instance_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
         step_mode: wgpu::VertexStepMode::Instance,
         attributes: &ATTRIBUTES,
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn same_seed_gives_same_field() {
+        let first = create_star_instances_with_rng(&mut rand::rngs::StdRng::seed_from_u64(42), 0.5);
+        let second = create_star_instances_with_rng(&mut rand::rngs::StdRng::seed_from_u64(42), 0.5);
+        assert_eq!(first.len(), WgpuState::STAR_INSTANCE_COUNT as usize);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn different_seeds_give_different_fields() {
+        let first = create_star_instances_with_rng(&mut rand::rngs::StdRng::seed_from_u64(42), 0.5);
+        let second = create_star_instances_with_rng(&mut rand::rngs::StdRng::seed_from_u64(43), 0.5);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn color_seed_changes_only_colors() {
+        let first = create_star_instances_with_seeds(7, 1, 0.5);
+        let second = create_star_instances_with_seeds(7, 2, 0.5);
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!((a.position, a.scale, a.speed), (b.position, b.scale, b.speed));
+            assert_eq!((a.initial_rotation, a.rotation_speed), (b.initial_rotation, b.rotation_speed));
+        }
+        assert!(first.iter().zip(&second).any(|(a, b)| a.opacity != b.opacity));
+    }
+
+    #[test]
+    fn spin_bias_picks_rotation_direction() {
+        let spins = |spin_bias: f32| -> Vec<f32> {
+            create_star_instances_with_rng(&mut rand::rngs::StdRng::seed_from_u64(42), spin_bias)
+                .iter()
+                .map(|instance| instance.rotation_speed)
+                .collect()
+        };
+        assert!(spins(1.0).iter().all(|speed| *speed > 0.0));
+        assert!(spins(0.0).iter().all(|speed| *speed < 0.0));
+        let mixed = spins(0.5);
+        assert!(mixed.iter().any(|speed| *speed > 0.0) && mixed.iter().any(|speed| *speed < 0.0));
+    }
+}
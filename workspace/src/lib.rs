@@ -1,15 +1,66 @@
+mod background;
+mod camera;
+mod clock;
+mod config;
+mod error;
+mod export;
+mod gradient;
+mod gpu_timer;
+mod grid;
+mod group;
 mod state;
 mod instance;
+mod link;
+mod morph;
+mod outline;
+mod physics;
+#[cfg(feature = "serde")]
+mod scene;
+#[cfg(feature = "serde")]
+mod snapshot;
+mod software;
 mod vertex;
 mod uniform;
+mod upscale;
+#[cfg(feature = "egui")]
+mod ui;
 
-use state::WgpuState;
+pub use background::BackgroundMode;
+pub use camera::Camera2D;
+pub use clock::PausableClock;
+pub use config::WgpuStateConfig;
+pub use error::{CaptureError, ConfigError, WgpuStateError};
+pub use export::{mesh_to_obj, mesh_to_svg, SvgUnit};
+#[cfg(feature = "serde")]
+pub use error::SceneError;
+pub use gpu_timer::GpuTimer;
+pub use gradient::{GradientAxis, SpatialGradient, MAX_GRADIENT_STOPS};
+pub use group::GroupParams;
+pub use instance::{
+    create_seeded_star_instances, create_star_instances, create_star_instances_with_margin,
+    create_star_instances_with_params, create_star_instances_with_rng, create_star_instances_with_rngs,
+    create_star_instances_with_seeds, create_star_instances_with_spin_bias, get_instance_buffer, jitter_star_centers, Instance,
+    InstanceData, InstanceParams,
+};
+pub use morph::{Easing, IntroEdge};
+pub use outline::OutlineStyle;
+pub use physics::PhysicsMode;
+#[cfg(feature = "serde")]
+pub use scene::SceneConfig;
+#[cfg(feature = "serde")]
+pub use snapshot::Snapshot;
+pub use software::SoftwareRenderer;
+pub use state::{FrameStats, MemoryReport, ReadyCallback, RenderCallback, UpdateCallback, WgpuState};
+#[cfg(feature = "egui")]
+pub use ui::TuningPanel;
+pub use uniform::{UniformUpdateMode, Uniforms};
+pub use vertex::{fan_indices, subdivide_triangles, validate_winding, LineVertex, Vertex};
 
 use winit::{
     event::*,
-    event_loop::{self, EventLoop},
+    event_loop::{ControlFlow, EventLoop},
     keyboard::{KeyCode, PhysicalKey},
-    window::{self, WindowBuilder}
+    window::{Fullscreen, WindowBuilder}
 };
 
 #[cfg(target_arch = "wasm32")]
@@ -19,37 +70,33 @@ use wasm_bindgen::prelude::wasm_bindgen;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-fn main() {
-    pollster::block_on(run());
-}
-
 #[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen(start))]
-async fn run() {
+pub async fn run() {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
             console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
-            wasm_logger::init(wasm_logger::Config::default());
         } else {
             env_logger::init();
         }
     }
 
-    cfg_if::cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            use winit::platform::web::EventLoopExtWebSys;
-            let event_loop = EventLoop::new().expect("Event Loop Error");
-            let window = WindowBuilder::new()
-                .with_title("Pentagrams WebAssembly")
-                .build(&event_loop)
-                .unwrap();
-        } else {
-            let event_loop = EventLoop::new().expect("Event Loop Error");
-            let window = WindowBuilder::new()
-                .with_title("Pentagrams Native Window")
-                .build(&event_loop)
-                .unwrap();
-        }
+    let state_config = WgpuStateConfig::default();
+
+    let title = if cfg!(target_arch = "wasm32") {
+        "Pentagrams WebAssembly"
+    } else {
+        "Pentagrams Native Window"
+    };
+    let event_loop = EventLoop::new().expect("Event Loop Error");
+    let window = WindowBuilder::new()
+        .with_title(title)
+        .with_transparent(state_config.transparent_window)
+        .with_fullscreen(state_config.screensaver.then_some(Fullscreen::Borderless(None)))
+        .build(&event_loop)
+        .unwrap();
+    if state_config.screensaver {
+        window.set_cursor_visible(false);
     }
 
     cfg_if::cfg_if! {
@@ -71,7 +118,11 @@ async fn run() {
         }
     }
 
-    let mut state = WgpuState::new(&window).await;
+    let mut state = WgpuState::with_config(&window, state_config)
+        .await
+        .expect("Failed to initialize the renderer");
+    #[cfg(feature = "egui")]
+    state.enable_tuning_panel();
     let mut surface_configured = false;
 
     event_loop
@@ -80,46 +131,97 @@ async fn run() {
                 Event::WindowEvent {
                     ref event,
                     window_id,
-                } if window_id == state.window.id() => {
-                    if !state.input(event) {
-                        match event {
-                            WindowEvent::CloseRequested
-                            | WindowEvent::KeyboardInput {
-                                event: KeyEvent {
-                                    state: ElementState::Pressed,
-                                    physical_key: PhysicalKey::Code(KeyCode::Escape),
-                                    ..
-                                },
-                                ..
-                            } => control_flow.exit(),
-                            WindowEvent::Resized(physical_size) => {
-                                log::info!("physical_size: {physical_size:?}");
-                                surface_configured = true;
-                                state.resize(*physical_size);
+                } if window_id == state.window.id() && state.screensaver_wakes(event) => control_flow.exit(),
+                Event::WindowEvent {
+                    ref event,
+                    window_id,
+                } if window_id == state.window.id() && state.input(event) => {
+                    // 入力でカメラや配置が変わったので、静止画モードでも描き直す
+                    state.request_redraw();
+                }
+                Event::WindowEvent {
+                    ref event,
+                    window_id,
+                } if window_id == state.window.id() => match event {
+                    WindowEvent::CloseRequested
+                    | WindowEvent::KeyboardInput {
+                        event: KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::Escape),
+                            ..
+                        },
+                        ..
+                    } => control_flow.exit(),
+                    WindowEvent::Resized(physical_size) => {
+                        log::info!("physical_size: {physical_size:?}");
+                        surface_configured = true;
+                        state.resize(*physical_size);
+                        state.request_redraw();
+                    }
+                    WindowEvent::Focused(focused) => {
+                        // フォーカスが外れている間は再描画を止めてイベント待ちにし、電力消費を抑える
+                        state.set_focused(*focused);
+                        if *focused {
+                            if state.needs_redraw() {
+                                control_flow.set_control_flow(ControlFlow::Poll);
                             }
-                            WindowEvent::RedrawRequested => {
-                                state.window.request_redraw();
-
-                                if (!surface_configured) {
-                                    return;
-                                }
+                            // 隠れている間に要求が捨てられていることがあるので、届いていない要求は忘れて要求し直す
+                            state.redraw_requested = false;
+                            state.request_redraw();
+                        } else {
+                            control_flow.set_control_flow(ControlFlow::Wait);
+                        }
+                    }
+                    WindowEvent::RedrawRequested => {
+                        // 要求していた再描画が届いたので、次の要求を受け付ける
+                        state.redraw_requested = false;
+                        if !state.focused || state.suspended {
+                            return;
+                        }
+                        // 動いているものが無くなったらイベント待ちにし、入力や大きさの変更で描き直すまで電力を抑える
+                        let animating = state.needs_redraw();
+                        control_flow.set_control_flow(if animating { ControlFlow::Poll } else { ControlFlow::Wait });
+                        // 次のフレームは今のフレームを描き終えてから1つだけ要求する
+                        if !surface_configured || !state.is_ready() {
+                            if animating {
+                                state.request_redraw();
+                            }
+                            return;
+                        }
 
-                                state.update();
-                                match state.render() {
-                                    Ok(_) => {}
-                                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => state.resize(state.size),
-                                    Err(wgpu::SurfaceError::OutOfMemory) => {
-                                        log::error!("OutOfMemory");
-                                        control_flow.exit();
-                                    }
-                                    Err(wgpu::SurfaceError::Timeout) => {
-                                        log::warn!("Surface timeout")
-                                    }
-                                }
+                        state.update();
+                        match state.render() {
+                            Ok(_) => {}
+                            // Lost / Outdated は render の中でフレームごとに再設定を試み、続けて回復しなかった場合だけ返ってくる
+                            // 終了はせずにこのフレームを飛ばし、次の再描画でまた試す
+                            Err(error @ (wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated)) => {
+                                log::warn!("Skipping the frame: surface is {error:?}")
+                            }
+                            Err(wgpu::SurfaceError::OutOfMemory) => {
+                                log::error!("OutOfMemory");
+                                control_flow.exit();
+                            }
+                            Err(wgpu::SurfaceError::Timeout) => {
+                                log::warn!("Surface timeout")
                             }
-                            _ => {}
                         }
+                        if animating {
+                            state.request_redraw();
+                        }
+                    }
+                    _ => {}
+                },
+                // サスペンド中はサーフェスが使えないので、Resumed まで描画を止めてイベント待ちにする
+                Event::Suspended => {
+                    state.suspend();
+                    control_flow.set_control_flow(ControlFlow::Wait);
+                }
+                Event::Resumed if state.resume() => {
+                    if state.needs_redraw() {
+                        control_flow.set_control_flow(ControlFlow::Poll);
                     }
+                    state.redraw_requested = false;
+                    state.request_redraw();
                 }
                 _ => {}
             }
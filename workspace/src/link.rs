@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+// 近い星どうしを結ぶ線 (星座のような網目) の頂点。2頂点で1本の線になる
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LinkVertex {
+    // 星空全体の回転前の位置 (画面端の振る舞いは適用済み)
+    pub position: [f32; 2],
+    // link_color のアルファに近さ (重なっていれば 1.0、link_distance ちょうどで 0.0) を掛けた色
+    pub color: [f32; 4],
+}
+
+impl LinkVertex {
+    pub fn get_vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+        static ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+            0 => Float32x2,
+            1 => Float32x4
+        ];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LinkVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// 距離が link_distance 未満の組 (添字の小さい方, 大きい方, 近さ) を返す
+pub(crate) fn find_links(positions: &[[f32; 2]], link_distance: f32) -> Vec<(usize, usize, f32)> {
+    let mut links = Vec::new();
+    for_each_near_pair(positions, link_distance, |index, other, distance| {
+        if distance < link_distance {
+            links.push((index, other, 1.0 - distance / link_distance));
+        }
+    });
+    links
+}
+
+// 距離が cell_size 未満になりうる組 (添字の小さい方, 大きい方, 距離) ごとに visit を呼ぶ (cell_size 以上離れた組も混ざる)
+// cell_size 四方のマス目に振り分け、周囲 3x3 マスの中だけを調べるので、星が散らばっていればほぼ線形時間で済む
+pub(crate) fn for_each_near_pair(positions: &[[f32; 2]], cell_size: f32, mut visit: impl FnMut(usize, usize, f32)) {
+    if cell_size <= 0.0 {
+        return;
+    }
+
+    let cell_of = |position: [f32; 2]| {
+        (
+            (position[0] / cell_size).floor() as i32,
+            (position[1] / cell_size).floor() as i32,
+        )
+    };
+    let mut grid: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+    for (index, position) in positions.iter().enumerate() {
+        grid.entry(cell_of(*position)).or_default().push(index);
+    }
+
+    for (index, position) in positions.iter().enumerate() {
+        let (cell_x, cell_y) = cell_of(*position);
+        for neighbor_x in cell_x.saturating_sub(1)..=cell_x.saturating_add(1) {
+            for neighbor_y in cell_y.saturating_sub(1)..=cell_y.saturating_add(1) {
+                let Some(cell) = grid.get(&(neighbor_x, neighbor_y)) else {
+                    continue;
+                };
+                // 同じ組を2回数えないよう、相手の添字が大きい場合だけ調べる
+                for &other in cell.iter().filter(|other| **other > index) {
+                    visit(index, other, (positions[other][0] - position[0]).hypot(positions[other][1] - position[1]));
+                }
+            }
+        }
+    }
+}
+
+// find_links で見つけた組を、LineList で描く頂点の列にする
+pub(crate) fn build_link_vertices(positions: &[[f32; 2]], link_distance: f32, color: [f32; 4]) -> Vec<LinkVertex> {
+    let mut vertices = Vec::new();
+    for (a, b, proximity) in find_links(positions, link_distance) {
+        let color = [color[0], color[1], color[2], color[3] * proximity];
+        vertices.push(LinkVertex { position: positions[a], color });
+        vertices.push(LinkVertex { position: positions[b], color });
+    }
+    vertices
+}
+
+// 毎フレーム書き換える線の頂点バッファ。足りなくなったら2の冪の頂点数で作り直す
+pub(crate) fn create_link_buffer(device: &wgpu::Device, vertex_capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Link Vertex Buffer"),
+        size: (vertex_capacity.max(2).next_power_of_two() * std::mem::size_of::<LinkVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+// 星を描くのと同じ uniform で、同梱シェーダの vertexLink / fragmentLink を使って1ピクセル幅の線を描く
+// カスタムシェーダを指定していても、線は常に同梱シェーダで描く
+pub(crate) fn create_link_pipeline(
+    device: &wgpu::Device,
+    uniform_bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    config: &wgpu::SurfaceConfiguration,
+    state_config: &crate::config::WgpuStateConfig,
+) -> wgpu::RenderPipeline {
+    create_line_pipeline(
+        device,
+        uniform_bind_group_layout,
+        shader,
+        config,
+        state_config,
+        "Link Pipeline",
+        "vertexLink",
+    )
+}
+
+// LinkVertex を LineList で描くパイプライン。頂点シェーダだけを vertex_entry_point で選ぶ
+pub(crate) fn create_line_pipeline(
+    device: &wgpu::Device,
+    uniform_bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    config: &wgpu::SurfaceConfiguration,
+    state_config: &crate::config::WgpuStateConfig,
+    label: &str,
+    vertex_entry_point: &str,
+) -> wgpu::RenderPipeline {
+    let blend = if state_config.premultiplied_alpha {
+        wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING
+    } else {
+        wgpu::BlendState::ALPHA_BLENDING
+    };
+    let mut constants = std::collections::HashMap::new();
+    constants.insert(
+        String::from("premultipliedAlpha"),
+        if state_config.premultiplied_alpha { 1.0 } else { 0.0 },
+    );
+    constants.insert(
+        String::from("encodeSrgb"),
+        if crate::uniform::shader_encodes_srgb(config.format) { 1.0 } else { 0.0 },
+    );
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &[uniform_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some(vertex_entry_point),
+            compilation_options: Default::default(),
+            buffers: &[LinkVertex::get_vertex_buffer_layout()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fragmentLink"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: config.format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &constants,
+                ..Default::default()
+            },
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::LineList,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,3 @@
+fn main() {
+    pollster::block_on(rust_pentagram::run());
+}
@@ -0,0 +1,89 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use crate::instance::Instance;
+
+// インスタンス配置を切り替えるときの補間の緩急
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Easing {
+    #[default]
+    Linear,
+    // 始めと終わりをゆっくりにする (smoothstep)
+    EaseInOut,
+    // 速く動き始めて、終わりに向かってゆっくり止まる (3次)
+    EaseOut,
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => t,
+            Self::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Self::EaseOut => 1.0 - (1.0 - t).powi(3),
+        }
+    }
+}
+
+// 起動時の登場演出で、星が画面外のどこから飛んでくるか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntroEdge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+    // 画面中心から見て各星の方向の画面外
+    #[default]
+    Around,
+}
+
+impl IntroEdge {
+    // Uniforms::intro_edge に書き込む値
+    pub(crate) fn as_uniform(self) -> u32 {
+        match self {
+            Self::Left => 0,
+            Self::Right => 1,
+            Self::Top => 2,
+            Self::Bottom => 3,
+            Self::Around => 4,
+        }
+    }
+}
+
+// 2つのインスタンス配置の間を duration 秒かけて補間する
+pub(crate) struct Morph {
+    source: Vec<Instance>,
+    target: Vec<Instance>,
+    started_at: Instant,
+    duration: f32,
+    easing: Easing,
+}
+
+impl Morph {
+    pub(crate) fn new(source: Vec<Instance>, target: Vec<Instance>, duration: f32, easing: Easing) -> Self {
+        Self {
+            source,
+            target,
+            started_at: Instant::now(),
+            duration,
+            easing,
+        }
+    }
+
+    // now 時点の補間済みの配置と、補間が終わったかどうかを返す
+    // 移行前の配置に対応するインスタンスがない分は、最初から移行先の値を使う
+    pub(crate) fn sample(&self, now: Instant) -> (Vec<Instance>, bool) {
+        let progress = now.duration_since(self.started_at).as_secs_f32() / self.duration;
+        let t = self.easing.apply(progress);
+        let instances = self
+            .target
+            .iter()
+            .enumerate()
+            .map(|(i, target)| self.source.get(i).map_or(*target, |source| source.lerp(target, t)))
+            .collect();
+        (instances, progress >= 1.0)
+    }
+}
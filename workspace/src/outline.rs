@@ -0,0 +1,142 @@
+use wgpu::util::DeviceExt;
+
+// 塗りつぶしの星形の上に重ねる縁取りの色 (線形の RGBA) と太さ (ピクセル)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutlineStyle {
+    pub color: [f32; 4],
+    pub width: f32,
+}
+
+impl Default for OutlineStyle {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0, 1.0],
+            width: 1.5,
+        }
+    }
+}
+
+// outline_style の縁取りを描くパイプラインと、星1つ分の輪郭の辺の四角形
+// 塗りつぶしと同じインスタンスバッファを読むので、星の動きや自転にそのまま付いていく
+pub(crate) struct OutlineStroke {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+}
+
+impl OutlineStroke {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        config: &wgpu::SurfaceConfiguration,
+        state_config: &crate::config::WgpuStateConfig,
+    ) -> Self {
+        let pipeline = create_stroke_pipeline(device, uniform_bind_group_layout, shader, config, state_config);
+        let (vertex_buffer, index_buffer, index_count) = create_stroke_geometry(device, state_config);
+        Self {
+            pipeline,
+            vertex_buffer,
+            index_buffer,
+            index_count,
+        }
+    }
+
+    // 星の形を変えた後に、パイプラインはそのまま輪郭の頂点だけを作り直す
+    pub(crate) fn rebuild_geometry(&mut self, device: &wgpu::Device, state_config: &crate::config::WgpuStateConfig) {
+        (self.vertex_buffer, self.index_buffer, self.index_count) = create_stroke_geometry(device, state_config);
+    }
+
+    // 続けて呼ぶ draw が使うパイプラインと頂点・インスタンスバッファを設定する
+    pub(crate) fn bind(&self, render_pass: &mut wgpu::RenderPass, instance_buffer: &wgpu::Buffer) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+    }
+
+    pub(crate) fn draw(&self, render_pass: &mut wgpu::RenderPass, instances: std::ops::Range<u32>) {
+        render_pass.draw_indexed(0..self.index_count, 0, instances);
+    }
+}
+
+fn create_stroke_geometry(
+    device: &wgpu::Device,
+    state_config: &crate::config::WgpuStateConfig,
+) -> (wgpu::Buffer, wgpu::Buffer, u32) {
+    let (vertices, indices) = crate::state::WgpuState::create_outline_vertices(state_config);
+    let vertex_buffer = crate::vertex::LineVertex::get_vertex_buffer(device, &vertices);
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Outline Stroke Index Buffer"),
+        contents: bytemuck::cast_slice(&indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+    (vertex_buffer, index_buffer, indices.len() as u32)
+}
+
+// 同梱シェーダの vertexStroke / fragmentStroke で、輪郭の辺の四角形をアルファブレンドして重ねる
+// カスタムシェーダは縁取りの入口を宣言していないことがあるので、常に同梱シェーダを使う
+fn create_stroke_pipeline(
+    device: &wgpu::Device,
+    uniform_bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    config: &wgpu::SurfaceConfiguration,
+    state_config: &crate::config::WgpuStateConfig,
+) -> wgpu::RenderPipeline {
+    let blend = if state_config.premultiplied_alpha {
+        wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING
+    } else {
+        wgpu::BlendState::ALPHA_BLENDING
+    };
+    let mut constants = std::collections::HashMap::new();
+    constants.insert(
+        String::from("premultipliedAlpha"),
+        if state_config.premultiplied_alpha { 1.0 } else { 0.0 },
+    );
+    constants.insert(
+        String::from("encodeSrgb"),
+        if crate::uniform::shader_encodes_srgb(config.format) { 1.0 } else { 0.0 },
+    );
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &[uniform_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Outline Stroke Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vertexStroke"),
+            compilation_options: Default::default(),
+            buffers: &[
+                crate::vertex::LineVertex::get_vertex_buffer_layout(),
+                crate::instance::get_instance_buffer_layout(),
+            ],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fragmentStroke"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: config.format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &constants,
+                ..Default::default()
+            },
+        }),
+        // 輪郭線の四角形は辺の向きによって巻き順が変わるのでカリングしない
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
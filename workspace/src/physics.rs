@@ -0,0 +1,112 @@
+// 星が画面端 (星空の座標で -1.0〜1.0) に達したときの振る舞い
+// シェーダは時刻から位置を直接求めるので、モードごとの計算は shader.wgsl の applyPhysics と揃えること
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PhysicsMode {
+    // 反対側の端から出てくる
+    #[default]
+    Wrap,
+    // 端で跳ね返る
+    Bounce,
+    // 端で止めずに画面の外へ流れていく
+    None,
+}
+
+impl PhysicsMode {
+    // P キーで切り替える順番
+    pub fn next(self) -> Self {
+        match self {
+            Self::Wrap => Self::Bounce,
+            Self::Bounce => Self::None,
+            Self::None => Self::Wrap,
+        }
+    }
+
+    // Uniforms::physics_mode に書き込む値
+    pub(crate) fn as_uniform(self) -> u32 {
+        match self {
+            Self::Wrap => 0,
+            Self::Bounce => 1,
+            Self::None => 2,
+        }
+    }
+
+    // 初期位置と速度から time 秒後に画面に映る1軸分の位置と、その時点の見かけの速度を求める
+    pub(crate) fn apply(self, position: f32, speed: f32, time: f32) -> (f32, f32) {
+        let moved = position + speed * time;
+        match self {
+            Self::Wrap => ((moved + 1.0).rem_euclid(2.0) - 1.0, speed),
+            Self::Bounce => {
+                // 周期 4 の三角波。後半の半周期は向きが逆になる
+                let phase = (moved + 1.0).rem_euclid(4.0);
+                if phase > 2.0 {
+                    (3.0 - phase, -speed)
+                } else {
+                    (phase - 1.0, speed)
+                }
+            }
+            Self::None => (moved, speed),
+        }
+    }
+}
+
+// 距離の2乗に足して、カーソルのすぐ近くで引力が発散しないようにする値
+const ATTRACTOR_SOFTENING: f32 = 0.05;
+
+// position の星が、強さ strength の引力で attractor の方へ受ける加速度 (負の strength では遠ざける向き)
+// 大きさは距離の2乗に反比例する
+pub(crate) fn attraction(position: [f32; 2], attractor: [f32; 2], strength: f32) -> [f32; 2] {
+    let delta = [attractor[0] - position[0], attractor[1] - position[1]];
+    let distance = delta[0].hypot(delta[1]);
+    if strength == 0.0 || distance < 0.0001 {
+        return [0.0, 0.0];
+    }
+    let magnitude = strength / (distance * distance + ATTRACTOR_SOFTENING);
+    [delta[0] / distance * magnitude, delta[1] / distance * magnitude]
+}
+
+// 重なっている (中心の距離が半径の和より小さい) 星どうしを、近づいている組だけ弾性衝突させて velocities を書き換える
+// 質量は半径の2乗に比例させ、fixed の星は動かない (質量が無限大の) 壁として扱う
+// restitution は反発係数で、1.0 なら運動エネルギーを保ち、0.0 なら法線方向の相対速度が無くなる
+// 速度を書き換えた星の添字を返す
+pub(crate) fn collide(
+    positions: &[[f32; 2]],
+    radii: &[f32],
+    fixed: &[bool],
+    velocities: &mut [[f32; 2]],
+    restitution: f32,
+) -> Vec<usize> {
+    let mut changed = vec![false; velocities.len()];
+    let max_radius = radii.iter().copied().fold(0.0, f32::max);
+    let inverse_mass = |index: usize| {
+        if fixed[index] {
+            0.0
+        } else {
+            1.0 / (radii[index] * radii[index]).max(1e-8)
+        }
+    };
+    crate::link::for_each_near_pair(positions, max_radius * 2.0, |a, b, distance| {
+        let (inverse_a, inverse_b) = (inverse_mass(a), inverse_mass(b));
+        if distance >= radii[a] + radii[b] || distance < 1e-6 || inverse_a + inverse_b == 0.0 {
+            return;
+        }
+        let normal = [
+            (positions[b][0] - positions[a][0]) / distance,
+            (positions[b][1] - positions[a][1]) / distance,
+        ];
+        let approach = (velocities[b][0] - velocities[a][0]) * normal[0] + (velocities[b][1] - velocities[a][1]) * normal[1];
+        // 離れつつある組はそのまま (押し返した直後にまた引き戻さないように)
+        if approach >= 0.0 {
+            return;
+        }
+        let impulse = -(1.0 + restitution) * approach / (inverse_a + inverse_b);
+        for (index, sign, inverse) in [(a, -1.0, inverse_a), (b, 1.0, inverse_b)] {
+            if inverse > 0.0 {
+                velocities[index][0] += sign * impulse * inverse * normal[0];
+                velocities[index][1] += sign * impulse * inverse * normal[1];
+                changed[index] = true;
+            }
+        }
+    });
+    changed.iter().enumerate().filter_map(|(index, changed)| changed.then_some(index)).collect()
+}
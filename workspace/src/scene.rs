@@ -0,0 +1,157 @@
+use crate::background::BackgroundMode;
+use crate::camera::Camera2D;
+use crate::config::WgpuStateConfig;
+use crate::error::{SceneError, WgpuStateError};
+use crate::state::WgpuState;
+
+// 星の形・配置の生成・色・カメラ・PresentMode をまとめたシーン全体の設定
+// RON ファイルとして保存・読み込みして、プリセットとして共有できる
+// 書かれていない項目はデフォルト値になる
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SceneConfig {
+    pub star_points: u32,
+    pub star_skip: u32,
+    pub tip_sharpness: f32,
+    pub subdivisions: u32,
+    pub point_mode: bool,
+    pub outline_mode: bool,
+    pub line_width: f32,
+    // Some の場合はこの種で星空の配置を作り直す。None の場合は今の配置のまま
+    pub seed: Option<u64>,
+    // Some の場合は星ごとの明るさをこの種で引き、seed の配置とは独立に決める
+    // seed が None なら今の配置のまま明るさだけを引き直す
+    pub color_seed: Option<u64>,
+    pub spin_bias: f32,
+    pub background: BackgroundMode,
+    pub doppler_intensity: f32,
+    pub field_rotation_speed: f32,
+    pub twinkle_speed: f32,
+    pub twinkle_amount: f32,
+    pub camera: Camera2D,
+    // None の場合は今の PresentMode のまま
+    pub present_mode: Option<wgpu::PresentMode>,
+}
+
+impl Default for SceneConfig {
+    fn default() -> Self {
+        let config = WgpuStateConfig::default();
+        Self {
+            star_points: config.star_points,
+            star_skip: config.star_skip,
+            tip_sharpness: config.tip_sharpness,
+            subdivisions: config.subdivisions,
+            point_mode: config.point_mode,
+            outline_mode: config.outline_mode,
+            line_width: config.line_width,
+            seed: None,
+            color_seed: None,
+            spin_bias: config.spin_bias,
+            background: config.background,
+            doppler_intensity: 0.0,
+            field_rotation_speed: 0.0,
+            twinkle_speed: 1.0,
+            twinkle_amount: 0.0,
+            camera: Camera2D::default(),
+            present_mode: None,
+        }
+    }
+}
+
+impl SceneConfig {
+    pub fn to_ron(&self) -> Result<String, SceneError> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(SceneError::Serialize)
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, SceneError> {
+        ron::from_str(source).map_err(SceneError::Parse)
+    }
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), SceneError> {
+        std::fs::write(path, self.to_ron()?).map_err(SceneError::Io)
+    }
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, SceneError> {
+        Self::from_ron(&std::fs::read_to_string(path).map_err(SceneError::Io)?)
+    }
+}
+
+impl WgpuState<'_> {
+    // 今の状態をシーンとして取り出す (配置は保存しないので seed と color_seed は None)
+    pub fn scene(&self) -> SceneConfig {
+        SceneConfig {
+            star_points: self.state_config.star_points,
+            star_skip: self.state_config.star_skip,
+            tip_sharpness: self.state_config.tip_sharpness,
+            subdivisions: self.state_config.subdivisions,
+            point_mode: self.state_config.point_mode,
+            outline_mode: self.state_config.outline_mode,
+            line_width: self.state_config.line_width,
+            seed: None,
+            color_seed: None,
+            spin_bias: self.state_config.spin_bias,
+            background: self.state_config.background,
+            doppler_intensity: self.doppler_intensity,
+            field_rotation_speed: self.field_rotation_speed,
+            twinkle_speed: self.twinkle_speed,
+            twinkle_amount: self.twinkle_amount,
+            camera: self.camera,
+            present_mode: self.config.as_ref().map(|config| config.present_mode),
+        }
+    }
+
+    // シーンを適用する。星の形や描画モードが変わる場合だけ頂点バッファとパイプラインを作り直す
+    pub fn apply_scene(&mut self, scene: &SceneConfig) -> Result<(), WgpuStateError> {
+        let mut state_config = self.state_config.clone();
+        state_config.star_points = scene.star_points;
+        state_config.star_skip = scene.star_skip;
+        state_config.tip_sharpness = scene.tip_sharpness;
+        state_config.subdivisions = scene.subdivisions;
+        state_config.point_mode = scene.point_mode;
+        state_config.outline_mode = scene.outline_mode;
+        state_config.line_width = scene.line_width;
+        state_config.spin_bias = scene.spin_bias;
+        state_config.validate()?;
+
+        let geometry_changed = (
+            state_config.star_points,
+            state_config.star_skip,
+            state_config.tip_sharpness.to_bits(),
+            state_config.subdivisions,
+            state_config.point_mode,
+            state_config.outline_mode,
+        ) != (
+            self.state_config.star_points,
+            self.state_config.star_skip,
+            self.state_config.tip_sharpness.to_bits(),
+            self.state_config.subdivisions,
+            self.state_config.point_mode,
+            self.state_config.outline_mode,
+        );
+        let pipeline_changed = (state_config.point_mode, state_config.outline_mode)
+            != (self.state_config.point_mode, self.state_config.outline_mode);
+        self.state_config = state_config;
+        if geometry_changed {
+            self.rebuild_geometry();
+        }
+        if pipeline_changed {
+            self.rebuild_pipeline();
+        }
+
+        match (scene.seed, scene.color_seed) {
+            (Some(seed), Some(color_seed)) => self.regenerate_field_with_seeds(seed, color_seed),
+            (Some(seed), None) => self.regenerate_field(seed),
+            (None, Some(color_seed)) => self.recolor_field(color_seed),
+            (None, None) => {}
+        }
+        self.set_background_mode(scene.background);
+        self.set_doppler_intensity(scene.doppler_intensity);
+        self.set_field_rotation_speed(scene.field_rotation_speed);
+        self.set_twinkle(scene.twinkle_speed, scene.twinkle_amount);
+        self.set_camera(scene.camera);
+        if let Some(present_mode) = scene.present_mode {
+            self.set_present_mode(present_mode);
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,44 @@
 struct Uniforms {
+    projection: mat4x4<f32>,
     time: f32,
+    fieldRotation: f32,
+    dopplerIntensity: f32,
+    lineWidth: f32,
+    resolution: vec2<f32>,
+    twinkleSpeed: f32,
+    twinkleAmount: f32,
+    startupFade: f32,
+    // 0: 反対側へラップ, 1: 跳ね返る, 2: そのまま画面外へ (PhysicsMode と対応)
+    physicsMode: u32,
+    // クリックで選んだ星の instance_index (0xffffffff で選択なし) と、その星を止めた時刻
+    selectedIndex: u32,
+    selectedTime: f32,
+    // 登場演出の進み具合 (1.0 で完了) と、飛んでくる方向 (0: 左, 1: 右, 2: 上, 3: 下, 4: 周囲)
+    introProgress: f32,
+    introEdge: u32,
+    // 星形とその補間先の形の間の割合 (0.0 で星形、1.0 で補間先)
+    shapeMorph: f32,
+    // 星の移動と自転に使う時刻 (別々に止められる)。time は瞬きなどに使い、これらを止めても進む
+    translationTime: f32,
+    rotationTime: f32,
+    // 選択中の星を止めた時点の自転の時刻 (selectedTime は移動の時刻)
+    selectedRotationTime: f32,
+    // 星の形の長さ 1.0 を何ピクセルとして描くか (0.0 で星空の座標のまま)
+    radiusPixels: f32,
+    // 塗りつぶしの上に重ねる縁取り (outline_style) の太さ (ピクセル) と色
+    outlineWidth: f32,
+    outlineColor: vec4<f32>,
+    // spatial_gradient の色と位置 (0.0〜1.0)、向き (0: 左→右, 1: 上→下, 2: 中心→外) と色の数 (0 で無効)
+    gradientColors: array<vec4<f32>, 4>,
+    gradientOffsets: vec4<f32>,
+    gradientAxis: u32,
+    gradientStopCount: u32,
+}
+
+// グループごとの色合いと速さ (描画ごとに動的オフセットで切り替わる)
+struct GroupUniforms {
+    tint: vec4<f32>,
+    speedScale: f32,
 }
 
 struct InstanceInput {
@@ -8,25 +47,142 @@ struct InstanceInput {
     @location(4) initialRotation: f32,
     @location(5) speed: vec2<f32>,
     @location(6) rotationSpeed: f32,
+    @location(7) opacity: f32,
+    @location(9) centerOffset: vec2<f32>,
+}
+
+struct LineInput {
+    @location(0) start: vec2<f32>,
+    @location(1) end: vec2<f32>,
+    // x: 辺に沿った位置 (0.0 = start, 1.0 = end), y: 辺のどちら側か (-1.0 / 1.0)
+    @location(8) params: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) speed: f32,
+    @location(1) dopplerIntensity: f32,
+    // インスタンス内のローカル座標 (ポイント描画時の中心からの距離計算に使う)
+    @location(2) local: vec2<f32>,
+    @location(3) opacity: f32,
+    // 線の中心からの距離 (-1.0 〜 1.0、線描画のアンチエイリアスに使う)
+    @location(4) edge: f32,
+    // 選択中の星なら 1.0
+    @location(5) selected: f32,
+    // グループの色合い
+    @location(6) tint: vec3<f32>,
+    // 星の中心の、星空全体の回転後の座標 (spatial_gradient の色を取る位置)
+    @location(7) center: vec2<f32>,
+}
+
+// 近い星どうしを結ぶ線の出力
+struct LinkOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
 }
 
 @binding(0) @group(0) var<uniform> uniforms: Uniforms;
+@binding(1) @group(0) var<uniform> groupUniforms: GroupUniforms;
 
-@vertex
-fn vertexMain(
-    @location(0) position: vec2<f32>,
-    @builtin(instance_index) instanceIdx: u32,
-    instance: InstanceInput,
-) -> @builtin(position) vec4<f32> {
-    // アニメーションの計算
-    let rotation = instance.initialRotation + uniforms.time * instance.rotationSpeed;
-    var pos = instance.position + instance.speed * uniforms.time;
-    
-    // 画面端でのラップ処理
-    pos = vec2<f32>(
-        fract((pos.x + 1.0) / 2.0) * 2.0 - 1.0,
-        fract((pos.y + 1.0) / 2.0) * 2.0 - 1.0
+// true の場合、フラグメントの出力を RGB にアルファを掛けた乗算済みアルファにする
+// パイプライン作成時に WgpuStateConfig::premultiplied_alpha から設定される
+override premultipliedAlpha: bool = false;
+
+// true の場合、サーフェスは Rgba16Float で、星の中心を 1.0 を超える明るさで出力する
+// パイプライン作成時に WgpuStateConfig::hdr とサーフェスのフォーマットから設定される
+override hdrOutput: bool = false;
+
+// true の場合、サーフェスは sRGB でないフォーマットなので、線形の色に sRGB のガンマを掛けてから出力する
+// sRGB のフォーマットではハードウェアが変換するので線形のまま出力する
+// パイプライン作成時にサーフェスのフォーマットから設定される (src/uniform.rs の shader_encodes_srgb)
+override encodeSrgb: bool = false;
+
+// HDR 出力時の星の中心の明るさ (SDR の白を 1.0 とする倍率)
+const HDR_PEAK: f32 = 4.0;
+
+// 移動速度の最大値 (create_star_instances の速度範囲 ±0.3 の対角)
+const MAX_SPEED: f32 = 0.42;
+
+// 星空の座標 (-1.0〜1.0) をアスペクト比を補正したクリップ空間の座標に変換する
+fn toClip(world: vec2<f32>) -> vec2<f32> {
+    return (uniforms.projection * vec4<f32>(world, 0.0, 1.0)).xy;
+}
+
+// 画面端での振る舞い (src/physics.rs の PhysicsMode::apply と揃えること)
+fn applyPhysics(pos: vec2<f32>) -> vec2<f32> {
+    switch uniforms.physicsMode {
+        case 1u: {
+            // 周期 4 の三角波で端から端へ往復させる
+            let phase = (pos + 1.0) - 4.0 * floor((pos + 1.0) / 4.0);
+            return 1.0 - abs(phase - 2.0);
+        }
+        case 2u: {
+            return pos;
+        }
+        default: {
+            return fract((pos + 1.0) / 2.0) * 2.0 - 1.0;
+        }
+    }
+}
+
+// 星の移動 (x) と自転 (y) の時刻。選択中の星は止めた時刻のまま、それ以外は現在の時刻で動かす
+// (グループの速さの倍率を掛ける)
+fn instanceTimes(instanceIdx: u32) -> vec2<f32> {
+    let selected = instanceIdx == uniforms.selectedIndex;
+    let times = select(
+        vec2<f32>(uniforms.translationTime, uniforms.rotationTime),
+        vec2<f32>(uniforms.selectedTime, uniforms.selectedRotationTime),
+        selected,
+    );
+    return times * groupUniforms.speedScale;
+}
+
+// 登場演出で星が飛び始める、画面の外の距離
+// 長い辺の方向には -1.0〜1.0 の外側も映るので、21:9 程度の横長の画面でも映らない距離にする
+const INTRO_DISTANCE: f32 = 2.5;
+
+// 登場演出の間は、画面外の出発点から本来の位置 (resting) へ補間する
+fn introPosition(resting: vec2<f32>) -> vec2<f32> {
+    if uniforms.introProgress >= 1.0 {
+        return resting;
+    }
+    var spawn: vec2<f32>;
+    switch uniforms.introEdge {
+        case 0u: {
+            spawn = vec2<f32>(-INTRO_DISTANCE, resting.y);
+        }
+        case 1u: {
+            spawn = vec2<f32>(INTRO_DISTANCE, resting.y);
+        }
+        case 2u: {
+            spawn = vec2<f32>(resting.x, INTRO_DISTANCE);
+        }
+        case 3u: {
+            spawn = vec2<f32>(resting.x, -INTRO_DISTANCE);
+        }
+        default: {
+            spawn = resting / max(length(resting), 0.0001) * INTRO_DISTANCE;
+        }
+    }
+    return mix(spawn, resting, uniforms.introProgress);
+}
+
+// 星空全体を画面中心まわりに回転
+fn rotateField(pos: vec2<f32>) -> vec2<f32> {
+    let fc = cos(uniforms.fieldRotation);
+    let fs = sin(uniforms.fieldRotation);
+    let fieldRotMatrix = mat2x2<f32>(
+        fc, -fs,
+        fs, fc
     );
+    return fieldRotMatrix * pos;
+}
+
+// インスタンスのローカル座標を星空の座標に変換する。times は instanceTimes の移動と自転の時刻
+fn transformLocal(local: vec2<f32>, instance: InstanceInput, times: vec2<f32>) -> vec2<f32> {
+    // アニメーションの計算
+    let rotation = instance.initialRotation + times.y * instance.rotationSpeed;
+    let pos = rotateField(introPosition(applyPhysics(instance.position + instance.speed * times.x)));
 
     // 回転行列の作成
     let c = cos(rotation);
@@ -36,15 +192,245 @@ fn vertexMain(
         s, c
     );
 
+    // 中心を centerOffset だけずらし、外側の頂点 (中心から 1.0 以上) は動かさない
+    let skewed = local + instance.centerOffset * max(1.0 - length(local), 0.0);
+
     // 頂点の変換
-    let scaledPos = position * instance.scale;
+    let scaledPos = skewed * instance.scale * localUnit();
     let rotatedPos = rotMatrix * scaledPos;
-    let finalPos = rotatedPos + pos;
+    return rotatedPos + pos;
+}
+
+// 星の形の長さ 1.0 に当たる星空の座標での長さ (src/state.rs の local_unit と揃えること)
+// radiusPixels が正なら、projection で星空の長さ 1.0 が何ピクセルになるかで割ってピクセル指定を星空の座標に直す
+fn localUnit() -> f32 {
+    if uniforms.radiusPixels <= 0.0 {
+        return 1.0;
+    }
+    let pixelsPerUnit = length(uniforms.projection[0].xy) * uniforms.resolution.x * 0.5;
+    return uniforms.radiusPixels / max(pixelsPerUnit, 1e-6);
+}
+
+// 整数から擬似乱数の整数を作る (PCG ハッシュ)
+fn pcgHash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn hashToUnit(value: u32) -> f32 {
+    return f32(pcgHash(value)) / 4294967295.0;
+}
+
+// インスタンス番号ごとに独立した、時間方向に滑らかな1次元のバリューノイズで明るさを揺らす
+// 1インスタンス内では一定なので、頂点シェーダで計算して tint (RGB) に掛ける
+// 不透明度ではなく色を暗くするので、アルファブレンドしないパイプラインでも見える
+fn twinkle(instanceIdx: u32) -> f32 {
+    let seed = pcgHash(instanceIdx);
+    let t = uniforms.time * uniforms.twinkleSpeed + hashToUnit(seed) * 16.0;
+    let cell = u32(floor(t));
+    let a = hashToUnit(seed ^ pcgHash(cell));
+    let b = hashToUnit(seed ^ pcgHash(cell + 1u));
+    let noise = mix(a, b, smoothstep(0.0, 1.0, fract(t)));
+    return 1.0 - uniforms.twinkleAmount * noise;
+}
+
+// morphPosition は補間先の形の同じ番号の頂点 (補間しない場合は position と同じ)
+@vertex
+fn vertexMain(
+    @location(0) position: vec2<f32>,
+    @location(10) morphPosition: vec2<f32>,
+    @builtin(instance_index) instanceIdx: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let local = mix(position, morphPosition, uniforms.shapeMorph);
+    let times = instanceTimes(instanceIdx);
+    var out: VertexOutput;
+    out.position = vec4<f32>(toClip(transformLocal(local, instance, times)), 0.0, 1.0);
+    out.center = transformLocal(vec2<f32>(0.0), instance, times);
+    out.speed = length(instance.speed);
+    out.dopplerIntensity = uniforms.dopplerIntensity;
+    out.local = local;
+    out.opacity = instance.opacity * uniforms.startupFade * groupUniforms.tint.a;
+    out.tint = groupUniforms.tint.rgb * twinkle(instanceIdx);
+    out.edge = 0.0;
+    out.selected = select(0.0, 1.0, instanceIdx == uniforms.selectedIndex);
+    return out;
+}
+
+// 輪郭の各辺を四角形に展開し、画面空間で lineWidth ピクセルの太さにする
+@vertex
+fn vertexLine(
+    line: LineInput,
+    @builtin(instance_index) instanceIdx: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    return lineVertex(line, instanceIdx, instance, uniforms.lineWidth);
+}
+
+// outline_style の縁取り。塗りつぶしと同じ位置に outlineWidth ピクセルの太さで重ね、outlineColor で塗る
+@vertex
+fn vertexStroke(
+    line: LineInput,
+    @builtin(instance_index) instanceIdx: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var out = lineVertex(line, instanceIdx, instance, uniforms.outlineWidth);
+    out.tint = uniforms.outlineColor.rgb * twinkle(instanceIdx);
+    out.opacity *= uniforms.outlineColor.a;
+    return out;
+}
+
+// 輪郭の辺の四角形を width ピクセルの太さで画面に置く
+fn lineVertex(line: LineInput, instanceIdx: u32, instance: InstanceInput, width: f32) -> VertexOutput {
+    let times = instanceTimes(instanceIdx);
+    let start = toClip(transformLocal(line.start, instance, times));
+    let end = toClip(transformLocal(line.end, instance, times));
+
+    // ピクセル空間で辺に垂直な方向を求める
+    let halfResolution = uniforms.resolution * 0.5;
+    let dir = normalize((end - start) * halfResolution);
+    let normal = vec2<f32>(-dir.y, dir.x);
+
+    // アンチエイリアス用に1ピクセル分だけ余分に広げる
+    let halfWidth = width * 0.5 + 1.0;
+    let offset = normal * line.params.y * halfWidth / halfResolution;
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(mix(start, end, line.params.x) + offset, 0.0, 1.0);
+    out.speed = length(instance.speed);
+    out.dopplerIntensity = uniforms.dopplerIntensity;
+    out.local = mix(line.start, line.end, line.params.x);
+    out.center = transformLocal(vec2<f32>(0.0), instance, times);
+    out.opacity = instance.opacity * uniforms.startupFade * groupUniforms.tint.a;
+    out.tint = groupUniforms.tint.rgb * twinkle(instanceIdx);
+    out.edge = line.params.y;
+    out.selected = select(0.0, 1.0, instanceIdx == uniforms.selectedIndex);
+    return out;
+}
+
+// 近い星どうしを結ぶ線。position は CPU 側で画面端の振る舞いまで適用した、星空全体の回転前の座標
+@vertex
+fn vertexLink(
+    @location(0) position: vec2<f32>,
+    @location(1) color: vec4<f32>,
+) -> LinkOutput {
+    var out: LinkOutput;
+    out.position = vec4<f32>(toClip(rotateField(introPosition(position))), 0.0, 1.0);
+    out.color = vec4<f32>(color.rgb, color.a * uniforms.startupFade);
+    return out;
+}
+
+// debug_grid の目盛り線。position は星空の座標で、カメラと投影だけを掛ける (星空全体の回転や登場の動きは掛けない)
+@vertex
+fn vertexGrid(
+    @location(0) position: vec2<f32>,
+    @location(1) color: vec4<f32>,
+) -> LinkOutput {
+    var out: LinkOutput;
+    out.position = vec4<f32>(toClip(position), 0.0, 1.0);
+    out.color = color;
+    return out;
+}
 
-    return vec4<f32>(finalPos, 0.0, 1.0);
+fn starColor(in: VertexOutput) -> vec3<f32> {
+    var baseColor = vec3<f32>(1.0, 1.0, 0.0);
+    if uniforms.gradientStopCount > 0u {
+        baseColor = gradientColor(in.center);
+    }
+
+    // 遅い星は赤く、速い星は青く (疑似ドップラー効果)
+    let t = clamp(in.speed / MAX_SPEED, 0.0, 1.0);
+    let dopplerColor = mix(vec3<f32>(1.0, 0.3, 0.1), vec3<f32>(0.3, 0.5, 1.0), t);
+    let color = mix(baseColor, dopplerColor, in.dopplerIntensity) * in.tint;
+    // 選択中の星は水色に染めて目立たせる
+    let highlighted = mix(color, vec3<f32>(0.2, 0.9, 1.0), in.selected * 0.8);
+    return highlighted * hdrBoost(length(in.local));
+}
+
+// spatial_gradient の色を星の中心の位置 center から取る (src/gradient.rs の GradientAxis と揃えること)
+// 隣り合う位置の間を順に補間し、最初の位置より手前は最初の色、最後の位置より先は最後の色になる
+fn gradientColor(center: vec2<f32>) -> vec3<f32> {
+    var t: f32;
+    switch uniforms.gradientAxis {
+        case 1u: {
+            t = 0.5 - center.y * 0.5;
+        }
+        case 2u: {
+            t = min(length(center), 1.0);
+        }
+        default: {
+            t = center.x * 0.5 + 0.5;
+        }
+    }
+    var color = uniforms.gradientColors[0].rgb;
+    for (var i = 1u; i < uniforms.gradientStopCount; i++) {
+        let start = uniforms.gradientOffsets[i - 1u];
+        let end = uniforms.gradientOffsets[i];
+        let amount = clamp((t - start) / max(end - start, 0.00001), 0.0, 1.0);
+        color = mix(color, uniforms.gradientColors[i].rgb, amount);
+    }
+    return color;
+}
+
+// 星の中心ほど明るくする (HDR 出力時のみ。SDR では 1.0)
+fn hdrBoost(dist: f32) -> f32 {
+    if !hdrOutput {
+        return 1.0;
+    }
+    return mix(HDR_PEAK, 1.0, smoothstep(0.0, 0.5, dist));
+}
+
+fn outputColor(linearColor: vec3<f32>, alpha: f32) -> vec4<f32> {
+    var color = linearColor;
+    if encodeSrgb {
+        color = linearToSrgb(color);
+    }
+    if premultipliedAlpha {
+        return vec4<f32>(color * alpha, alpha);
+    }
+    return vec4<f32>(color, alpha);
+}
+
+// 線形の色を sRGB の伝達関数で符号化する
+fn linearToSrgb(color: vec3<f32>) -> vec3<f32> {
+    let clamped = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+    let low = clamped * 12.92;
+    let high = 1.055 * pow(clamped, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, clamped <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fragmentMain(in: VertexOutput) -> @location(0) vec4<f32> {
+    return outputColor(starColor(in), in.opacity);
 }
 
+// 四角形を中心からの距離でぼかし、柔らかく光る丸い点として描く
 @fragment
-fn fragmentMain() -> @location(0) vec4<f32> {
-    return vec4<f32>(1.0, 1.0, 0.0, 1.0);
-}
\ No newline at end of file
+fn fragmentPoint(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dist = length(in.local);
+    let alpha = 1.0 - smoothstep(0.2, 1.0, dist);
+    return outputColor(starColor(in), alpha * in.opacity);
+}
+
+// 線の縁を1ピクセル幅でぼかしてアンチエイリアスする
+@fragment
+fn fragmentLine(in: VertexOutput) -> @location(0) vec4<f32> {
+    return outputColor(starColor(in), lineCoverage(in.edge) * in.opacity);
+}
+
+// 縁取りはドップラー効果や選択の色に染めず、vertexStroke で渡した色のまま描く
+@fragment
+fn fragmentStroke(in: VertexOutput) -> @location(0) vec4<f32> {
+    return outputColor(in.tint, lineCoverage(in.edge) * in.opacity);
+}
+
+fn lineCoverage(edge: f32) -> f32 {
+    let pixel = fwidth(edge);
+    return 1.0 - smoothstep(1.0 - pixel, 1.0, abs(edge));
+}
+
+@fragment
+fn fragmentLink(in: LinkOutput) -> @location(0) vec4<f32> {
+    return outputColor(in.color.rgb, in.color.a);
+}
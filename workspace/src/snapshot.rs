@@ -0,0 +1,102 @@
+use crate::clock::PausableClock;
+use crate::error::{SceneError, WgpuStateError};
+use crate::instance::Instance;
+use crate::physics::PhysicsMode;
+use crate::scene::SceneConfig;
+use crate::state::WgpuState;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+// アニメーションを途中からそのまま再開するために必要な状態一式
+// SceneConfig に加えて、今の配置 (インスタンス) と時刻・選択中の星を持つ
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Snapshot {
+    // 取り出した時点のアニメーションの時刻 (秒)
+    pub time: f32,
+    // true の場合は set_time で時刻を固定していた。false なら復元後は time から壁時計で進み続ける
+    pub time_fixed: bool,
+    pub instances: Vec<Instance>,
+    pub physics_mode: PhysicsMode,
+    pub selected_instance: Option<usize>,
+    pub selected_time: f32,
+    // 選んだ星を止めた自転の時刻。この項目より前に保存したものでは None で、selected_time と同じとみなす
+    #[serde(default)]
+    pub selected_rotation_time: Option<f32>,
+    // freeze_translation / freeze_rotation の状態 (止めた時刻と止めていた長さ)
+    #[serde(default)]
+    pub translation_clock: PausableClock,
+    #[serde(default)]
+    pub rotation_clock: PausableClock,
+    pub scene: SceneConfig,
+}
+
+impl Snapshot {
+    pub fn to_ron(&self) -> Result<String, SceneError> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(SceneError::Serialize)
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, SceneError> {
+        ron::from_str(source).map_err(SceneError::Parse)
+    }
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), SceneError> {
+        std::fs::write(path, self.to_ron()?).map_err(SceneError::Io)
+    }
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, SceneError> {
+        Self::from_ron(&std::fs::read_to_string(path).map_err(SceneError::Io)?)
+    }
+}
+
+impl WgpuState<'_> {
+    // 今の状態を取り出す。インスタンスはバッファと同じ内容のCPU側のコピーから取るので、GPUからの読み戻しは要らない
+    // morph_to の補間中は、その時点の補間結果を配置として保存する (補間先は保存しない)
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            time: self.current_time(),
+            time_fixed: self.fixed_time.is_some(),
+            instances: self.instances.clone(),
+            physics_mode: self.state_config.physics_mode,
+            selected_instance: self.selected_instance,
+            selected_time: self.selected_time,
+            selected_rotation_time: Some(self.selected_rotation_time),
+            translation_clock: self.translation_clock,
+            rotation_clock: self.rotation_clock,
+            scene: self.scene(),
+        }
+    }
+
+    // snapshot を取り出した時点の状態に戻し、アニメーションをその時刻から続ける
+    // シーンかインスタンスの適用に失敗した場合はエラーを返す (それまでに適用した分は戻さない)
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), WgpuStateError> {
+        self.apply_scene(&snapshot.scene)?;
+        // インスタンスは保存時のモードで時刻 0 から計算した値なので、置き直さずにモードだけ合わせる
+        self.state_config.physics_mode = snapshot.physics_mode;
+        self.set_instances(&snapshot.instances)?;
+        self.selected_instance = snapshot.selected_instance.filter(|index| *index < self.instances.len());
+        self.selected_time = snapshot.selected_time;
+        self.selected_rotation_time = snapshot.selected_rotation_time.unwrap_or(snapshot.selected_time);
+        // 時計はアニメーションの時刻を基準にしているので、下で time を戻せば保存時と同じ値になる
+        self.translation_clock = snapshot.translation_clock;
+        self.rotation_clock = snapshot.rotation_clock;
+
+        if snapshot.time_fixed {
+            self.fixed_time = Some(snapshot.time);
+        } else {
+            // 次のフレームから time の続きを進める
+            let now = Instant::now();
+            self.animation_time = snapshot.time.max(0.0) as f64;
+            self.animated_at = Some(now);
+            self.fixed_time = None;
+            // フォーカスが外れている間に復元した場合は、復帰時に止まっていた時間として足されないようにする
+            if self.unfocused_at.is_some() {
+                self.unfocused_at = Some(now);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use crate::camera::Camera2D;
+use crate::config::WgpuStateConfig;
+use crate::instance::Instance;
+use crate::physics::PhysicsMode;
+use crate::state::WgpuState;
+use crate::vertex::Vertex;
+
+// GPU が全く使えない環境 (ソフトウェアの wgpu アダプタも無い CI など) 向けに、星空を CPU で RgbaImage に塗る
+// 塗りつぶしの星形だけを、シェーダと同じ変換 (移動・画面端の振る舞い・自転・中心のずれ・カメラ) で走査線ごとに塗る
+// 点描画・輪郭線・瞬き・引力・登場演出・星空全体の回転などは再現しない。速さは求めず、出力を必ず作れることを優先する
+pub struct SoftwareRenderer {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u16>,
+    pub instances: Vec<Instance>,
+    pub physics_mode: PhysicsMode,
+    pub camera: Camera2D,
+    pub background: wgpu::Color,
+}
+
+impl SoftwareRenderer {
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u16>, instances: Vec<Instance>) -> Self {
+        Self {
+            vertices,
+            indices,
+            instances,
+            physics_mode: PhysicsMode::default(),
+            camera: Camera2D::default(),
+            background: WgpuStateConfig::default().background.clear_color(),
+        }
+    }
+
+    // state_config の星形 (create_star_vertices と同じメッシュ)・画面端の振る舞い・背景色で描く
+    pub fn from_config(state_config: &WgpuStateConfig, instances: Vec<Instance>) -> Self {
+        let (vertices, indices) = WgpuState::create_star_vertices(state_config, state_config.subdivisions);
+        Self {
+            physics_mode: state_config.physics_mode,
+            background: state_config.background.clear_color(),
+            ..Self::new(vertices, indices, instances)
+        }
+    }
+
+    pub fn with_physics_mode(mut self, physics_mode: PhysicsMode) -> Self {
+        self.physics_mode = physics_mode;
+        self
+    }
+
+    pub fn with_camera(mut self, camera: Camera2D) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    pub fn with_background(mut self, background: wgpu::Color) -> Self {
+        self.background = background;
+        self
+    }
+
+    // time 秒時点の1フレームを width x height の画像に描く
+    // 星形のパイプラインと同じく (1, 1, 0) を opacity でアルファブレンドして重ね、裏向きの三角形は描かない
+    pub fn render(&self, width: u32, height: u32, time: f32) -> image::RgbaImage {
+        let (width, height) = (width.max(1), height.max(1));
+        let to_byte = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let background = [self.background.r, self.background.g, self.background.b, self.background.a].map(to_byte);
+        let mut image = image::RgbaImage::from_pixel(width, height, image::Rgba(background));
+
+        let mut camera = self.camera;
+        camera.set_aspect(width, height);
+        let view = camera.view_matrix();
+        // 星空の座標 → クリップ空間 → ピクセル (y は下向き)
+        let to_pixel = |[x, y]: [f32; 2]| {
+            let clip_x = view[0][0] * x + view[1][0] * y + view[3][0];
+            let clip_y = view[0][1] * x + view[1][1] * y + view[3][1];
+            [(clip_x + 1.0) * 0.5 * width as f32, (1.0 - clip_y) * 0.5 * height as f32]
+        };
+
+        // GPU と同じく layer の小さい順に、同じ layer の中では並び順に描く
+        let mut order: Vec<&Instance> = self.instances.iter().collect();
+        order.sort_by_key(|instance| instance.layer());
+        for instance in order {
+            let color = image::Rgba([255, 255, 0, to_byte(instance.opacity() as f64)]);
+            let corners: Vec<[f32; 2]> = self
+                .vertices
+                .iter()
+                .map(|vertex| to_pixel(instance.transform_local(vertex.position, self.physics_mode, time)))
+                .collect();
+            for triangle in self.indices.chunks_exact(3) {
+                let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| corners[index as usize]);
+                // ピクセルは y が下向きなので、クリップ空間で反時計回り (表) の三角形は符号付き面積が負になる
+                let area = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+                if area < 0.0 {
+                    fill_triangle(&mut image, [a, b, c], color);
+                }
+            }
+        }
+        image
+    }
+}
+
+// 三角形を走査線ごとに塗る。ピクセルの中心が三角形の内側にあれば塗り、
+// 辺の上は上側・左側だけを含めて、隣り合う三角形の境目を二重に塗らないようにする
+fn fill_triangle(image: &mut image::RgbaImage, corners: [[f32; 2]; 3], color: image::Rgba<u8>) {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let top = corners.iter().map(|corner| corner[1]).fold(f32::INFINITY, f32::min);
+    let bottom = corners.iter().map(|corner| corner[1]).fold(f32::NEG_INFINITY, f32::max);
+    let first_row = (top - 0.5).ceil().max(0.0) as u32;
+    let end_row = (bottom - 0.5).ceil().clamp(0.0, height) as u32;
+
+    for row in first_row..end_row {
+        let y = row as f32 + 0.5;
+        let mut span: Option<(f32, f32)> = None;
+        for (start, end) in [(corners[0], corners[1]), (corners[1], corners[2]), (corners[2], corners[0])] {
+            if (start[1] <= y) == (end[1] <= y) {
+                continue;
+            }
+            let x = start[0] + (y - start[1]) / (end[1] - start[1]) * (end[0] - start[0]);
+            span = Some(span.map_or((x, x), |(left, right)| (left.min(x), right.max(x))));
+        }
+        let Some((left, right)) = span else {
+            continue;
+        };
+        let first_column = (left - 0.5).ceil().max(0.0) as u32;
+        let end_column = (right - 0.5).ceil().clamp(0.0, width) as u32;
+        for column in first_column..end_column {
+            blend_over(image.get_pixel_mut(column, row), color);
+        }
+    }
+}
+
+// BlendState::ALPHA_BLENDING と同じく、color をその不透明度で下の色に重ねる
+fn blend_over(pixel: &mut image::Rgba<u8>, color: image::Rgba<u8>) {
+    let alpha = color[3] as f32 / 255.0;
+    for channel in 0..3 {
+        pixel[channel] = (color[channel] as f32 * alpha + pixel[channel] as f32 * (1.0 - alpha)).round() as u8;
+    }
+    pixel[3] = (color[3] as f32 + pixel[3] as f32 * (1.0 - alpha)).round() as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKGROUND: image::Rgba<u8> = image::Rgba([0, 0, 0, 255]);
+    const STAR: image::Rgba<u8> = image::Rgba([255, 255, 0, 255]);
+
+    fn opaque_black(renderer: SoftwareRenderer) -> SoftwareRenderer {
+        renderer.with_background(wgpu::Color::BLACK)
+    }
+
+    #[test]
+    fn star_covers_its_center_and_leaves_the_corners() {
+        let star = Instance::new([0.0, 0.0], 0.3, 0.0, [0.0, 0.0], 0.0);
+        let renderer = SoftwareRenderer::from_config(&WgpuStateConfig::default(), vec![star]);
+        let image = opaque_black(renderer).render(64, 64, 0.0);
+        assert_eq!(*image.get_pixel(32, 32), STAR);
+        for (x, y) in [(0, 0), (63, 0), (0, 63), (63, 63)] {
+            assert_eq!(*image.get_pixel(x, y), BACKGROUND, "corner ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn back_facing_triangles_are_skipped() {
+        let vertices = [[0.0, 1.0], [-1.0, -1.0], [1.0, -1.0]].map(|position| Vertex { position }).to_vec();
+        let star = Instance::new([0.0, 0.0], 0.5, 0.0, [0.0, 0.0], 0.0);
+        // 反時計回り (表) の並びなら中心が塗られ、逆順 (裏) なら何も塗られない
+        let front = opaque_black(SoftwareRenderer::new(vertices.clone(), vec![0, 1, 2], vec![star])).render(32, 32, 0.0);
+        assert_eq!(*front.get_pixel(16, 16), STAR);
+        let back = opaque_black(SoftwareRenderer::new(vertices, vec![0, 2, 1], vec![star])).render(32, 32, 0.0);
+        assert!(back.pixels().all(|pixel| *pixel == BACKGROUND));
+    }
+}
@@ -1,5 +1,10 @@
 use wgpu::util::DeviceExt;
-use winit::{event::WindowEvent, window::Window};
+use winit::{
+    dpi::PhysicalPosition,
+    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+    window::Window,
+};
 use core::f64;
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::Instant;
@@ -7,27 +12,36 @@ use std::time::Instant;
 #[cfg(target_arch = "wasm32")]
 use web_time::Instant;
 
+use crate::background::{Background, BackgroundMode};
+use crate::camera::Camera2D;
+use crate::config::WgpuStateConfig;
+use crate::error::WgpuStateError;
+use crate::gpu_timer::GpuTimer;
+use crate::morph::Morph;
+
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-struct FrameStats {
-    min_time: f64,
-    max_time: f64,
+pub struct FrameStats {
+    pub(crate) min_time: f64,
+    pub(crate) max_time: f64,
     total_time: f64,
-    frame_count: u64,
+    pub(crate) frame_count: u64,
+    dropped_frames: u64,
 }
 
 impl FrameStats {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             min_time: f64::MAX,
             max_time: 0.0,
             total_time: 0.0,
             frame_count: 0,
+            dropped_frames: 0,
         }
     }
 
-    fn update(&mut self, frame_time: f64) {
+    pub(crate) fn update(&mut self, frame_time: f64) {
         if frame_time < self.min_time {
             self.min_time = frame_time;
         }
@@ -38,7 +52,18 @@ impl FrameStats {
         self.frame_count += 1;
     }
 
-    fn average_time(&self) -> f64 {
+    // 前のフレームからの間隔が目標のフレーム時間の1.5倍を超えたら、1フレーム落ちたとみなす
+    pub(crate) fn record_interval(&mut self, interval: f64, target_frame_time: f64) {
+        if interval > target_frame_time * 1.5 {
+            self.dropped_frames += 1;
+        }
+    }
+
+    pub fn dropped_frame_count(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    pub(crate) fn average_time(&self) -> f64 {
         if self.frame_count == 0 {
             0.0
         } else {
@@ -46,14 +71,16 @@ impl FrameStats {
         }
     }
 
-    fn display_stats(&self) {
+    fn display_stats(&self, memory: &MemoryReport) {
         cfg_if::cfg_if! {
             if #[cfg(not(target_arch = "wasm32"))] {
                 println!("Min Time: {} sec", self.min_time);
                 println!("Max Time: {} sec", self.max_time);
                 println!("Average Time: {} sec", self.average_time());
                 println!("Total Frames: {}", self.frame_count);
-                print!("----------------------------------\n");
+                println!("Dropped Frames: {}", self.dropped_frames);
+                println!("Buffer Memory: {memory}");
+                println!("----------------------------------");
             } else {
                 let document = web_sys::window().unwrap().document().unwrap();
                 let stats_element = document.get_element_by_id("stats").unwrap();
@@ -64,58 +91,299 @@ impl FrameStats {
                         <tr><td>Max Time</td><td>{} sec</td></tr>
                         <tr><td>Average Time</td><td>{} sec</td></tr>
                         <tr><td>Total Frames</td><td>{}</td></tr>
+                        <tr><td>Dropped Frames</td><td>{}</td></tr>
+                        <tr><td>Buffer Memory</td><td>{}</td></tr>
                     </table>",
                     self.min_time,
                     self.max_time,
                     self.average_time(),
-                    self.frame_count
+                    self.frame_count,
+                    self.dropped_frames,
+                    memory
                 ));
             }
         }
     }
 }
 
+// このクレートが確保したGPUバッファの大きさ (バイト)
+// wgpu からは実際のVRAM使用量を取得できないので、自前の確保分だけを数える
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryReport {
+    pub vertex_bytes: u64,
+    pub index_bytes: u64,
+    // 交互に使う2枚のインスタンスバッファの合計
+    pub instance_bytes: u64,
+    pub uniform_bytes: u64,
+    // indirect が有効な場合の描画引数
+    pub indirect_bytes: u64,
+}
+
+impl MemoryReport {
+    pub fn total_bytes(&self) -> u64 {
+        self.vertex_bytes + self.index_bytes + self.instance_bytes + self.uniform_bytes + self.indirect_bytes
+    }
+}
+
+impl std::fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes (vertex {}, index {}, instance {}, uniform {}, indirect {})",
+            self.total_bytes(),
+            self.vertex_bytes,
+            self.index_bytes,
+            self.instance_bytes,
+            self.uniform_bytes,
+            self.indirect_bytes,
+        )
+    }
+}
+
+// 同じグループ・同じ詳細度の段階のインスタンスが並ぶ、インスタンスバッファ上の範囲
+pub(crate) struct InstanceBatch {
+    group: usize,
+    lod: usize,
+    instances: std::ops::Range<u32>,
+}
+
+// 1段階分の星形のメッシュが、共有の頂点・インデックスバッファのどこにあるか
+// WebGL2 は base_vertex に対応していないので、頂点バッファは段階ごとに範囲を切り出してバインドする
+pub(crate) struct MeshLod {
+    vertex_bytes: std::ops::Range<wgpu::BufferAddress>,
+    indices: std::ops::Range<u32>,
+}
+
+pub type ReadyCallback<'window> = Box<dyn FnOnce(&WgpuState<'window>) + 'window>;
+pub type RenderCallback<'window> = Box<dyn FnMut(&mut wgpu::RenderPass<'_>, &WgpuState<'window>) + 'window>;
+pub type UpdateCallback<'window> = Box<dyn FnMut(&mut [crate::instance::Instance], f32) + 'window>;
+
 pub struct WgpuState<'window> {
     pub instance: wgpu::Instance,
     pub surface: wgpu::Surface<'window>,
     pub device: Option<wgpu::Device>,
     pub queue: Option<wgpu::Queue>,
     pub config: Option<wgpu::SurfaceConfiguration>,
+    // サーフェスの再設定時に対応状況を問い合わせ直すために保持する
+    pub adapter: wgpu::Adapter,
+    // サーフェスが透過して合成されるかどうか (alpha_mode が Opaque 以外)
+    // false の場合、背景の透明なクリア色は不透明にして描く
+    pub transparent_surface: bool,
+    // サーフェスが Rgba16Float で、1.0 を超える明るさをそのまま出力しているかどうか
+    pub hdr_surface: bool,
+    pub adapter_info: wgpu::AdapterInfo,
+    // アダプタが対応している機能と上限 (デバイスに要求したものではなく、要求できる最大)
+    pub adapter_features: wgpu::Features,
+    pub adapter_limits: wgpu::Limits,
     pub size: winit::dpi::PhysicalSize<u32>,
+    // resize で受け取った最新の大きさ。サーフェスの再設定は次の render の始めに1回だけ行う
+    pub pending_resize: Option<winit::dpi::PhysicalSize<u32>>,
+    pub projection: [[f32; 4]; 4],
+    // 表示中心・拡大率・回転。projection はこれとモニタをまたぐ分担から作る
+    pub camera: Camera2D,
+    pub cursor_position: Option<PhysicalPosition<f64>>,
+    pub dragging: bool,
+    // false の場合は attractor_strength に関係なくカーソルの引力を止める (A キーで切り替え)
+    pub attractor_enabled: bool,
+    // カーソルの引力を最後に速度へ足したときの移動の時刻。引力が効いていない間は None
+    attractor_time: Option<f32>,
+    // 左ボタンを押してからカーソルが動いた距離 (ピクセル)。小さければドラッグではなくクリックとみなす
+    pub drag_distance: f64,
     pub render_pipeline: Option<wgpu::RenderPipeline>,
+    // link_distance が 0.0 より大きい場合だけ作る、近い星どうしを結ぶ線のパイプラインと、毎フレーム書き換える頂点バッファ
+    pub link_pipeline: Option<wgpu::RenderPipeline>,
+    pub link_buffer: Option<wgpu::Buffer>,
+    // 直前に描いたフレームの線の頂点数。link_buffer に収まらなければ次の render で作り直す
+    pub(crate) link_vertex_count: std::cell::Cell<usize>,
+    // debug_grid が有効な場合だけ作る、星の後ろに描く目盛り線
+    pub(crate) debug_grid: Option<crate::grid::DebugGrid>,
+    // outline_style が有効な場合だけ作る、塗りつぶしの上に重ねる縁取り
+    pub(crate) outline_stroke: Option<crate::outline::OutlineStroke>,
+    // internal_resolution が有効な場合だけ作る、星を描く固定の大きさのテクスチャと、それをサーフェスへ写すパイプライン
+    pub(crate) upscaler: Option<crate::upscale::Upscaler>,
     pub vertex_buffer: Option<wgpu::Buffer>,
+    // 形の補間先の頂点 (vertex_buffer と同じ並び)。補間しない場合は None で、代わりに vertex_buffer を読ませる
+    pub morph_buffer: Option<wgpu::Buffer>,
     pub num_vertices: Option<u32>,
     pub index_buffer: Option<wgpu::Buffer>,
     pub num_indices: Option<u32>,
+    // 詳細度ごとのメッシュと、グループ・段階の組ごとのインスタンスの範囲 (インスタンスは組ごとに並べ替えて書き込む)
+    pub(crate) lods: Vec<MeshLod>,
+    pub(crate) instance_batches: Vec<InstanceBatch>,
+    // instances の各要素がインスタンスバッファのどの位置に書き込まれているか
+    pub(crate) instance_slots: Vec<u32>,
+    // クリックで選んだ星 (instances の添字) と、その星の動きを止めた移動と自転の時刻 (秒)
+    pub selected_instance: Option<usize>,
+    pub selected_time: f32,
+    pub selected_rotation_time: f32,
+    // 星の移動と自転に使う時刻。freeze_translation / freeze_rotation で別々に止める
+    pub translation_clock: crate::clock::PausableClock,
+    pub rotation_clock: crate::clock::PausableClock,
     pub uniform_buffer: Option<wgpu::Buffer>,
     pub uniform_bind_group: Option<wgpu::BindGroup>,
-    pub instance_buffer: Option<wgpu::Buffer>,
+    // frames_in_flight が2以上のときに、uniform_buffer / uniform_bind_group に続けて順番に使う Uniforms のバッファ
+    pub(crate) uniform_ring: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+    // 次のフレームで使う Uniforms のバッファの番号。0 は uniform_buffer、1 以降は uniform_ring[frame_slot - 1]
+    pub(crate) frame_slot: usize,
+    // 各 Uniforms のバッファに最後に書き込んだ内容 (uniform_updates が OnChange のときに差分を求めるのに使う)
+    pub(crate) written_uniforms: Vec<std::cell::Cell<Option<crate::uniform::Uniforms>>>,
+    // 描画モードを切り替えたときにパイプラインを作り直すために保持する
+    pub uniform_bind_group_layout: wgpu::BindGroupLayout,
+    // グループごとの色合いと速さ。group_stride バイトおきに並び、動的オフセットで切り替える
+    pub group_buffer: Option<wgpu::Buffer>,
+    pub group_stride: u32,
+    pub shader: wgpu::ShaderModule,
+    pub custom_vertex_shader: Option<wgpu::ShaderModule>,
+    pub custom_fragment_shader: Option<wgpu::ShaderModule>,
+    // インスタンスバッファは2枚を交互に使う。描画は instance_read_index の側を読み、
+    // 更新はもう一方に書き込んで、次の render で入れ替える
+    pub instance_buffers: Option<[wgpu::Buffer; 2]>,
+    pub instance_read_index: usize,
+    // 書き込み側のバッファに、まだ描画に使っていない新しい内容があるかどうか
+    pub instances_pending: bool,
+    // 不透明度が1未満のインスタンスがあるか。あればパイプラインをアルファブレンドにする
+    pub(crate) translucent_instances: bool,
+    // indirect が有効で対応しているときだけ作る、instance_batches と同じ順の DrawIndexedIndirectArgs の列
+    // インスタンスの並びが変わるたびにCPUから書き直す。コンピュートパスで instance_count を書き換えて間引くこともできる
+    pub indirect_buffer: Option<wgpu::Buffer>,
+    pub(crate) indirect_draw: bool,
+    pub background: Option<Background>,
+    pub num_instances: u32,
+    // インスタンスバッファと同じ内容のCPU側のコピー (morph_to の補間元になる)
+    // set_instance_data で Instance 以外の型を描いている間と、set_instances_from_iter で流し込んだ間は空のまま
+    pub instances: Vec<crate::instance::Instance>,
+    // set_instance_data で渡された型の頂点属性の並び。None なら instances と同じ内容の Instance を描いている
+    // set_instances_from_iter で流し込んだ間は Instance の並びが入る
+    pub(crate) custom_instance_layout: Option<wgpu::VertexBufferLayout<'static>>,
+    pub(crate) morph: Option<Morph>,
     pub start_time: Option<Instant>,
     pub frame_stats: FrameStats,
+    pub gpu_timer: Option<GpuTimer>,
+    pub present_modes: Vec<wgpu::PresentMode>,
+    pub skip_frames: u32,
+    // get_current_texture が連続したフレームで Lost / Outdated を返した回数。諦めたときにも 0 に戻す
+    pub surface_error_count: u32,
+    // 最後に星空の配置を作った種 (初期の配置は種を使わないので 0)。範囲だけを変えて作り直すときに使う
+    field_seed: u64,
+    // デバイスが失われたときに、デバイスの失われたコールバックが理由のメッセージを書き込む
+    device_lost: std::sync::Arc<std::sync::Mutex<Option<String>>>,
+    pub field_rotation_speed: f32,
+    pub doppler_intensity: f32,
+    pub twinkle_speed: f32,
+    pub twinkle_amount: f32,
+    pub on_ready: Option<ReadyCallback<'window>>,
+    pub on_render: Option<RenderCallback<'window>>,
+    pub on_update: Option<UpdateCallback<'window>>,
+    // 星の上に重ねる設定の調整パネル (enable_tuning_panel で作る)
+    #[cfg(feature = "egui")]
+    pub tuning_panel: Option<crate::ui::TuningPanel>,
+    pub focused: bool,
+    pub unfocused_at: Option<Instant>,
+    // Suspended を受け取ってから Resumed までの間。サーフェスが使えないので描画しない
+    pub suspended: bool,
+    // request_redraw で要求した再描画がまだ届いていない間。RedrawRequested の処理の最初で false に戻す
+    pub redraw_requested: bool,
+    // screensaver が有効なときに最初に受け取ったカーソル位置。ここからの移動量で終了を判定する
+    pub screensaver_origin: Option<PhysicalPosition<f64>>,
+    // 前のフレームを描いた時刻と、モニタのリフレッシュレートから求めた目標のフレーム時間 (秒)
+    // 前のフレームの後に待機に入った場合は None で、次のフレームまでの間隔を測らない
+    pub last_frame_at: Option<Instant>,
+    // 前のフレームでアニメーションの時刻を進めた時刻。次のフレームまでの間隔を測るのに使う
+    pub animated_at: Option<Instant>,
+    // 毎フレーム smoothed_delta ずつ進めるアニメーションの時刻 (秒)
+    pub animation_time: f64,
+    // set_time で固定したアニメーションの時刻 (秒)。None の場合は animation_time を使う
+    pub fixed_time: Option<f32>,
+    pub target_frame_time: f64,
+    // フレーム間隔 (秒) の指数移動平均。最初のフレームまでは None
+    pub smoothed_delta: Option<f64>,
+    pub state_config: WgpuStateConfig,
     pub window: &'window Window,
 }
 
 impl<'window> WgpuState<'window> {
     pub const STAR_INSTANCE_COUNT: u32 = 1000;
+    // サーフェスの回復を諦めるまでに、1フレームに1回ずつ再設定を試すフレーム数
+    pub const MAX_SURFACE_RETRIES: u32 = 5;
+    // hdr が有効なときに選ぶ、1.0 を超える値を保持できるサーフェスのフォーマット
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    // 左ボタンを押してから離すまでにこれ以上動いたらクリックではなくドラッグとみなす (ピクセル)
+    pub const CLICK_SLOP: f64 = 4.0;
+    // screensaver が有効なとき、カーソルが最初の位置からこれ以上動いたら終了する (ピクセル)
+    // ウィンドウを開いた直後のわずかな揺れやマウスの微小な振動では終了しないようにする
+    pub const SCREENSAVER_SLOP: f64 = 8.0;
+    // クリックで生まれた星が画面中心から外向きに動く速さ
+    pub const SPAWN_SPEED: f32 = 0.2;
+    // マウスホイール1段で拡大・縮小する倍率 (ピクセル単位で届くタッチパッドは ZOOM_PIXELS_PER_STEP ピクセルを1段とする)
+    pub const ZOOM_STEP: f32 = 1.1;
+    pub const ZOOM_PIXELS_PER_STEP: f32 = 50.0;
+    // Q / E キーを1回押すごとにカメラを回す角度 (ラジアン)
+    pub const ROTATE_STEP: f32 = std::f32::consts::PI / 36.0;
+    // 初期化の途中でスレッドをブロックしないため、tokio などのランタイム上でそのまま .await できる
     pub async fn new(window: &'window Window) -> WgpuState<'window> {
+        Self::with_config(window, WgpuStateConfig::default())
+            .await
+            .expect("Failed to initialize the renderer")
+    }
+
+    pub async fn with_config(
+        window: &'window Window,
+        state_config: WgpuStateConfig,
+    ) -> Result<WgpuState<'window>, WgpuStateError> {
+        state_config.validate()?;
+        if crate::vertex::gcd(state_config.star_points, state_config.star_skip) != 1 {
+            log::warn!(
+                "Star {{{}/{}}} is not a single continuous figure; pick a skip coprime with the point count",
+                state_config.star_points,
+                state_config.star_skip,
+            );
+        }
         let size = window.inner_size();
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            #[cfg(target_arch = "wasm32")]
-            backends: wgpu::Backends::SECONDARY,
-            #[cfg(not(target_arch = "wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
-            ..Default::default()
-        });
-        let surface = instance.create_surface(window).unwrap();
+        // セーフモードではネイティブでも GL バックエンドを優先し、見つからなければ通常のバックエンドに戻す
+        // debug_validation では検証レイヤーを通すために Vulkan を優先する
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let candidate_backends = vec![wgpu::Backends::SECONDARY];
+            } else {
+                let candidate_backends = if state_config.debug_validation {
+                    vec![wgpu::Backends::VULKAN, wgpu::Backends::PRIMARY]
+                } else if state_config.safe_mode {
+                    vec![wgpu::Backends::GL, wgpu::Backends::PRIMARY]
+                } else {
+                    vec![wgpu::Backends::PRIMARY]
+                };
+            }
+        }
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
+        let mut found = None;
+        for backends in candidate_backends {
+            let flags = if state_config.debug_validation {
+                wgpu::InstanceFlags::VALIDATION | wgpu::InstanceFlags::DEBUG
+            } else {
+                wgpu::InstanceFlags::default()
+            };
+            let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+                backends,
+                flags,
+                ..Default::default()
+            });
+            let surface = instance
+                .create_surface(window)
+                .map_err(|error| WgpuStateError::CreateSurface(error.to_string()))?;
+            let adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: Some(&surface),
+                    force_fallback_adapter: false,
+                })
+                .await;
+            if let Some(adapter) = adapter {
+                found = Some((instance, surface, adapter));
+                break;
+            }
+            log::warn!("No adapter found for {backends:?}");
+        }
+        let (instance, surface, adapter) = found.ok_or(WgpuStateError::NoAdapter)?;
 
         cfg_if::cfg_if! {
             if #[cfg(not(target_arch = "wasm32"))] {
@@ -125,40 +393,110 @@ impl<'window> WgpuState<'window> {
                 web_sys::console::log_1(&JsValue::from_str(format!("Adapter: {:?}", adapter.get_info()).as_str()));
             }
         }
-        let device_result = adapter
+        // GPU時間の計測はアダプタが TIMESTAMP_QUERY に対応している場合だけ有効にする
+        let gpu_timing = state_config.gpu_timing
+            && !state_config.safe_mode
+            && adapter.features().contains(wgpu::Features::TIMESTAMP_QUERY);
+        if state_config.gpu_timing && !gpu_timing {
+            log::warn!("TIMESTAMP_QUERY is not supported by this adapter; GPU timing is disabled");
+        }
+
+        // 間接描画は、組ごとの first_instance が 0 とは限らないので INDIRECT_FIRST_INSTANCE も要る
+        let indirect = state_config.indirect
+            && !state_config.safe_mode
+            && adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION)
+            && adapter.features().contains(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+        if state_config.indirect && !indirect {
+            log::warn!("Indirect drawing with INDIRECT_FIRST_INSTANCE is not supported by this adapter; drawing directly");
+        }
+        let mut required_features = wgpu::Features::empty();
+        if gpu_timing {
+            required_features |= wgpu::Features::TIMESTAMP_QUERY;
+        }
+        if indirect {
+            required_features |= wgpu::Features::INDIRECT_FIRST_INSTANCE;
+        }
+
+        let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
+                    required_features,
+                    // セーフモードに限らず、WebGL2 でも動く控えめな上限を要求する
                     required_limits: wgpu::Limits::downlevel_webgl2_defaults(),
                     ..Default::default()
                 },
                 None,
             )
-            .await;
-
-        let (device, queue) = match device_result {
-            Ok(result) => result,
-            Err(err) => {
-                panic!("Device error: {:?}", err);
-            }
-        };
+            .await
+            .map_err(WgpuStateError::RequestDevice)?;
 
         device.on_uncaptured_error(Box::new(|error| {
             panic!("Device error: {:?}", error);
         }));
+        // 書き出しの途中で失われた場合に CaptureError::DeviceLost を返せるよう記録しておく
+        // デバイスを捨てたときやコールバックを差し替えたときにも呼ばれるので、それらは除く
+        let device_lost = std::sync::Arc::new(std::sync::Mutex::new(None));
+        let lost = device_lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            if matches!(reason, wgpu::DeviceLostReason::Unknown | wgpu::DeviceLostReason::Destroyed) {
+                log::error!("Device lost ({reason:?}): {message}");
+                if let Ok(mut lost) = lost.lock() {
+                    *lost = Some(message);
+                }
+            }
+        });
 
-        let surface_caps = surface.get_capabilities(&adapter);
+        let mut surface_caps = surface.get_capabilities(&adapter);
+        // 仮想ディスプレイなどでは対応状況が空で返ることがあるので、先頭の要素を取る前に確かめる
+        let missing = if surface_caps.formats.is_empty() {
+            Some("formats")
+        } else if surface_caps.present_modes.is_empty() {
+            Some("present modes")
+        } else if surface_caps.alpha_modes.is_empty() {
+            Some("alpha modes")
+        } else {
+            None
+        };
+        if let Some(missing) = missing {
+            return Err(WgpuStateError::IncompatibleSurface { adapter: adapter.get_info().name, missing });
+        }
+        // Fifo はすべての環境で対応が保証されているため、セーフモードではこれだけを使う
+        if state_config.safe_mode {
+            surface_caps.present_modes = vec![wgpu::PresentMode::Fifo];
+        }
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_caps.formats[0],
+            format: Self::choose_surface_format(&surface_caps.formats, &state_config),
             width: size.width.max(1),
             height: size.height.max(1),
             present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
+            alpha_mode: Self::choose_alpha_mode(&surface_caps.alpha_modes, &state_config),
             view_formats: vec![],
-            desired_maximum_frame_latency: 1,
+            desired_maximum_frame_latency: state_config.frame_latency,
         };
         surface.configure(&device, &config);
+        let transparent_surface = config.alpha_mode != wgpu::CompositeAlphaMode::Opaque;
+        if state_config.transparent_window && !transparent_surface {
+            log::warn!("Surface does not support transparency; clearing to an opaque background instead");
+        }
+        if state_config.store_op == wgpu::StoreOp::Discard {
+            log::warn!("store_op is Discard; frames presented to the surface will have undefined contents");
+        }
+        let hdr_surface = config.format == Self::HDR_FORMAT;
+        if state_config.hdr && !hdr_surface {
+            log::warn!("Surface does not support {:?}; rendering in SDR instead", Self::HDR_FORMAT);
+        }
+        log::info!(
+            "Effective configuration: backend {:?}, format {:?}, present mode {:?}, frame latency {}",
+            adapter.get_info().backend,
+            config.format,
+            config.present_mode,
+            config.desired_maximum_frame_latency,
+        );
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
@@ -170,148 +508,2507 @@ impl<'window> WgpuState<'window> {
 
         let uniform_buffer = crate::uniform::Uniforms::get_uniform_buffer(&device);
 
+        let (group_buffer, group_stride) =
+            crate::group::create_group_buffer(&device, &crate::group::effective_groups(&state_config.groups));
+
         let (uniform_bind_group_layout, uniform_bind_group) =
-            crate::uniform::Uniforms::get_uniform_bind_groups(&device, &uniform_buffer);
+            crate::uniform::Uniforms::get_uniform_bind_groups(&device, &uniform_buffer, &group_buffer);
+        let uniform_ring = (1..state_config.frames_in_flight)
+            .map(|_| {
+                let buffer = crate::uniform::Uniforms::get_uniform_buffer(&device);
+                let bind_group = crate::uniform::Uniforms::create_uniform_bind_group(
+                    &device,
+                    &uniform_bind_group_layout,
+                    &buffer,
+                    &group_buffer,
+                );
+                (buffer, bind_group)
+            })
+            .collect::<Vec<_>>();
 
-        let render_pipeline =
-            crate::uniform::Uniforms::get_render_setting(&device, &uniform_bind_group_layout, &shader, &config);
+        let custom_vertex_shader = match &state_config.vertex_shader {
+            Some(source) => Some(Self::create_custom_shader(&device, source, state_config.vertex_entry_point(), naga::ShaderStage::Vertex).await?),
+            None => None,
+        };
+        let custom_fragment_shader = match &state_config.fragment_shader {
+            Some(source) => Some(Self::create_custom_shader(&device, source, state_config.fragment_entry_point(), naga::ShaderStage::Fragment).await?),
+            None => None,
+        };
 
-        let (vertices, indices) = Self::create_star_vertices();
-        let vertex_buffer = crate::vertex::Vertex::get_vertex_buffer(&device, &vertices);
+        let mut rng = crate::instance::field_rng();
+        let mut instances =
+            crate::instance::create_star_instances_with_params(
+                &mut rng,
+                &state_config.instance_params,
+                state_config.spin_bias,
+                state_config.spawn_margin,
+            );
+        crate::instance::jitter_star_centers(&mut instances, &mut rng, state_config.center_jitter);
+        let translucent_instances = Self::has_translucent(&instances);
 
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&indices),
-            usage: wgpu::BufferUsages::INDEX,
+        // カスタムシェーダのバインディングや入出力の不一致はパイプライン作成時に検出される
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let render_pipeline =
+            crate::uniform::Uniforms::get_render_setting_blended(
+                &device,
+                &uniform_bind_group_layout,
+                custom_vertex_shader.as_ref().unwrap_or(&shader),
+                custom_fragment_shader.as_ref().unwrap_or(&shader),
+                &config,
+                &state_config,
+                crate::uniform::InstanceLayout {
+                    buffer_layout: crate::instance::get_instance_buffer_layout(),
+                    translucent: translucent_instances,
+                },
+            );
+        let link_pipeline = (state_config.link_distance > 0.0).then(|| {
+            crate::link::create_link_pipeline(&device, &uniform_bind_group_layout, &shader, &config, &state_config)
+        });
+        let debug_grid = state_config.debug_grid.then(|| {
+            crate::grid::DebugGrid::new(&device, &uniform_bind_group_layout, &shader, &config, &state_config)
+        });
+        let outline_stroke = state_config.outline_stroke_enabled().then(|| {
+            crate::outline::OutlineStroke::new(&device, &uniform_bind_group_layout, &shader, &config, &state_config)
         });
+        let upscaler = state_config
+            .internal_resolution
+            .map(|size| crate::upscale::Upscaler::new(&device, config.format, size, state_config.upscale_filter));
+        if let Some(error) = device.pop_error_scope().await {
+            return Err(WgpuStateError::PipelineCreation(error.to_string()));
+        }
 
-        let instances = crate::instance::create_star_instances();
-        let instance_buffer = crate::instance::get_instance_buffer(&device, &instances);
-        let mut stats = FrameStats::new();
+        let (vertex_buffer, num_vertices, index_buffer, num_indices, lods, morph_buffer) =
+            Self::create_geometry(&device, &state_config);
 
-        Self {
+        let (ordered_instances, instance_batches, instance_slots) = Self::batch_instances(&state_config, &instances);
+        let instance_buffers = [
+            crate::instance::get_instance_buffer(&device, &ordered_instances),
+            crate::instance::get_instance_buffer(&device, &ordered_instances),
+        ];
+        let background = Background::new(&device, config.format, state_config.background);
+        let stats = FrameStats::new();
+        let gpu_timer = gpu_timing.then(|| GpuTimer::new(&device, &queue));
+
+        let mut state = Self {
             instance,
             surface,
             device: Some(device),
             queue: Some(queue),
             config: Some(config),
+            adapter_info: adapter.get_info(),
+            adapter_features: adapter.features(),
+            adapter_limits: adapter.limits(),
+            adapter,
+            transparent_surface,
+            hdr_surface,
             size,
+            pending_resize: None,
+            projection: crate::uniform::IDENTITY_MATRIX,
+            camera: Camera2D::default(),
+            cursor_position: None,
+            dragging: false,
+            attractor_enabled: true,
+            attractor_time: None,
+            drag_distance: 0.0,
             render_pipeline: Some(render_pipeline),
+            link_pipeline,
+            link_buffer: None,
+            link_vertex_count: std::cell::Cell::new(0),
+            debug_grid,
+            outline_stroke,
+            upscaler,
             vertex_buffer: Some(vertex_buffer),
-            num_vertices: Some(vertices.len() as u32),
+            morph_buffer,
+            num_vertices: Some(num_vertices),
             index_buffer: Some(index_buffer),
-            num_indices: Some(indices.len() as u32),
+            num_indices: Some(num_indices),
+            lods,
+            instance_batches,
+            instance_slots,
+            selected_instance: None,
+            selected_time: 0.0,
+            selected_rotation_time: 0.0,
+            translation_clock: Default::default(),
+            rotation_clock: Default::default(),
             uniform_buffer: Some(uniform_buffer),
             uniform_bind_group: Some(uniform_bind_group),
-            instance_buffer: Some(instance_buffer),
+            written_uniforms: (0..=uniform_ring.len()).map(|_| std::cell::Cell::new(None)).collect(),
+            uniform_ring,
+            frame_slot: 0,
+            uniform_bind_group_layout,
+            group_buffer: Some(group_buffer),
+            group_stride,
+            shader,
+            custom_vertex_shader,
+            custom_fragment_shader,
+            instance_buffers: Some(instance_buffers),
+            instance_read_index: 0,
+            instances_pending: false,
+            translucent_instances,
+            custom_instance_layout: None,
+            indirect_buffer: None,
+            indirect_draw: indirect,
+            num_instances: instances.len() as u32,
+            instances,
+            morph: None,
+            background: Some(background),
             start_time: Some(Instant::now()),
             frame_stats: stats,
-            window: window,
+            gpu_timer,
+            present_modes: surface_caps.present_modes,
+            skip_frames: 0,
+            surface_error_count: 0,
+            field_seed: 0,
+            device_lost,
+            field_rotation_speed: 0.0,
+            doppler_intensity: 0.0,
+            twinkle_speed: 1.0,
+            twinkle_amount: 0.0,
+            on_ready: None,
+            on_render: None,
+            on_update: None,
+            #[cfg(feature = "egui")]
+            tuning_panel: None,
+            focused: true,
+            unfocused_at: None,
+            suspended: false,
+            redraw_requested: false,
+            screensaver_origin: None,
+            last_frame_at: None,
+            animated_at: Some(Instant::now()),
+            animation_time: 0.0,
+            fixed_time: state_config.static_time,
+            target_frame_time: window
+                .current_monitor()
+                .and_then(|monitor| monitor.refresh_rate_millihertz())
+                .map_or(1.0 / 60.0, |millihertz| 1000.0 / millihertz as f64),
+            smoothed_delta: None,
+            state_config,
+            window,
+        };
+        state.update_projection();
+        state.write_indirect_args();
+        log::info!("Buffer memory: {}", state.memory_report());
+        if state.state_config.warm_up_pipeline {
+            state.warm_up();
+        }
+        Ok(state)
+    }
+
+    // hdr が有効で対応していれば HDR_FORMAT、それ以外はアダプタの先頭のフォーマットを使う
+    fn choose_surface_format(formats: &[wgpu::TextureFormat], state_config: &WgpuStateConfig) -> wgpu::TextureFormat {
+        if state_config.hdr && formats.contains(&Self::HDR_FORMAT) {
+            return Self::HDR_FORMAT;
+        }
+        formats[0]
+    }
+
+    // premultiplied_alpha なら PreMultiplied、透過ウィンドウなら Opaque 以外を優先し、なければアダプタの先頭の値を使う
+    fn choose_alpha_mode(
+        alpha_modes: &[wgpu::CompositeAlphaMode],
+        state_config: &WgpuStateConfig,
+    ) -> wgpu::CompositeAlphaMode {
+        if state_config.premultiplied_alpha && alpha_modes.contains(&wgpu::CompositeAlphaMode::PreMultiplied) {
+            return wgpu::CompositeAlphaMode::PreMultiplied;
+        }
+        if state_config.transparent_window {
+            if let Some(mode) = alpha_modes.iter().find(|mode| **mode != wgpu::CompositeAlphaMode::Opaque) {
+                return *mode;
+            }
         }
+        alpha_modes[0]
     }
 
+    async fn create_custom_shader(
+        device: &wgpu::Device,
+        source: &str,
+        entry_point: &str,
+        stage: naga::ShaderStage,
+    ) -> Result<wgpu::ShaderModule, WgpuStateError> {
+        Self::check_entry_point(source, entry_point, stage)?;
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Custom Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        match device.pop_error_scope().await {
+            Some(error) => Err(WgpuStateError::ShaderCompilation(error.to_string())),
+            None => Ok(module),
+        }
+    }
+
+    // source が stage の段のエントリポイント entry_point を宣言しているか
+    // コメントや別の段の関数に惑わされないよう、パースしたモジュールのエントリポイントから探す
+    fn check_entry_point(source: &str, entry_point: &str, stage: naga::ShaderStage) -> Result<(), WgpuStateError> {
+        let parsed = naga::front::wgsl::parse_str(source)
+            .map_err(|error| WgpuStateError::ShaderCompilation(error.emit_to_string(source)))?;
+        if !parsed.entry_points.iter().any(|entry| entry.name == entry_point && entry.stage == stage) {
+            return Err(WgpuStateError::MissingEntryPoint(entry_point.to_string()));
+        }
+        Ok(())
+    }
+
+    // 呼び出し元のスレッドを初期化完了までブロックする
+    // 非同期ランタイム (tokio など) の中からは呼ばず、new / with_config を .await すること
+    #[cfg(feature = "blocking")]
     pub fn native_new(window: &'window Window) -> WgpuState<'window> {
         pollster::block_on(Self::new(window))
     }
 
+    // ウィンドウの端をドラッグしている間は Resized が連続して届くので、ここでは大きさを記録するだけにする
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            if let Some(config) = &mut self.config {
-                config.width = new_size.width;
-                config.height = new_size.height;
+            self.pending_resize = Some(new_size);
+        }
+    }
+
+    // 記録しておいた最新の大きさでサーフェスを再設定する
+    fn apply_pending_resize(&mut self) {
+        let Some(new_size) = self.pending_resize.take() else {
+            return;
+        };
+        if new_size == self.size {
+            return;
+        }
+        self.size = new_size;
+        self.update_projection();
+        if let Some(config) = &mut self.config {
+            config.width = new_size.width;
+            config.height = new_size.height;
+        }
+        self.surface.configure(self.device.as_ref().unwrap(), self.config.as_ref().unwrap());
+    }
+
+    // Lost / Outdated からの回復。1回目は今の設定のまま再設定し、
+    // それでも続く場合はモニタの変更などで PresentMode が使えなくなったとみなして、対応状況を問い合わせ直して Fifo に戻す
+    fn recover_surface(&mut self) {
+        let (Some(device), Some(config)) = (&self.device, &mut self.config) else {
+            return;
+        };
+        if self.surface_error_count > 1 {
+            let surface_caps = self.surface.get_capabilities(&self.adapter);
+            if !surface_caps.formats.contains(&config.format) {
+                log::warn!("Surface format {:?} is no longer reported as supported", config.format);
+            }
+            log::warn!("Reconfiguring the surface with Fifo (was {:?})", config.present_mode);
+            config.present_mode = wgpu::PresentMode::Fifo;
+            self.present_modes = surface_caps.present_modes;
+        }
+        config.width = self.size.width.max(1);
+        config.height = self.size.height.max(1);
+        self.surface.configure(device, config);
+    }
+
+    // screensaver が有効なとき、event がスクリーンセーバーを終了させる入力かどうか
+    // キーやマウスボタンを押したとき、ホイールを回したとき、カーソルが SCREENSAVER_SLOP 以上動いたときに true
+    pub fn screensaver_wakes(&mut self, event: &WindowEvent) -> bool {
+        if !self.state_config.screensaver {
+            return false;
+        }
+        match event {
+            WindowEvent::KeyboardInput { event: KeyEvent { state: ElementState::Pressed, .. }, .. }
+            | WindowEvent::MouseInput { state: ElementState::Pressed, .. }
+            | WindowEvent::MouseWheel { .. } => true,
+            WindowEvent::CursorMoved { position, .. } => {
+                // 最初に届いた位置は、カーソルが動いていなくても送られてくるので基準として覚えるだけにする
+                let origin = *self.screensaver_origin.get_or_insert(*position);
+                (position.x - origin.x).hypot(position.y - origin.y) >= Self::SCREENSAVER_SLOP
             }
-            self.surface.configure(&self.device.as_ref().unwrap(), &self.config.as_ref().unwrap());
+            _ => false,
         }
     }
 
-    #[allow(unused_variables)]
     pub fn input(&mut self, event: &WindowEvent) -> bool {
-        false
+        #[cfg(feature = "egui")]
+        if let Some(panel) = &mut self.tuning_panel {
+            if panel.on_window_event(event, self.window.scale_factor() as f32) {
+                return true;
+            }
+        }
+        match event {
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::KeyV),
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => {
+                self.toggle_present_mode();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::KeyN),
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => {
+                // 起動からの経過時間 (ナノ秒) を種にするので、押すたびに別の配置になる
+                let seed = self.start_time.map_or(0, |start_time| start_time.elapsed().as_nanos() as u64);
+                self.regenerate_field(seed);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::KeyA),
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => {
+                self.attractor_enabled = !self.attractor_enabled;
+                log::info!("Cursor attractor {}", if self.attractor_enabled { "enabled" } else { "disabled" });
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::KeyP),
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => {
+                self.cycle_physics_mode();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(key @ (KeyCode::KeyQ | KeyCode::KeyE)),
+                    ..
+                },
+                ..
+            } => {
+                // Q で反時計回り、E で時計回りにカメラを回す (押し続ければ回り続ける)
+                let angle = if *key == KeyCode::KeyQ { Self::ROTATE_STEP } else { -Self::ROTATE_STEP };
+                self.camera.rotate(angle);
+                self.update_projection();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(key),
+                    ..
+                },
+                ..
+            } if self.nudge_instance_params(*key) => true,
+            WindowEvent::MouseWheel { delta, .. } => {
+                let steps = match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => *lines,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / Self::ZOOM_PIXELS_PER_STEP,
+                };
+                // カーソルの下の点を動かさずに拡大する。カーソルがウィンドウの外なら画面中心で拡大する
+                let clip = self.cursor_position.map_or([0.0, 0.0], |position| self.window_to_clip(position));
+                self.camera.zoom_at(clip, Self::ZOOM_STEP.powf(steps));
+                self.update_projection();
+                true
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.dragging = *state == ElementState::Pressed;
+                if self.dragging {
+                    self.drag_distance = 0.0;
+                } else if self.drag_distance < Self::CLICK_SLOP {
+                    // 星の上をクリックしたらその星を選び、何もないところなら新しい星を生む
+                    if let Some(position) = self.cursor_position {
+                        if !self.pick_star_at(position) {
+                            self.spawn_star_at(position);
+                        }
+                    }
+                }
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let previous = self.cursor_position.replace(*position);
+                match previous {
+                    Some(previous) if self.dragging => {
+                        self.drag_distance += (position.x - previous.x).hypot(position.y - previous.y);
+                        // ドラッグ量をズームを考慮して星空の座標に変換し、カーソルに1:1で追従させる
+                        let [width, height] = self.canvas_size();
+                        let scale = crate::uniform::world_units_per_pixel(width, height, self.camera.zoom);
+                        self.camera.pan([
+                            -(position.x - previous.x) as f32 * scale,
+                            (position.y - previous.y) as f32 * scale,
+                        ]);
+                        self.update_projection();
+                        true
+                    }
+                    // 引力が効いていれば、カーソルの移動で星の加速する向きが変わる
+                    _ => self.attractor_active(),
+                }
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor_position = None;
+                self.attractor_active()
+            }
+            _ => false,
+        }
     }
 
-    pub fn update(&mut self) {}
+    fn update_projection(&mut self) {
+        let [width, height] = self.canvas_size();
+        self.camera.set_aspect(width, height);
+        let slice = crate::uniform::viewport_slice(
+            [width, height],
+            self.state_config.viewport_offset,
+            self.render_size(),
+        );
+        self.projection = crate::uniform::multiply_matrices(slice, self.camera.view_matrix());
+    }
 
-    fn create_star_vertices() -> (Vec<crate::vertex::Vertex>, Vec<u16>) {
-        let num_points = 5;
-        let vertices = crate::vertex::Vertex::get_vertices();
+    pub fn set_camera(&mut self, camera: Camera2D) {
+        self.camera = camera;
+        self.update_projection();
+    }
 
-        let mut indices = Vec::new();
+    // 星空を映す仮想キャンバス全体の大きさ (モニタをまたがない場合はウィンドウの大きさ)
+    fn canvas_size(&self) -> [u32; 2] {
+        self.state_config
+            .viewport_total
+            .unwrap_or(self.render_size())
+    }
 
-        for i in 0..num_points {
-            let current = 1 + i;
-            let next = 1 + ((i + 2) % num_points);
+    // aspect_lock の場合に星を描く、サーフェスの中央に収まる最大の矩形 (x, y, 幅, 高さ のピクセル)
+    pub fn letterbox(&self) -> Option<[u32; 4]> {
+        let aspect = self.state_config.aspect_lock?;
+        let (width, height) = (self.size.width.max(1), self.size.height.max(1));
+        let (inner_width, inner_height) = if width as f32 / height as f32 > aspect {
+            ((height as f32 * aspect).round() as u32, height)
+        } else {
+            (width, (width as f32 / aspect).round() as u32)
+        };
+        let (inner_width, inner_height) = (inner_width.clamp(1, width), inner_height.clamp(1, height));
+        Some([(width - inner_width) / 2, (height - inner_height) / 2, inner_width, inner_height])
+    }
 
-            indices.extend_from_slice(&[0, current as u16, next as u16]);
+    // 星を描く範囲の大きさ (ピクセル)。aspect_lock が無ければサーフェス全体
+    fn render_size(&self) -> [u32; 2] {
+        match self.letterbox() {
+            Some([_, _, width, height]) => [width, height],
+            None => [self.size.width, self.size.height],
         }
+    }
 
-        (vertices, indices)
+    // 星のパスが実際に描くテクスチャのピクセル数。internal_resolution があればその大きさで、無ければ render_size と同じ
+    // 線の太さや radius_pixels はこのピクセル数で数える。見た目の縦横比は render_size の方で決まる
+    fn target_size(&self) -> [u32; 2] {
+        self.upscaler.as_ref().map_or(self.render_size(), |upscaler| upscaler.size())
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let render_before_time = Instant::now();
-        let output = self.surface.get_current_texture().unwrap();
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+    // 星を内部解像度のテクスチャに描いて引き伸ばす (None でサーフェスへ直接描く)
+    // ウィンドウの大きさを変えてもテクスチャはそのままで、大きさかフィルタが変わったときだけ作り直す
+    pub fn set_internal_resolution(&mut self, internal_resolution: Option<[u32; 2]>, filter: wgpu::FilterMode) {
+        if internal_resolution.is_some_and(|resolution| resolution.contains(&0)) {
+            log::warn!("internal_resolution {internal_resolution:?} must be at least 1x1; ignoring");
+            return;
+        }
+        let unchanged = (self.state_config.internal_resolution, self.state_config.upscale_filter)
+            == (internal_resolution, filter)
+            && self.upscaler.is_some() == internal_resolution.is_some();
+        self.state_config.internal_resolution = internal_resolution;
+        self.state_config.upscale_filter = filter;
+        if unchanged {
+            return;
+        }
+        if let (Some(device), Some(config)) = (&self.device, &self.config) {
+            self.upscaler = internal_resolution
+                .map(|size| crate::upscale::Upscaler::new(device, config.format, size, filter));
+        }
+    }
 
-        let now = Instant::now();
-        let time = now.duration_since(self.start_time.clone().unwrap()).as_secs_f32();
-        if let (
-            Some(queue),
-            Some(device),
-            Some(uniform_buffer),
-            Some(render_pipeline),
-            Some(uniform_bind_group),
-            Some(vertex_buffer),
-            Some(index_buffer),
-            Some(instance_buffer),
-            Some(num_indices),
-        ) = (
-            &mut self.queue,
-            &self.device,
-            &self.uniform_buffer,
-            &self.render_pipeline,
-            &self.uniform_bind_group,
-            &self.vertex_buffer,
-            &self.index_buffer,
-            &self.instance_buffer,
-            self.num_indices,
-        ) {
-            queue.write_buffer(uniform_buffer, 0, bytemuck::cast_slice(&[crate::uniform::Uniforms::new(time)]));
-            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    // アダプタが対応しているPresentModeを順番に切り替える (Fifo <-> Immediate/Mailbox の比較用)
+    pub fn toggle_present_mode(&mut self) {
+        if self.present_modes.is_empty() {
+            return;
+        }
+        if let Some(config) = &self.config {
+            let current = self
+                .present_modes
+                .iter()
+                .position(|mode| *mode == config.present_mode)
+                .unwrap_or(0);
+            let next = self.present_modes[(current + 1) % self.present_modes.len()];
+            self.set_present_mode(next);
+        }
+    }
 
-            {
-                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: None,
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
-                            store: wgpu::StoreOp::Store,
-                        },
-                    })],
-                    depth_stencil_attachment: None,
-                    timestamp_writes: None,
-                    occlusion_query_set: None,
-                });
+    // アダプタが対応していない PresentMode は無視して false を返す
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) -> bool {
+        if !self.present_modes.contains(&present_mode) {
+            log::warn!("present_mode {present_mode:?} is not supported by this surface");
+            return false;
+        }
+        if let (Some(device), Some(config)) = (&self.device, &mut self.config) {
+            config.present_mode = present_mode;
+            log::info!("present_mode: {:?}", config.present_mode);
+            self.surface.configure(device, config);
+            // 再設定直後はフレームがヒッチしやすいので1フレーム描画を飛ばす
+            self.skip_frames = 1;
+        }
+        true
+    }
+
+    // set_instances で受け付けるインスタンス数の上限
+    // 設定値があってもデバイスのバッファサイズ上限 (と描画時のインスタンス数の u32) を超えることはない
+    pub fn max_instances(&self) -> usize {
+        let device_max = self.device.as_ref().map_or(u32::MAX as usize, |device| {
+            let max = device.limits().max_buffer_size / std::mem::size_of::<crate::instance::Instance>() as u64;
+            usize::try_from(max.min(u32::MAX as u64)).unwrap_or(usize::MAX)
+        });
+        self.state_config.max_instances.map_or(device_max, |max| max.min(device_max))
+    }
+
+    // インスタンスを差し替えてインスタンスバッファを更新する
+    // 上限を超える場合はバッファを確保せずにエラーを返す。補間中の morph_to は打ち切る
+    pub fn set_instances(&mut self, instances: &[crate::instance::Instance]) -> Result<(), WgpuStateError> {
+        self.check_instance_count(instances.len())?;
+        self.store_instances(instances.to_vec());
+        Ok(())
+    }
+
+    // 数を確かめ終えたインスタンスを書き込み、そのまま CPU 側の控えにする
+    fn store_instances(&mut self, instances: Vec<crate::instance::Instance>) {
+        self.morph = None;
+        self.use_builtin_instances();
+        self.upload_instances(&instances);
+        self.instances = instances;
+    }
+
+    // set_instances と同じだが、Vec<Instance> を用意せずにイテレータから count 個を取り出しながら、
+    // 作成時にマップしたインスタンスバッファへ INSTANCE_STREAM_CHUNK 個ずつ書き込む
+    // バッファの大きさを先に決めるため count は前もって分かっている必要がある (count 個より多い分は読まない)
+    // CPU 側の控え (instances) も持たないので、set_instance_data と同じく選択、衝突、物理モードの切り替え、
+    // 画面外の間引き、縁取りは行わない。set_instances などで渡し直すと元に戻る
+    // 星は渡した順に描くので、layer は小さい順に並べておく。layer が戻る星が来たら、そこまでに書いた分を読み戻して
+    // 残りと合わせて set_instances と同じ経路で書き直す
+    // グループや詳細度の段階が複数ある場合は並べ替えが要るので、はじめから集めて set_instances と同じ経路で書き込む
+    pub fn set_instances_from_iter(
+        &mut self,
+        iter: impl Iterator<Item = crate::instance::Instance>,
+        count: usize,
+    ) -> Result<(), WgpuStateError> {
+        self.check_instance_count(count)?;
+        let mut iter = iter.take(count);
+        let single_batch = self.state_config.lod_count() * self.state_config.groups.len().max(1) == 1;
+        let Some(device) = self.device.as_ref().filter(|_| count > 0 && single_batch) else {
+            let instances: Vec<_> = iter.collect();
+            if instances.len() < count {
+                return Err(WgpuStateError::InstanceCountMismatch { expected: count, actual: instances.len() });
+            }
+            self.store_instances(instances);
+            return Ok(());
+        };
 
-                render_pass.set_pipeline(render_pipeline);
-                render_pass.set_bind_group(0, uniform_bind_group, &[]);
-                render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-                render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
-                render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                render_pass.draw_indexed(0..num_indices, 0, 0..Self::STAR_INSTANCE_COUNT);
+        let instance_size = std::mem::size_of::<crate::instance::Instance>();
+        let buffers = [0, 1].map(|_| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Streamed Instance Buffer"),
+                size: (count * instance_size) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: true,
+            })
+        });
+        let mut translucent = false;
+        let mut written = 0;
+        // layer が戻った星から先を、読み戻した分と合わせて並べ替える
+        let mut unsorted = None;
+        {
+            let mut mapped = buffers.each_ref().map(|buffer| buffer.slice(..).get_mapped_range_mut());
+            let mut chunk = Vec::with_capacity(INSTANCE_STREAM_CHUNK.min(count));
+            let mut layer = 0;
+            for instance in iter.by_ref() {
+                if instance.layer() < layer {
+                    let mut instances: Vec<crate::instance::Instance> =
+                        bytemuck::pod_collect_to_vec(&mapped[0][..written * instance_size]);
+                    instances.append(&mut chunk);
+                    instances.push(instance);
+                    unsorted = Some(instances);
+                    break;
+                }
+                layer = instance.layer();
+                translucent |= instance.opacity() < 1.0;
+                chunk.push(instance);
+                if chunk.len() == INSTANCE_STREAM_CHUNK {
+                    written = Self::write_instance_chunk(&mut mapped, written, &chunk);
+                    chunk.clear();
+                }
             }
-            queue.submit(std::iter::once(encoder.finish()));
+            if unsorted.is_none() {
+                written = Self::write_instance_chunk(&mut mapped, written, &chunk);
+            }
+        }
+        buffers.iter().for_each(wgpu::Buffer::unmap);
+        if let Some(mut instances) = unsorted {
+            instances.extend(iter);
+            if instances.len() < count {
+                return Err(WgpuStateError::InstanceCountMismatch { expected: count, actual: instances.len() });
+            }
+            self.store_instances(instances);
+            return Ok(());
+        }
+        if written < count {
+            return Err(WgpuStateError::InstanceCountMismatch { expected: count, actual: written });
         }
 
-        output.present();
-        let render_after_time = Instant::now();
-        let render_time = render_after_time.duration_since(render_before_time).as_secs_f64();
-        self.frame_stats.update(render_time);
-        if self.frame_stats.frame_count % 60 == 0 {
-            self.frame_stats.display_stats();
+        self.morph = None;
+        self.instances.clear();
+        self.selected_instance = None;
+        let count = count as u32;
+        self.instance_batches = vec![InstanceBatch { group: 0, lod: 0, instances: 0..count }];
+        self.instance_slots = (0..count).collect();
+        self.instance_buffers = Some(buffers);
+        self.num_instances = count;
+        self.instances_pending = false;
+        self.write_indirect_args();
+        // CPU 側の控えがないことを set_instance_data で渡した型と同じ扱いで示し、instances での上書きを止める
+        self.custom_instance_layout = Some(crate::instance::get_instance_buffer_layout());
+        self.translucent_instances = translucent;
+        self.rebuild_pipeline();
+        Ok(())
+    }
+
+    // Instance の代わりに独自の型 I のインスタンスを描く。バッファと頂点属性の並びは I::buffer_layout で作る
+    // I の属性を読むカスタムの頂点シェーダ (WgpuStateConfig::vertex_shader) と組み合わせて使う
+    // 中身を知らないので、詳細度やグループによる並べ替え、画面外の間引き、選択、衝突、物理モードの切り替えは行わない
+    // 不透明度も分からないので、I::TRANSLUCENT が true ならアルファブレンド、false なら REPLACE で描く
+    // set_instances などで Instance を渡すと元に戻る
+    pub fn set_instance_data<I: crate::instance::InstanceData>(&mut self, instances: &[I]) -> Result<(), WgpuStateError> {
+        let device_max = self.device.as_ref().map_or(u32::MAX as u64, |device| {
+            (device.limits().max_buffer_size / std::mem::size_of::<I>().max(1) as u64).min(u32::MAX as u64)
+        }) as usize;
+        let max = self.state_config.max_instances.map_or(device_max, |max| max.min(device_max));
+        if instances.len() > max {
+            return Err(WgpuStateError::TooManyInstances { requested: instances.len(), max });
+        }
+        let Some(device) = &self.device else {
+            return Ok(());
+        };
+        self.morph = None;
+        self.instances.clear();
+        self.selected_instance = None;
+        let count = instances.len() as u32;
+        self.instance_batches = vec![InstanceBatch { group: 0, lod: 0, instances: 0..count }];
+        self.instance_slots = (0..count).collect();
+        self.instance_buffers = Some([
+            crate::instance::get_instance_buffer(device, instances),
+            crate::instance::get_instance_buffer(device, instances),
+        ]);
+        self.num_instances = count;
+        self.instances_pending = false;
+        self.write_indirect_args();
+        self.custom_instance_layout = Some(I::buffer_layout());
+        self.translucent_instances = I::TRANSLUCENT;
+        self.rebuild_pipeline();
+        Ok(())
+    }
+
+    // set_instance_data で独自の型を描いていれば、Instance の頂点属性の並びのパイプラインに戻す
+    fn use_builtin_instances(&mut self) {
+        if self.custom_instance_layout.take().is_some() {
+            self.translucent_instances = false;
+            self.rebuild_pipeline();
+        }
+    }
+
+    // マップ中の2つのインスタンスバッファの offset 個目から chunk を書き込み、書き込んだ後の位置を返す
+    fn write_instance_chunk(
+        mapped: &mut [wgpu::BufferViewMut<'_>; 2],
+        offset: usize,
+        chunk: &[crate::instance::Instance],
+    ) -> usize {
+        let instance_size = std::mem::size_of::<crate::instance::Instance>();
+        let bytes: &[u8] = bytemuck::cast_slice(chunk);
+        for view in mapped.iter_mut() {
+            view[offset * instance_size..offset * instance_size + bytes.len()].copy_from_slice(bytes);
+        }
+        offset + chunk.len()
+    }
+
+    // seed から星空の配置を作り直してインスタンスバッファを書き換える
+    pub fn regenerate_field(&mut self, seed: u64) {
+        self.selected_instance = None;
+        self.field_seed = seed;
+        // create_seeded_star_instances と同じ生成器で、配置に続けて中心のずれも引く
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(seed);
+        let mut instances = crate::instance::create_star_instances_with_params(
+            &mut rng,
+            &self.state_config.instance_params,
+            self.state_config.spin_bias,
+            self.state_config.spawn_margin,
+        );
+        crate::instance::jitter_star_centers(&mut instances, &mut rng, self.state_config.center_jitter);
+        if let Err(error) = self.set_instances(&instances) {
+            log::error!("Failed to regenerate the star field: {error}");
+        }
+    }
+
+    // 配置を position_seed から、明るさを color_seed から作り直す (create_star_instances_with_seeds と同じ)
+    // 中心のずれは配置の一部として position_seed の生成器から続けて引く
+    pub fn regenerate_field_with_seeds(&mut self, position_seed: u64, color_seed: u64) {
+        self.selected_instance = None;
+        self.field_seed = position_seed;
+        use rand::SeedableRng;
+        let mut position_rng = rand::rngs::SmallRng::seed_from_u64(position_seed);
+        let mut color_rng = rand::rngs::SmallRng::seed_from_u64(color_seed);
+        let mut instances = crate::instance::star_field_with_rngs(
+            &mut position_rng,
+            &mut color_rng,
+            &self.state_config.instance_params,
+            self.state_config.spin_bias,
+            self.state_config.spawn_margin,
+        );
+        crate::instance::jitter_star_centers(&mut instances, &mut position_rng, self.state_config.center_jitter);
+        if let Err(error) = self.set_instances(&instances) {
+            log::error!("Failed to regenerate the star field: {error}");
+        }
+    }
+
+    // 星ごとの値を引く範囲を params に変え、seed から星空の配置を作り直す
+    // 値を写して設定に使えるよう、変えた範囲をログに出す
+    pub fn regenerate_instances(&mut self, params: &crate::instance::InstanceParams, seed: u64) {
+        if !params.is_valid() {
+            log::error!("Failed to regenerate the star field: {}", crate::error::ConfigError::InstanceParams(*params));
+            return;
+        }
+        self.state_config.instance_params = *params;
+        log::info!("Instance params: {params:?}");
+        self.regenerate_field(seed);
+    }
+
+    // [ ] で大きさ、- = で移動の速さ、, . で自転の速さの範囲を 1 割ずつ縮める・広げ、同じ種で作り直す
+    // 配置が入れ替わらないので、範囲を変えた効果だけを見比べられる
+    fn nudge_instance_params(&mut self, key: KeyCode) -> bool {
+        let mut params = self.state_config.instance_params;
+        match key {
+            KeyCode::BracketLeft => params.scale = params.scale.map(|scale| scale / 1.1),
+            KeyCode::BracketRight => params.scale = params.scale.map(|scale| scale * 1.1),
+            KeyCode::Minus => params.speed /= 1.1,
+            KeyCode::Equal => params.speed *= 1.1,
+            KeyCode::Comma => params.rotation_speed = params.rotation_speed.map(|speed| speed / 1.1),
+            KeyCode::Period => params.rotation_speed = params.rotation_speed.map(|speed| speed * 1.1),
+            _ => return false,
+        }
+        self.regenerate_instances(&params, self.field_seed);
+        true
+    }
+
+    // 今の配置のまま、星ごとの明るさだけを color_seed から引き直す
+    // 星の数が同じなら、regenerate_field_with_seeds に同じ color_seed を渡したときと同じ明るさになる
+    pub fn recolor_field(&mut self, color_seed: u64) {
+        if self.custom_instance_layout.is_some() {
+            return;
+        }
+        use rand::SeedableRng;
+        let mut color_rng = rand::rngs::SmallRng::seed_from_u64(color_seed);
+        let instances: Vec<_> = self
+            .instances
+            .iter()
+            .map(|instance| instance.with_opacity(crate::instance::random_opacity(&mut color_rng)))
+            .collect();
+        if let Err(error) = self.set_instances(&instances) {
+            log::error!("Failed to recolor the star field: {error}");
+        }
+    }
+
+    // ウィンドウのピクセル座標を、time 秒時点の星空全体の回転を打ち消した星空の座標に変換する
+    fn window_to_field(&self, position: PhysicalPosition<f64>, time: f32) -> [f32; 2] {
+        let world = self.camera.clip_to_world(self.window_to_clip(position));
+
+        // シェーダが掛ける星空全体の回転を打ち消して、回転前の座標に戻す
+        let (sin, cos) = (time * self.field_rotation_speed).sin_cos();
+        [cos * world[0] - sin * world[1], sin * world[0] + cos * world[1]]
+    }
+
+    // ウィンドウのピクセル座標をキャンバス全体のクリップ座標に変換する
+    // aspect_lock の帯の分だけずらして、星を描く矩形の左上を原点にする
+    fn window_to_clip(&self, position: PhysicalPosition<f64>) -> [f32; 2] {
+        let [width, height] = self.canvas_size();
+        let [left, top, _, _] = self.letterbox().unwrap_or_default();
+        let canvas_x = position.x as f32 - left as f32 + self.state_config.viewport_offset[0] as f32;
+        let canvas_y = position.y as f32 - top as f32 + self.state_config.viewport_offset[1] as f32;
+        [
+            canvas_x / width.max(1) as f32 * 2.0 - 1.0,
+            1.0 - canvas_y / height.max(1) as f32 * 2.0,
+        ]
+    }
+
+    // カーソル位置に映っている星のうち中心が最も近いものを選び、その星だけ動きを止めて色を変える
+    // 選択中の星をもう一度クリックすると選択を解除する。星の上でなければ false を返す
+    pub fn pick_star_at(&mut self, position: PhysicalPosition<f64>) -> bool {
+        let time = self.current_time();
+        let cursor = self.window_to_field(position, time);
+        let local_unit = self.local_unit();
+        let nearest = self
+            .instances
+            .iter()
+            .enumerate()
+            .map(|(index, instance)| {
+                let [x, y] = self.instance_position(index, instance, time);
+                (index, (x - cursor[0]).hypot(y - cursor[1]), instance.scale() * local_unit)
+            })
+            .filter(|(_, distance, scale)| distance <= scale)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        let Some((index, _, _)) = nearest else {
+            return false;
+        };
+        if self.selected_instance == Some(index) {
+            self.select_instance(None);
+        } else {
+            self.select_instance(Some(index));
+        }
+        true
+    }
+
+    // instances の index 番目の星を選んで動きを止め、内容をログに出す。None で選択を解除する
+    pub fn select_instance(&mut self, index: Option<usize>) {
+        let index = index.filter(|index| *index < self.instances.len());
+        if let Some(index) = index {
+            if self.selected_instance != Some(index) {
+                let time = self.current_time();
+                self.selected_time = self.translation_clock.at(time);
+                self.selected_rotation_time = self.rotation_clock.at(time);
+            }
+            log::info!("Selected star {index}: {:?}", self.instances[index]);
+        }
+        self.selected_instance = index;
+    }
+
+    pub fn selected_instance(&self) -> Option<usize> {
+        self.selected_instance
+    }
+
+    // ウィンドウ上のカーソル位置に、画面中心から外向きに動く星を1つ追加する
+    // max_instances に達している場合は追加しない
+    pub fn spawn_star_at(&mut self, position: PhysicalPosition<f64>) {
+        if self.custom_instance_layout.is_some() {
+            return;
+        }
+        let time = self.current_time();
+        let unrotated = self.window_to_field(position, time);
+
+        let distance = unrotated[0].hypot(unrotated[1]);
+        let direction = if distance > f32::EPSILON {
+            [unrotated[0] / distance, unrotated[1] / distance]
+        } else {
+            [1.0, 0.0]
+        };
+        let speed = [direction[0] * Self::SPAWN_SPEED, direction[1] * Self::SPAWN_SPEED];
+        // シェーダは初期位置 + 速度 * 時刻 で位置を求めるので、今の時刻にカーソルの位置へ来るように逆算する
+        // 時刻には追加先のグループの速さの倍率が掛かる
+        let scaled_time = self.translation_clock.at(time) * self.speed_scale_of(self.instances.len());
+        let star = crate::instance::Instance::new(
+            [unrotated[0] - speed[0] * scaled_time, unrotated[1] - speed[1] * scaled_time],
+            0.035,
+            0.0,
+            speed,
+            1.0,
+        );
+
+        let mut instances = self.instances.clone();
+        instances.push(star);
+        if let Err(error) = self.set_instances(&instances) {
+            log::warn!("Failed to spawn a star: {error}");
+        }
+    }
+
+    // 画面端での振る舞いを Wrap → Bounce → None の順に切り替える
+    pub fn cycle_physics_mode(&mut self) {
+        self.set_physics_mode(self.state_config.physics_mode.next());
+    }
+
+    // シェーダは時刻から位置を直接求めるので、今映っている位置と向きを初期値に置き直してから切り替える
+    // (跳ね返って逆向きに動いていた星は、その向きの速度に置き換わる)
+    pub fn set_physics_mode(&mut self, mode: crate::physics::PhysicsMode) {
+        let previous = self.state_config.physics_mode;
+        if previous == mode {
+            return;
+        }
+        let time = self.translation_clock.at(self.current_time());
+        let instances: Vec<_> = self
+            .instances
+            .iter()
+            .enumerate()
+            .map(|(index, instance)| instance.rebased(previous, time * self.speed_scale_of(index)))
+            .collect();
+        if self.custom_instance_layout.is_none() {
+            if let Err(error) = self.set_instances(&instances) {
+                log::error!("Failed to switch the physics mode: {error}");
+                return;
+            }
+        }
+        self.state_config.physics_mode = mode;
+        log::info!("Physics mode: {mode:?}");
+    }
+
+    // 現在の配置から target へ duration 秒かけて補間しながら移行する
+    // 補間の緩急は WgpuStateConfig::morph_easing で選ぶ
+    pub fn morph_to(&mut self, target: &[crate::instance::Instance], duration: f32) -> Result<(), WgpuStateError> {
+        if duration <= 0.0 {
+            return self.set_instances(target);
+        }
+        self.check_instance_count(target.len())?;
+        let morph = Morph::new(self.instances.clone(), target.to_vec(), duration, self.state_config.morph_easing);
+        let (instances, _) = morph.sample(Instant::now());
+        self.use_builtin_instances();
+        self.upload_instances(&instances);
+        self.instances = instances;
+        self.morph = Some(morph);
+        Ok(())
+    }
+
+    fn check_instance_count(&self, requested: usize) -> Result<(), WgpuStateError> {
+        let max = self.max_instances();
+        if requested > max {
+            return Err(WgpuStateError::TooManyInstances { requested, max });
         }
         Ok(())
     }
-}
\ No newline at end of file
+
+    // インスタンス数が変わらなければ、描画中のバッファには触れずに書き込み側のバッファへ内容だけ書き込む
+    fn upload_instances(&mut self, instances: &[crate::instance::Instance]) {
+        // set_instance_data で渡した独自の型のバッファは、空の CPU 側のコピーで上書きしない
+        if self.custom_instance_layout.is_some() {
+            return;
+        }
+        // REPLACE のままだとインスタンスの不透明度が捨てられるので、半透明の星が入ったらブレンドに切り替える
+        let translucent = Self::has_translucent(instances);
+        if translucent != self.translucent_instances {
+            self.translucent_instances = translucent;
+            self.rebuild_pipeline();
+        }
+        let (instances, instance_batches, instance_slots) = Self::batch_instances(&self.state_config, instances);
+        self.instance_batches = instance_batches;
+        self.instance_slots = instance_slots;
+        self.write_indirect_args();
+        if self.selected_instance.is_some_and(|index| index >= instances.len()) {
+            self.selected_instance = None;
+        }
+        if instances.len() as u32 == self.num_instances {
+            if let (Some(queue), Some(instance_buffers)) = (&self.queue, &self.instance_buffers) {
+                let write_buffer = &instance_buffers[1 - self.instance_read_index];
+                queue.write_buffer(write_buffer, 0, bytemuck::cast_slice(&instances));
+                self.instances_pending = true;
+                return;
+            }
+        }
+        if let Some(device) = &self.device {
+            self.instance_buffers = Some([
+                crate::instance::get_instance_buffer(device, &instances),
+                crate::instance::get_instance_buffer(device, &instances),
+            ]);
+            self.num_instances = instances.len() as u32;
+            self.instances_pending = false;
+        }
+    }
+
+    fn has_translucent(instances: &[crate::instance::Instance]) -> bool {
+        instances.iter().any(|instance| instance.opacity() < 1.0)
+    }
+
+    // instance_batches の組ごとの描画引数を indirect_buffer に書き込む。足りなければ作り直す
+    fn write_indirect_args(&mut self) {
+        let (true, Some(device), Some(queue)) = (self.indirect_draw, &self.device, &self.queue) else {
+            return;
+        };
+        let args: Vec<u8> = self
+            .instance_batches
+            .iter()
+            .flat_map(|batch| {
+                let indices = &self.lods[batch.lod].indices;
+                wgpu::util::DrawIndexedIndirectArgs {
+                    index_count: indices.len() as u32,
+                    instance_count: batch.instances.len() as u32,
+                    first_index: indices.start,
+                    base_vertex: 0,
+                    first_instance: batch.instances.start,
+                }
+                .as_bytes()
+                .to_vec()
+            })
+            .collect();
+        if self.indirect_buffer.as_ref().is_none_or(|buffer| buffer.size() < args.len() as wgpu::BufferAddress) {
+            self.indirect_buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Indirect Buffer"),
+                size: (args.len() as wgpu::BufferAddress).max(INDIRECT_ARGS_SIZE),
+                usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        if let Some(buffer) = &self.indirect_buffer {
+            queue.write_buffer(buffer, 0, &args);
+        }
+    }
+
+    // インスタンスを重なりの順番 (layer) ごとに、その中でグループと詳細度の段階の組ごとに並べ替え、
+    // 組ごとのインスタンスバッファ上の範囲と、元の並びの各インスタンスが書き込まれる位置 (instance_index) を返す
+    // 組は layer の小さい順に並ぶので、先頭から描けば layer の大きい星ほど上に重なる
+    fn batch_instances(
+        state_config: &WgpuStateConfig,
+        instances: &[crate::instance::Instance],
+    ) -> (Vec<crate::instance::Instance>, Vec<InstanceBatch>, Vec<u32>) {
+        let lod_count = state_config.lod_count();
+        let group_count = state_config.groups.len().max(1);
+        let count = instances.len() as u32;
+        let single_layer = instances.windows(2).all(|pair| pair[0].layer() == pair[1].layer());
+        if lod_count * group_count == 1 && single_layer {
+            let batch = InstanceBatch { group: 0, lod: 0, instances: 0..count };
+            return (instances.to_vec(), vec![batch], (0..count).collect());
+        }
+
+        // (layer, グループ, 段階) の順に安定ソートする (同じ組の中では元の順番を保つ)
+        let keys: Vec<(u32, usize)> = instances
+            .iter()
+            .enumerate()
+            .map(|(index, instance)| {
+                let group = crate::group::group_of(&state_config.groups, index);
+                (instance.layer(), group * lod_count + state_config.lod_level(instance.scale()))
+            })
+            .collect();
+        let mut order: Vec<usize> = (0..instances.len()).collect();
+        order.sort_by_key(|index| keys[*index]);
+
+        let mut ordered = Vec::with_capacity(instances.len());
+        let mut slots = vec![0; instances.len()];
+        let mut batches: Vec<InstanceBatch> = Vec::new();
+        for (slot, index) in order.iter().enumerate() {
+            let slot = slot as u32;
+            let key = keys[*index];
+            ordered.push(instances[*index]);
+            slots[*index] = slot;
+            match batches.last_mut() {
+                Some(batch) if slot > 0 && keys[order[slot as usize - 1]] == key => batch.instances.end = slot + 1,
+                _ => batches.push(InstanceBatch {
+                    group: key.1 / lod_count,
+                    lod: key.1 % lod_count,
+                    instances: slot..slot + 1,
+                }),
+            }
+        }
+        (ordered, batches, slots)
+    }
+
+    // instances の index 番目の星 instance が time 秒時点で映る、星空全体の回転前の位置
+    // 選択中の星は止めた時刻のまま、グループの速さの倍率も掛ける (シェーダの instanceTime と揃えること)
+    fn instance_position(&self, index: usize, instance: &crate::instance::Instance, time: f32) -> [f32; 2] {
+        let instance_time = if Some(index) == self.selected_instance {
+            self.selected_time
+        } else {
+            self.translation_clock.at(time)
+        };
+        instance.position_at(self.state_config.physics_mode, instance_time * self.speed_scale_of(index))
+    }
+
+    // カーソルの引力が効いているか (強さが 0.0 でなく、A キーで止めていない)
+    pub fn attractor_active(&self) -> bool {
+        self.attractor_enabled && self.state_config.attractor_strength != 0.0
+    }
+
+    // time 秒時点でカーソルの引力が引き寄せる点 (星空全体の回転前の座標)。カーソルがウィンドウの外なら None
+    fn attractor(&self, time: f32) -> Option<[f32; 2]> {
+        let position = self.cursor_position.filter(|_| self.attractor_active())?;
+        Some(self.window_to_field(position, time))
+    }
+
+    pub fn set_attractor_strength(&mut self, strength: f32) {
+        self.state_config.attractor_strength = strength;
+    }
+
+    // time 秒時点で link_distance より近い星どうしを結ぶ線の頂点
+    fn link_vertices(&self, time: f32) -> Vec<crate::link::LinkVertex> {
+        let positions: Vec<[f32; 2]> = self
+            .instances
+            .iter()
+            .enumerate()
+            .map(|(index, instance)| self.instance_position(index, instance, time))
+            .collect();
+        crate::link::build_link_vertices(&positions, self.state_config.link_distance, self.state_config.link_color)
+    }
+
+    // viewport_culling が有効なとき、time 秒時点で画面に掛かりうるかをインスタンスバッファ上の位置ごとに返す
+    // 星の形の長さ 1.0 に当たる星空の座標での長さ (シェーダの localUnit と同じ計算)
+    // radius_pixels が None なら 1.0、Some なら今の projection とウィンドウの幅からピクセル数を星空の座標に直す
+    fn local_unit(&self) -> f32 {
+        let Some(radius_pixels) = self.state_config.radius_pixels else {
+            return 1.0;
+        };
+        let pixels_per_unit = self.projection[0][0].hypot(self.projection[0][1]) * self.target_size()[0] as f32 * 0.5;
+        radius_pixels / pixels_per_unit.max(1e-6)
+    }
+
+    // 星の外接円 (中心のずれ、点描画の四角形の角、輪郭の太さを含む) がクリップ空間の -1.0〜1.0 に掛かるかで調べる
+    // 登場演出の間は星が画面外から飛んでくるので調べない
+    fn visible_slots(&self, time: f32) -> Option<Vec<bool>> {
+        let config = &self.state_config;
+        if !config.viewport_culling || time < config.intro_duration || self.custom_instance_layout.is_some() {
+            return None;
+        }
+        let projection = self.projection;
+        // 星空の長さ 1.0 がクリップ空間で取りうる最大の長さ (回転とカメラの拡大を含む行列の上限)
+        let clip_scale = projection[0][0]
+            .hypot(projection[0][1])
+            .hypot(projection[1][0].hypot(projection[1][1]));
+        let line_margin = if config.outline_mode {
+            (config.line_width * 0.5 + 1.0) * 2.0 / self.target_size().into_iter().min().unwrap_or(1).max(1) as f32
+        } else {
+            0.0
+        };
+        let (sin, cos) = (time * self.field_rotation_speed).sin_cos();
+        let local_unit = self.local_unit();
+
+        let mut visible = vec![false; self.num_instances as usize];
+        for (index, instance) in self.instances.iter().enumerate() {
+            // シェーダの rotateField と同じ向きに星空全体を回転させる
+            let [x, y] = self.instance_position(index, instance, time);
+            let [x, y] = [cos * x + sin * y, cos * y - sin * x];
+            let clip_x = projection[0][0] * x + projection[1][0] * y + projection[3][0];
+            let clip_y = projection[0][1] * x + projection[1][1] * y + projection[3][1];
+            let radius = instance.bounding_radius() * local_unit * clip_scale + line_margin;
+            if clip_x.abs() <= 1.0 + radius && clip_y.abs() <= 1.0 + radius {
+                if let Some(slot) = visible.get_mut(self.instance_slots[index] as usize) {
+                    *slot = true;
+                }
+            }
+        }
+        Some(visible)
+    }
+
+    // instances の index 番目の星が属するグループの速さの倍率
+    fn speed_scale_of(&self, index: usize) -> f32 {
+        let groups = &self.state_config.groups;
+        groups
+            .get(crate::group::group_of(groups, index))
+            .map_or(1.0, |group| group.speed_scale)
+    }
+
+    // 描画に使うインスタンスバッファ (まだ入れ替えていない新しい内容があればそちら)
+    fn draw_instance_buffer(&self) -> Option<&wgpu::Buffer> {
+        let index = if self.instances_pending {
+            1 - self.instance_read_index
+        } else {
+            self.instance_read_index
+        };
+        self.instance_buffers.as_ref().map(|buffers| &buffers[index])
+    }
+
+    pub fn set_background_mode(&mut self, mode: BackgroundMode) {
+        self.state_config.background = mode;
+        if let (Some(queue), Some(background)) = (&self.queue, &mut self.background) {
+            background.set_mode(queue, mode);
+        }
+    }
+
+    // フォーカスの変化を記録し、設定に応じてフォーカス外の時間をアニメーションから除外する
+    pub fn set_focused(&mut self, focused: bool) {
+        if self.focused == focused {
+            return;
+        }
+        self.focused = focused;
+        if !focused {
+            self.unfocused_at = Some(Instant::now());
+            // 描画を止めている間の空白を落ちたフレームとして数えない
+            self.last_frame_at = None;
+        } else if let Some(unfocused_at) = self.unfocused_at.take() {
+            if self.state_config.freeze_time_when_unfocused {
+                // 止めていた間を前のフレームからの間隔に含めず、アニメーションの時刻をその分進めない
+                let paused = Instant::now().duration_since(unfocused_at);
+                if let Some(animated_at) = &mut self.animated_at {
+                    *animated_at += paused;
+                }
+            }
+        }
+    }
+
+    // Suspended を受け取ったときに呼ぶ。Resumed まで描画を止め、フォーカスが外れたときと同じく時間の扱いを記録する
+    pub fn suspend(&mut self) {
+        if self.suspended {
+            return;
+        }
+        self.suspended = true;
+        self.set_focused(false);
+    }
+
+    // Resumed を受け取ったときに呼ぶ。サスペンド中でなければ (起動直後の Resumed など) 何もせず false を返す
+    // Android などではサスペンドの間にウィンドウの裏のサーフェスが破棄されるので、作り直して設定し直す
+    pub fn resume(&mut self) -> bool {
+        if !self.suspended {
+            return false;
+        }
+        match self.instance.create_surface(self.window) {
+            Ok(surface) => self.surface = surface,
+            Err(error) => log::error!("Failed to recreate the surface after resuming: {error}"),
+        }
+        if let (Some(device), Some(config)) = (&self.device, &mut self.config) {
+            config.width = self.size.width.max(1);
+            config.height = self.size.height.max(1);
+            self.surface.configure(device, config);
+        }
+        self.suspended = false;
+        self.set_focused(true);
+        true
+    }
+
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    // ホスト側で任意機能 (タイムスタンプ、ワイヤーフレームなど) を有効にできるかを判断するために使う
+    pub fn supported_features(&self) -> wgpu::Features {
+        self.adapter_features
+    }
+
+    pub fn supported_limits(&self) -> wgpu::Limits {
+        self.adapter_limits.clone()
+    }
+
+    // 実際に選ばれたサーフェスのフォーマット (設定は初期化時に必ず作られる)
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.config.as_ref().unwrap().format
+    }
+
+    // 現在のPresentMode (V キーで切り替えた後はその値)
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.as_ref().unwrap().present_mode
+    }
+
+    // 頂点・インデックス・インスタンス・uniform バッファに確保した大きさ
+    pub fn memory_report(&self) -> MemoryReport {
+        let size = |buffer: &Option<wgpu::Buffer>| buffer.as_ref().map_or(0, |buffer| buffer.size());
+        MemoryReport {
+            vertex_bytes: size(&self.vertex_buffer) + size(&self.morph_buffer),
+            index_bytes: size(&self.index_buffer),
+            instance_bytes: self
+                .instance_buffers
+                .as_ref()
+                .map_or(0, |buffers| buffers.iter().map(|buffer| buffer.size()).sum()),
+            uniform_bytes: size(&self.uniform_buffer)
+                + self.uniform_ring.iter().map(|(buffer, _)| buffer.size()).sum::<u64>()
+                + size(&self.group_buffer),
+            indirect_bytes: size(&self.indirect_buffer),
+        }
+    }
+
+    // デバイス・キュー・パイプライン・各バッファが揃い、実際に描画できる状態かどうか
+    pub fn is_ready(&self) -> bool {
+        self.device.is_some()
+            && self.queue.is_some()
+            && self.config.is_some()
+            && self.render_pipeline.is_some()
+            && self.uniform_bind_group.is_some()
+            && self.uniform_buffer.is_some()
+            && self.vertex_buffer.is_some()
+            && self.index_buffer.is_some()
+            && self.instance_buffers.is_some()
+    }
+
+    // フレームレイテンシを変更してサーフェスを再設定する
+    pub fn set_frame_latency(&mut self, frame_latency: u32) {
+        self.state_config.frame_latency = frame_latency;
+        if let (Some(device), Some(config)) = (&self.device, &mut self.config) {
+            config.desired_maximum_frame_latency = frame_latency;
+            self.surface.configure(device, config);
+        }
+    }
+
+    // 毎フレーム、星を描いた後の同じレンダーパスの中で呼ばれるコールバックを登録する
+    // ホスト側のUIなどを同じパスに重ねて描くために使う。パイプラインやバインドグループは上書きしてよい
+    pub fn on_render(&mut self, callback: impl FnMut(&mut wgpu::RenderPass<'_>, &WgpuState<'window>) + 'window) {
+        self.on_render = Some(Box::new(callback));
+    }
+
+    // 毎フレーム update の中で、CPU側のインスタンスの列とフレーム間隔 (smoothed_delta、秒) を渡して呼ばれるコールバックを登録する
+    // WGSL を書かずに Rust で独自の動きを付けるためのもので、書き換えた列は毎フレームまるごとインスタンスバッファへ送り直す
+    // 送る量は星の数に比例するので、数万個を超えるような場合はシェーダ側 (vertex_shader) で動かす方がよい
+    pub fn on_update(&mut self, callback: impl FnMut(&mut [crate::instance::Instance], f32) + 'window) {
+        self.on_update = Some(Box::new(callback));
+    }
+
+    // 描画可能になった時点で一度だけ呼ばれるコールバックを登録する (既に準備済みなら即座に呼ぶ)
+    pub fn on_ready(&mut self, callback: impl FnOnce(&WgpuState<'window>) + 'window) {
+        if self.is_ready() {
+            callback(self);
+        } else {
+            self.on_ready = Some(Box::new(callback));
+        }
+    }
+
+    pub fn update(&mut self) {
+        if self.is_ready() {
+            if let Some(callback) = self.on_ready.take() {
+                callback(self);
+            }
+        }
+
+        let mut changed = false;
+        if let Some(morph) = &self.morph {
+            let (instances, finished) = morph.sample(Instant::now());
+            self.instances = instances;
+            changed = true;
+            if finished {
+                self.morph = None;
+            }
+        }
+        let delta = self.smoothed_delta();
+        if let Some(callback) = &mut self.on_update {
+            callback(&mut self.instances, delta);
+            changed = true;
+        }
+        if self.morph.is_none() {
+            changed |= self.apply_attractor();
+        }
+        if self.state_config.collisions && self.morph.is_none() {
+            changed |= self.resolve_collisions();
+        }
+        if changed {
+            let instances = std::mem::take(&mut self.instances);
+            self.upload_instances(&instances);
+            self.instances = instances;
+        }
+    }
+
+    // カーソルの引力を加速度として、前回から進んだ移動の時刻の分だけ各星の速度に足し、
+    // 今の位置から新しい速度で動き出すようにインスタンスを置き直す (止めている間は時刻が進まないので加速しない)
+    // 選択中の星と倍率 0.0 のグループの星は動かさない。書き換えた星があれば true
+    fn apply_attractor(&mut self) -> bool {
+        let time = self.current_time();
+        let translation_time = self.translation_clock.at(time);
+        let Some(attractor) = self.attractor(time) else {
+            self.attractor_time = None;
+            return false;
+        };
+        let previous = self.attractor_time.replace(translation_time);
+        let Some(delta) = previous.map(|previous| translation_time - previous).filter(|delta| *delta > 0.0) else {
+            return false;
+        };
+        let mode = self.state_config.physics_mode;
+        let strength = self.state_config.attractor_strength;
+        let mut changed = false;
+        for index in 0..self.instances.len() {
+            let speed_scale = self.speed_scale_of(index);
+            if Some(index) == self.selected_instance || speed_scale == 0.0 {
+                continue;
+            }
+            let instance = self.instances[index];
+            let [acceleration_x, acceleration_y] =
+                crate::physics::attraction(self.instance_position(index, &instance, time), attractor, strength);
+            if acceleration_x == 0.0 && acceleration_y == 0.0 {
+                continue;
+            }
+            // 加速度は見かけの速度に掛かるので、グループの速さの倍率で割ってインスタンスの速度に直す
+            let scaled_time = translation_time * speed_scale;
+            let [speed_x, speed_y] = instance.velocity_at(mode, scaled_time);
+            let speed = [
+                speed_x + acceleration_x * delta / speed_scale,
+                speed_y + acceleration_y * delta / speed_scale,
+            ];
+            self.instances[index] = instance.redirected(mode, scaled_time, speed);
+            changed = true;
+        }
+        changed
+    }
+
+    // 今の時刻に重なっている星どうしを弾き合わせ、新しい速度で今の位置から動き出すようにインスタンスを置き直す
+    // 速度はグループの速さの倍率を掛けた見かけの速さで比べる。選択中の星と倍率 0.0 のグループの星は動かない壁になる
+    // 書き換えた星があれば true
+    fn resolve_collisions(&mut self) -> bool {
+        let time = self.current_time();
+        let translation_time = self.translation_clock.at(time);
+        let mode = self.state_config.physics_mode;
+        let local_unit = self.local_unit();
+        let count = self.instances.len();
+        let (mut positions, mut radii) = (Vec::with_capacity(count), Vec::with_capacity(count));
+        let (mut fixed, mut velocities) = (Vec::with_capacity(count), Vec::with_capacity(count));
+        for (index, instance) in self.instances.iter().enumerate() {
+            let speed_scale = self.speed_scale_of(index);
+            let [speed_x, speed_y] = instance.velocity_at(mode, translation_time * speed_scale);
+            positions.push(self.instance_position(index, instance, time));
+            radii.push(instance.scale() * local_unit);
+            fixed.push(Some(index) == self.selected_instance || speed_scale == 0.0);
+            velocities.push([speed_x * speed_scale, speed_y * speed_scale]);
+        }
+
+        let changed = crate::physics::collide(&positions, &radii, &fixed, &mut velocities, self.state_config.restitution);
+        for &index in &changed {
+            let speed_scale = self.speed_scale_of(index);
+            let speed = velocities[index].map(|velocity| velocity / speed_scale);
+            self.instances[index] = self.instances[index].redirected(mode, translation_time * speed_scale, speed);
+        }
+        !changed.is_empty()
+    }
+
+    // 星どうしの衝突を切り替える。restitution は 0.0〜1.0 に収める
+    pub fn set_collisions(&mut self, collisions: bool, restitution: f32) {
+        self.state_config.collisions = collisions;
+        self.state_config.restitution = if restitution.is_nan() { 1.0 } else { restitution.clamp(0.0, 1.0) };
+    }
+
+    // 星空全体の回転速度 (ラジアン/秒)
+    pub fn set_field_rotation_speed(&mut self, speed: f32) {
+        self.field_rotation_speed = speed;
+    }
+
+    // 速度による色変化の強さ (0.0 で無効、1.0 で完全に置き換え)
+    pub fn set_doppler_intensity(&mut self, intensity: f32) {
+        self.doppler_intensity = intensity;
+    }
+
+    // 星ごとの瞬きの速さと強さ (amount が 0.0 で無効、1.0 で瞬くたびに消えるまで暗くなる)
+    pub fn set_twinkle(&mut self, speed: f32, amount: f32) {
+        self.twinkle_speed = speed;
+        self.twinkle_amount = amount;
+    }
+
+    // 描画モードに合わせた星1つ分の頂点バッファとインデックスバッファを作る
+    // 塗りつぶしの星形では、詳細度の段階ごとのメッシュを1つのバッファに続けて詰める
+    // 形の補間を使う場合は、補間先の頂点を同じ並びで詰めたバッファも作る
+    fn create_geometry(
+        device: &wgpu::Device,
+        state_config: &WgpuStateConfig,
+    ) -> (wgpu::Buffer, u32, wgpu::Buffer, u32, Vec<MeshLod>, Option<wgpu::Buffer>) {
+        let (vertex_buffer, num_vertices, indices, lods, morph_buffer) = if state_config.outline_mode {
+            let (vertices, indices) = Self::create_outline_vertices(state_config);
+            let vertex_buffer = crate::vertex::LineVertex::get_vertex_buffer(device, &vertices);
+            let lod = MeshLod { vertex_bytes: 0..vertex_buffer.size(), indices: 0..indices.len() as u32 };
+            (vertex_buffer, vertices.len(), indices, vec![lod], None)
+        } else {
+            let mut vertices = Vec::new();
+            let mut morph_vertices = Vec::new();
+            let mut indices = Vec::new();
+            let mut lods = Vec::new();
+            let vertex_size = std::mem::size_of::<crate::vertex::Vertex>() as wgpu::BufferAddress;
+            for level in 0..state_config.lod_count() {
+                let subdivisions = state_config.lod_subdivisions(level);
+                let (lod_vertices, lod_indices) = if state_config.point_mode {
+                    Self::create_point_vertices()
+                } else if let Some(target) = state_config.shape_morph_target {
+                    let (lod_vertices, lod_indices, lod_morph_vertices) =
+                        Self::create_morph_vertices(state_config, target, subdivisions);
+                    morph_vertices.extend(lod_morph_vertices);
+                    (lod_vertices, lod_indices)
+                } else {
+                    Self::create_star_vertices(state_config, subdivisions)
+                };
+                let vertex_start = vertices.len() as wgpu::BufferAddress * vertex_size;
+                let index_start = indices.len() as u32;
+                vertices.extend(lod_vertices);
+                indices.extend(lod_indices);
+                lods.push(MeshLod {
+                    vertex_bytes: vertex_start..vertices.len() as wgpu::BufferAddress * vertex_size,
+                    indices: index_start..indices.len() as u32,
+                });
+            }
+            let morph_buffer = (!morph_vertices.is_empty())
+                .then(|| crate::vertex::Vertex::get_vertex_buffer(device, &morph_vertices));
+            (crate::vertex::Vertex::get_vertex_buffer(device, &vertices), vertices.len(), indices, lods, morph_buffer)
+        };
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        (vertex_buffer, num_vertices as u32, index_buffer, indices.len() as u32, lods, morph_buffer)
+    }
+
+    // state_config の現在の値で頂点・インデックスバッファを作り直す
+    // 星形の形や描画モードを state_config で変えた後に呼ぶ
+    pub fn rebuild_geometry(&mut self) {
+        if let Some(device) = &self.device {
+            let (vertex_buffer, num_vertices, index_buffer, num_indices, lods, morph_buffer) =
+                Self::create_geometry(device, &self.state_config);
+            self.vertex_buffer = Some(vertex_buffer);
+            self.morph_buffer = morph_buffer;
+            self.num_vertices = Some(num_vertices);
+            self.index_buffer = Some(index_buffer);
+            self.num_indices = Some(num_indices);
+            self.lods = lods;
+            if let Some(outline_stroke) = &mut self.outline_stroke {
+                outline_stroke.rebuild_geometry(device, &self.state_config);
+            }
+            // 段階の数や境界が変わっていれば、インスタンスの並びも作り直す
+            let instances = std::mem::take(&mut self.instances);
+            self.upload_instances(&instances);
+            self.instances = instances;
+        }
+    }
+
+    // state_config の現在の値 (描画モードなど) でパイプラインを作り直す
+    pub fn rebuild_pipeline(&mut self) {
+        if let (Some(device), Some(config)) = (&self.device, &self.config) {
+            self.render_pipeline = Some(crate::uniform::Uniforms::get_render_setting_blended(
+                device,
+                &self.uniform_bind_group_layout,
+                self.custom_vertex_shader.as_ref().unwrap_or(&self.shader),
+                self.custom_fragment_shader.as_ref().unwrap_or(&self.shader),
+                config,
+                &self.state_config,
+                crate::uniform::InstanceLayout {
+                    buffer_layout: self
+                        .custom_instance_layout
+                        .clone()
+                        .unwrap_or_else(crate::instance::get_instance_buffer_layout),
+                    translucent: self.translucent_instances,
+                },
+            ));
+            self.link_pipeline = (self.state_config.link_distance > 0.0).then(|| {
+                crate::link::create_link_pipeline(
+                    device,
+                    &self.uniform_bind_group_layout,
+                    &self.shader,
+                    config,
+                    &self.state_config,
+                )
+            });
+            self.debug_grid = self.state_config.debug_grid.then(|| {
+                crate::grid::DebugGrid::new(
+                    device,
+                    &self.uniform_bind_group_layout,
+                    &self.shader,
+                    config,
+                    &self.state_config,
+                )
+            });
+            // 縁取りは同梱シェーダで Instance の並びを読むので、独自の型や流し込んだ星を描いている間は重ねない
+            let outline_stroke = self.state_config.outline_stroke_enabled() && self.custom_instance_layout.is_none();
+            self.outline_stroke = outline_stroke.then(|| {
+                crate::outline::OutlineStroke::new(
+                    device,
+                    &self.uniform_bind_group_layout,
+                    &self.shader,
+                    config,
+                    &self.state_config,
+                )
+            });
+        }
+    }
+
+    // 星1つ分の輪郭の辺を四角形にした頂点 (outline_mode と outline_style の縁取りで使う)
+    pub(crate) fn create_outline_vertices(
+        state_config: &WgpuStateConfig,
+    ) -> (Vec<crate::vertex::LineVertex>, Vec<u16>) {
+        if state_config.sharpened() {
+            crate::vertex::LineVertex::get_sharpened_outline_vertices(
+                state_config.star_points,
+                state_config.star_skip,
+                state_config.inverted,
+                state_config.tip_sharpness,
+            )
+        } else {
+            crate::vertex::LineVertex::get_oriented_outline_vertices(
+                state_config.star_points,
+                state_config.star_skip,
+                state_config.inverted,
+            )
+        }
+    }
+
+    pub(crate) fn create_star_vertices(
+        state_config: &WgpuStateConfig,
+        subdivisions: u32,
+    ) -> (Vec<crate::vertex::Vertex>, Vec<u16>) {
+        let num_points = state_config.star_points;
+        if state_config.sharpened() {
+            // 外側の頂点とくぼみを交互に並べた輪郭を、中心からの扇で塗る
+            let samples = 2 * num_points;
+            let vertices = crate::vertex::Vertex::get_sharpened_radial_vertices(
+                num_points,
+                state_config.star_skip,
+                samples,
+                state_config.inverted,
+                state_config.tip_sharpness,
+            );
+            return crate::vertex::subdivide_triangles(&vertices, &crate::vertex::fan_indices(samples), subdivisions);
+        }
+        let vertices = crate::vertex::Vertex::get_oriented_vertices(num_points, state_config.inverted);
+
+        // {n/m} と {n/(n-m)} は同じ図形になる。中心から見て反時計回りの三角形になるよう小さい方を使う
+        let skip = state_config.star_skip.min(num_points - state_config.star_skip);
+        let mut indices = Vec::new();
+
+        for i in 0..num_points {
+            let current = 1 + i;
+            let next = 1 + ((i + skip) % num_points);
+
+            indices.extend_from_slice(&[0, current as u16, next as u16]);
+        }
+        let (vertices, indices) = crate::vertex::subdivide_triangles(&vertices, &indices, subdivisions);
+
+        if state_config.debug_geometry {
+            if let Err(triangles) = crate::vertex::validate_winding(&vertices, &indices) {
+                log::warn!("Star triangles {triangles:?} are wound against the front face and will be culled");
+            }
+        }
+
+        (vertices, indices)
+    }
+
+    // 星形とその補間先の形を、同じ数の点で取り直した扇状のメッシュにする
+    // 分割は三角形ごとに同じ順で行うので、分割後も2つの頂点の列は1対1に対応する
+    fn create_morph_vertices(
+        state_config: &WgpuStateConfig,
+        [points, skip]: [u32; 2],
+        subdivisions: u32,
+    ) -> (Vec<crate::vertex::Vertex>, Vec<u16>, Vec<crate::vertex::Vertex>) {
+        let samples = crate::vertex::morph_sample_count(state_config.star_points, points);
+        let from = crate::vertex::Vertex::get_sharpened_radial_vertices(
+            state_config.star_points,
+            state_config.star_skip,
+            samples,
+            state_config.inverted,
+            state_config.tip_sharpness,
+        );
+        let to = crate::vertex::Vertex::get_sharpened_radial_vertices(
+            points,
+            skip,
+            samples,
+            state_config.inverted,
+            state_config.tip_sharpness,
+        );
+        let indices = crate::vertex::fan_indices(samples);
+        let (from, subdivided_indices) = crate::vertex::subdivide_triangles(&from, &indices, subdivisions);
+        let (to, _) = crate::vertex::subdivide_triangles(&to, &indices, subdivisions);
+
+        if state_config.debug_geometry {
+            for vertices in [&from, &to] {
+                if let Err(triangles) = crate::vertex::validate_winding(vertices, &subdivided_indices) {
+                    log::warn!("Morph triangles {triangles:?} are wound against the front face and will be culled");
+                }
+            }
+        }
+
+        (from, subdivided_indices, to)
+    }
+
+    fn create_point_vertices() -> (Vec<crate::vertex::Vertex>, Vec<u16>) {
+        let vertices = crate::vertex::Vertex::get_quad_vertices();
+        let indices = vec![0, 1, 2, 0, 2, 3];
+
+        (vertices, indices)
+    }
+
+    // 描画先に使うテクスチャをサーフェスと同じフォーマットで作る
+    fn create_target_texture(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        usage: wgpu::TextureUsages,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        })
+    }
+
+    // encode_frame と同じだが、internal_resolution が有効なら内部解像度のテクスチャに描いてから view へ引き伸ばして写す
+    // overlay のコールバックも内部解像度のテクスチャのパスで呼ばれる
+    fn encode_output(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        time: f32,
+        instances: std::ops::Range<u32>,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'_>>,
+        overlay: Option<&mut RenderCallback<'window>>,
+    ) {
+        let Some(upscaler) = &self.upscaler else {
+            self.encode_frame(encoder, view, time, instances, timestamp_writes, overlay);
+            return;
+        };
+        self.encode_frame(encoder, upscaler.view(), time, instances, timestamp_writes, overlay);
+        let letterbox = self.letterbox();
+        let bar_color = if letterbox.is_some() {
+            let [r, g, b, a] = self.state_config.letterbox_color.map(|value| value as f64);
+            wgpu::Color { r, g, b, a }
+        } else {
+            wgpu::Color::TRANSPARENT
+        };
+        upscaler.draw(encoder, view, bar_color, letterbox);
+    }
+
+    // time 秒時点の uniform を書き込み、背景と星を view に描くコマンドを積む
+    fn encode_frame(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        time: f32,
+        instances: std::ops::Range<u32>,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'_>>,
+        overlay: Option<&mut RenderCallback<'window>>,
+    ) {
+        let (Some(queue), Some(render_pipeline), Some((uniform_buffer, uniform_bind_group))) =
+            (&self.queue, &self.render_pipeline, self.uniform_slot())
+        else {
+            return;
+        };
+        let (Some(vertex_buffer), Some(index_buffer), Some(instance_buffer)) = (
+            &self.vertex_buffer,
+            &self.index_buffer,
+            self.draw_instance_buffer(),
+        ) else {
+            return;
+        };
+
+        let gradient = self.state_config.spatial_gradient.as_ref();
+        let (gradient_colors, gradient_offsets) = gradient.map(|gradient| gradient.uniform_stops()).unwrap_or_default();
+        self.write_uniforms(queue, uniform_buffer, crate::uniform::Uniforms {
+            projection: self.projection,
+            field_rotation: time * self.field_rotation_speed,
+            doppler_intensity: self.doppler_intensity,
+            line_width: self.state_config.line_width,
+            resolution: self.target_size().map(|length| length as f32),
+            twinkle_speed: self.twinkle_speed,
+            twinkle_amount: self.twinkle_amount,
+            physics_mode: self.state_config.physics_mode.as_uniform(),
+            selected_index: self
+                .selected_instance
+                .and_then(|index| self.instance_slots.get(index).copied())
+                .unwrap_or(crate::uniform::NO_SELECTION),
+            selected_time: self.selected_time,
+            translation_time: self.translation_clock.at(time),
+            rotation_time: self.rotation_clock.at(time),
+            selected_rotation_time: self.selected_rotation_time,
+            radius_pixels: self.state_config.radius_pixels.unwrap_or(0.0),
+            outline_width: self.state_config.outline_style.map_or(0.0, |style| style.width),
+            outline_color: self.state_config.outline_style.map_or([0.0; 4], |style| style.color),
+            gradient_colors,
+            gradient_offsets,
+            gradient_axis: gradient.map_or(0, |gradient| gradient.axis.as_uniform()),
+            gradient_stop_count: gradient.map_or(0, |gradient| gradient.stops.len() as u32),
+            intro_progress: if self.state_config.intro_duration > 0.0 {
+                crate::morph::Easing::EaseOut.apply(time / self.state_config.intro_duration)
+            } else {
+                1.0
+            },
+            intro_edge: self.state_config.intro_edge.as_uniform(),
+            shape_morph: if self.state_config.shape_morph_enabled() {
+                0.5 + 0.5 * (time * self.state_config.shape_morph_rate).sin()
+            } else {
+                0.0
+            },
+            startup_fade: if self.state_config.startup_fade_secs > 0.0 {
+                (time / self.state_config.startup_fade_secs).clamp(0.0, 1.0)
+            } else {
+                1.0
+            },
+            ..crate::uniform::Uniforms::new(time)
+        });
+
+        // グラデーション背景は前段のパスで描き、星はその上に重ねる
+        // aspect_lock の場合は前段のパスで帯の色にクリアし、背景は単色でも矩形の内側だけに描く
+        // 内部解像度のテクスチャにはその全体に描き、帯は引き伸ばして写すときに塗る
+        let letterbox = self.letterbox().filter(|_| self.upscaler.is_none());
+        let load = match &self.background {
+            Some(background) if letterbox.is_some() => {
+                let [r, g, b, a] = self.state_config.letterbox_color.map(|value| value as f64);
+                background.draw_letterboxed(encoder, view, wgpu::Color { r, g, b, a }, letterbox);
+                wgpu::LoadOp::Load
+            }
+            Some(background) if background.is_gradient() => {
+                background.draw(encoder, view);
+                wgpu::LoadOp::Load
+            }
+            _ => {
+                let mut clear_color = self.state_config.background.clear_color();
+                if !self.transparent_surface {
+                    clear_color.a = 1.0;
+                }
+                wgpu::LoadOp::Clear(clear_color)
+            }
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: self.state_config.store_op,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes,
+            occlusion_query_set: None,
+        });
+        if let Some([x, y, width, height]) = letterbox {
+            render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+        }
+
+        if let Some(debug_grid) = &self.debug_grid {
+            debug_grid.draw(&mut render_pass, uniform_bind_group);
+        }
+
+        // 星を結ぶ線は星の後ろに描く。link_buffer に収まらないフレームだけ一時的なバッファを使う
+        if let Some(link_pipeline) = &self.link_pipeline {
+            let link_vertices = self.link_vertices(time);
+            self.link_vertex_count.set(link_vertices.len());
+            let contents: &[u8] = bytemuck::cast_slice(&link_vertices);
+            let size = contents.len() as wgpu::BufferAddress;
+            let temporary_buffer;
+            let link_buffer = match (&self.link_buffer, &self.device) {
+                _ if link_vertices.is_empty() => None,
+                (Some(buffer), _) if buffer.size() >= size => {
+                    queue.write_buffer(buffer, 0, contents);
+                    Some(buffer)
+                }
+                (_, Some(device)) => {
+                    temporary_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("Link Vertex Buffer"),
+                        contents,
+                        usage: wgpu::BufferUsages::VERTEX,
+                    });
+                    Some(&temporary_buffer)
+                }
+                _ => None,
+            };
+            if let Some(link_buffer) = link_buffer {
+                render_pass.set_pipeline(link_pipeline);
+                render_pass.set_bind_group(0, uniform_bind_group, &[0]);
+                render_pass.set_vertex_buffer(0, link_buffer.slice(..size));
+                render_pass.draw(0..link_vertices.len() as u32, 0..1);
+            }
+        }
+
+        // 画面外の星を除く場合も、instance_index (選択や瞬きの種) が変わらないようバッファ上の位置のまま描く
+        let visible = self.visible_slots(time);
+        render_pass.set_pipeline(render_pipeline);
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        // グループと詳細度の段階の組ごとに、グループの値のオフセットと段階のメッシュで担当するインスタンスを描く
+        for (batch_index, batch) in self.instance_batches.iter().enumerate() {
+            let lod = &self.lods[batch.lod];
+            let start = batch.instances.start.max(instances.start);
+            let end = batch.instances.end.min(instances.end);
+            if start >= end || lod.vertex_bytes.is_empty() {
+                continue;
+            }
+            render_pass.set_bind_group(0, uniform_bind_group, &[batch.group as u32 * self.group_stride]);
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(lod.vertex_bytes.clone()));
+            if !self.state_config.outline_mode {
+                let morph_buffer = self.morph_buffer.as_ref().unwrap_or(vertex_buffer);
+                render_pass.set_vertex_buffer(2, morph_buffer.slice(lod.vertex_bytes.clone()));
+            }
+            match (&visible, &self.indirect_buffer) {
+                // 組の全体を描く場合は、indirect_buffer に置いた引数で描く
+                (None, Some(indirect_buffer)) if (start..end) == batch.instances => {
+                    render_pass.draw_indexed_indirect(indirect_buffer, batch_index as u64 * INDIRECT_ARGS_SIZE);
+                }
+                (Some(visible), _) => {
+                    for run in visible_runs(visible, start..end) {
+                        render_pass.draw_indexed(lod.indices.clone(), 0, run);
+                    }
+                }
+                (None, _) => render_pass.draw_indexed(lod.indices.clone(), 0, start..end),
+            }
+        }
+
+        // 縁取りは塗りつぶしの全ての星の後に、同じインスタンスの範囲とグループのオフセットで重ねる
+        if let Some(outline_stroke) = &self.outline_stroke {
+            outline_stroke.bind(&mut render_pass, instance_buffer);
+            for batch in &self.instance_batches {
+                let start = batch.instances.start.max(instances.start);
+                let end = batch.instances.end.min(instances.end);
+                if start >= end {
+                    continue;
+                }
+                render_pass.set_bind_group(0, uniform_bind_group, &[batch.group as u32 * self.group_stride]);
+                match &visible {
+                    Some(visible) => {
+                        for run in visible_runs(visible, start..end) {
+                            outline_stroke.draw(&mut render_pass, run);
+                        }
+                    }
+                    None => outline_stroke.draw(&mut render_pass, start..end),
+                }
+            }
+        }
+
+        if let Some(overlay) = overlay {
+            overlay(&mut render_pass, self);
+        }
+    }
+
+    // frame_slot が指す、このフレームで使う Uniforms のバッファとバインドグループ
+    fn uniform_slot(&self) -> Option<(&wgpu::Buffer, &wgpu::BindGroup)> {
+        match self.frame_slot.checked_sub(1) {
+            None => self.uniform_buffer.as_ref().zip(self.uniform_bind_group.as_ref()),
+            Some(index) => self.uniform_ring.get(index).map(|(buffer, bind_group)| (buffer, bind_group)),
+        }
+    }
+
+    // uniform_updates が OnChange の場合は、前回そのバッファに書き込んだ内容と比べて変わった範囲 (4バイト単位) だけを書き込む
+    // 時刻に関係する値は続けて並んでいるので、射影行列など滅多に変わらない値は毎フレームは送らずに済む
+    fn write_uniforms(&self, queue: &wgpu::Queue, uniform_buffer: &wgpu::Buffer, uniforms: crate::uniform::Uniforms) {
+        let Some(written) = self.written_uniforms.get(self.frame_slot) else {
+            return;
+        };
+        let bytes = bytemuck::bytes_of(&uniforms);
+        match (self.state_config.uniform_updates, written.get()) {
+            (crate::uniform::UniformUpdateMode::OnChange, Some(previous)) => {
+                let previous = bytemuck::bytes_of(&previous);
+                let changed = |(byte, previous): (&u8, &u8)| byte != previous;
+                let Some(first) = bytes.iter().zip(previous).position(changed) else {
+                    return;
+                };
+                let last = bytes.iter().zip(previous).rposition(changed).unwrap_or(first);
+                let (start, end) = (first / 4 * 4, (last / 4 + 1) * 4);
+                queue.write_buffer(uniform_buffer, start as wgpu::BufferAddress, &bytes[start..end]);
+            }
+            _ => queue.write_buffer(uniform_buffer, 0, bytes),
+        }
+        written.set(Some(uniforms));
+    }
+
+    // 1x1 のダミーテクスチャに捨てる描画を一度投げて完了まで待ち、
+    // ドライバによるパイプラインの最終的なコンパイルを最初の表示フレームより前に済ませる
+    // aspect_lock の場合はビューポートが収まるよう、帯の内側の矩形の右下までの大きさにする
+    pub fn warm_up(&self) {
+        let (Some(device), Some(queue), Some(config)) = (&self.device, &self.queue, &self.config) else {
+            return;
+        };
+
+        let (width, height) = self.letterbox().map_or((1, 1), |[x, y, width, height]| (x + width, y + height));
+        let texture =
+            Self::create_target_texture(device, config.format, width, height, wgpu::TextureUsages::RENDER_ATTACHMENT);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Warm Up Encoder"),
+        });
+        self.encode_output(&mut encoder, &view, 0.0, 0..1, None, None);
+        queue.submit(std::iter::once(encoder.finish()));
+        device.poll(wgpu::Maintain::Wait);
+    }
+
+    // 滑らかにしたフレーム間隔 (秒)。まだ計測していなければ目標のフレーム時間を返す
+    // 時間で進める処理をホスト側で積分するときは、生の間隔の代わりにこれを使う
+    pub fn smoothed_delta(&self) -> f32 {
+        self.smoothed_delta.unwrap_or(self.target_frame_time) as f32
+    }
+
+    // アニメーションの時刻を壁時計から切り離して固定する (秒)。None で固定する前の時刻の続きに戻す
+    pub fn set_time(&mut self, time: Option<f32>) {
+        self.fixed_time = time;
+    }
+
+    // 星の移動だけを止める。自転や瞬き、星空全体の回転は進み続ける
+    pub fn freeze_translation(&mut self) {
+        let time = self.current_time();
+        self.translation_clock.pause(time);
+    }
+
+    // freeze_translation で止めた移動を、止めた位置から再開する
+    pub fn resume_translation(&mut self) {
+        let time = self.current_time();
+        self.translation_clock.resume(time);
+    }
+
+    // 星の自転だけを止める。移動は進み続ける
+    pub fn freeze_rotation(&mut self) {
+        let time = self.current_time();
+        self.rotation_clock.pause(time);
+    }
+
+    // freeze_rotation で止めた自転を、止めた向きから再開する
+    pub fn resume_rotation(&mut self) {
+        let time = self.current_time();
+        self.rotation_clock.resume(time);
+    }
+
+    // time 秒時点で描く星の数。population_rate が 0.0 なら全ての星を描く
+    pub fn population(&self, time: f32) -> u32 {
+        let config = &self.state_config;
+        if config.population_rate <= 0.0 {
+            return self.num_instances;
+        }
+        let max = config.population_max.map_or(self.num_instances, |max| max.min(self.num_instances));
+        let min = config.population_min.min(max);
+        let wave = 0.5 * (1.0 + (time * config.population_rate).sin());
+        min + ((max - min) as f32 * wave).round() as u32
+    }
+
+    // static_time を指定した静止画モードかどうか
+    pub fn is_static(&self) -> bool {
+        self.state_config.static_time.is_some()
+    }
+
+    // 時刻が止まっていて、変化があったときだけ描けばよい状態かどうか
+    // (静止画モードか、idle_when_static が有効で set_time により時刻が固定されている)
+    pub fn is_idle(&self) -> bool {
+        self.is_static() || (self.state_config.idle_when_static && self.fixed_time.is_some())
+    }
+
+    // 次のフレームも描く必要があるか。待機中は、描き終わっていない変化が残っている間だけ true
+    // 待機中に外から set_time などで見た目を変えた場合は、呼び出し側で request_redraw() する
+    pub fn needs_redraw(&self) -> bool {
+        !self.is_idle()
+            || !self.is_ready()
+            || self.skip_frames > 0
+            || self.surface_error_count > 0
+            || self.pending_resize.is_some()
+            || self.instances_pending
+            || self.morph.is_some()
+            || self.on_update.is_some()
+    }
+
+    // 再描画を要求する。前の要求がまだ RedrawRequested として届いていなければ重ねて要求しない
+    // 入力やリサイズが1フレームの間に何度届いても、描くのは次の1フレームだけにする
+    pub fn request_redraw(&mut self) {
+        if !self.redraw_requested {
+            self.redraw_requested = true;
+            self.window.request_redraw();
+        }
+    }
+
+    // 前のフレームからの間隔を smoothed_delta に加え、アニメーションの時刻をその平均だけ進める (計算は clock::frame_step)
+    // 平均は目標のフレーム時間から始めるので、最初の間隔が初期化の読み込みを含んでいても長く引きずらない
+    fn advance_animation_time(&mut self, now: Instant) {
+        let Some(animated_at) = self.animated_at.replace(now) else {
+            return;
+        };
+        let interval = now.duration_since(animated_at).as_secs_f64();
+        let config = &self.state_config;
+        let (smoothed_delta, advance) = crate::clock::frame_step(
+            self.smoothed_delta.unwrap_or(self.target_frame_time),
+            interval,
+            config.max_delta as f64,
+            config.delta_smoothing as f64,
+            config.clamp_time_gaps,
+        );
+        self.smoothed_delta = Some(smoothed_delta);
+        self.animation_time += advance;
+    }
+
+    pub(crate) fn current_time(&self) -> f32 {
+        self.fixed_time.unwrap_or(self.animation_time as f32)
+    }
+
+    // デバイスが失われていれば、その理由のメッセージを返す
+    pub fn device_lost(&self) -> Option<String> {
+        self.device_lost.lock().ok().and_then(|lost| lost.clone())
+    }
+
+    // イベントループを回さずに time 秒時点の1フレームをウィンドウと同じ大きさで描き、画像として取り出す
+    // 描画の完了を待つので、サムネイルの生成などに使う。デバイスの喪失やコマンドの失敗は CaptureError として返す
+    // テクスチャとバッファの作成から描画とコピーの投入までをエラースコープで囲み、マップの結果も確かめる
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_at(&self, time: f32) -> Result<image::RgbaImage, crate::error::CaptureError> {
+        use crate::error::CaptureError;
+        let (Some(device), Some(queue), Some(config)) = (&self.device, &self.queue, &self.config) else {
+            return Err(CaptureError::NotReady);
+        };
+        if let Some(message) = self.device_lost() {
+            return Err(CaptureError::DeviceLost(message));
+        }
+        let format = config.format;
+        let (width, height) = (self.size.width.max(1), self.size.height.max(1));
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        let texture = Self::create_target_texture(
+            device,
+            format,
+            width,
+            height,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // バッファへのコピーは1行を256バイト境界に揃える必要がある
+        let bytes_per_pixel = format.block_copy_size(None).unwrap_or(4);
+        let unpadded_bytes_per_row = width * bytes_per_pixel;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let capture_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Capture Encoder"),
+        });
+        self.encode_output(&mut encoder, &view, time, 0..self.population(time), None, None);
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &capture_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+        // ネイティブではスコープのエラーは投入の時点で確定しているので、待たずに取り出せる
+        let scope_errors = [device.pop_error_scope(), device.pop_error_scope()].map(poll_ready);
+        let lost = |fallback: CaptureError| match self.device_lost() {
+            Some(message) => CaptureError::DeviceLost(message),
+            None => fallback,
+        };
+        if let Some(error) = scope_errors.into_iter().flatten().flatten().next() {
+            return Err(lost(CaptureError::Gpu(error.to_string())));
+        }
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let slice = capture_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        match receiver.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => return Err(lost(CaptureError::Map(error))),
+            // マップのコールバックが呼ばれないまま捨てられた
+            Err(_) => return Err(lost(CaptureError::DeviceLost(String::from("capture buffer mapping was dropped")))),
+        }
+
+        // サーフェスのフォーマットが BGRA の場合は RGBA に並べ替える
+        let swap_red_blue = matches!(
+            format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                for pixel in row[..unpadded_bytes_per_row as usize].chunks_exact(bytes_per_pixel as usize) {
+                    if format == Self::HDR_FORMAT {
+                        // 線形の半精度浮動小数点を 1.0 で切り詰め、sRGB の8ビットに変換する
+                        let channels: &[u16] = bytemuck::cast_slice(pixel);
+                        let [r, g, b, a] = [0, 1, 2, 3].map(|index| half_to_f32(channels[index]));
+                        pixels.extend_from_slice(&[
+                            linear_to_srgb8(r),
+                            linear_to_srgb8(g),
+                            linear_to_srgb8(b),
+                            (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+                        ]);
+                    } else if swap_red_blue {
+                        pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                    } else {
+                        pixels.extend_from_slice(pixel);
+                    }
+                }
+            }
+        }
+        capture_buffer.unmap();
+
+        Ok(image::RgbaImage::from_raw(width, height, pixels).unwrap())
+    }
+
+    // ウィンドウの大きさに関係なく、time 秒時点の1フレームを width x height のオフスクリーンテクスチャに描いて取り出す
+    // 投影と resolution をその大きさで作り直して描くので、印刷用の大きな書き出しでも画面と同じ構図になる
+    // アダプタの max_texture_dimension_2d を超える大きさは切り詰める。失敗は render_at と同じく CaptureError として返す
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn capture_hires(
+        &mut self,
+        width: u32,
+        height: u32,
+        time: f32,
+    ) -> Result<image::RgbaImage, crate::error::CaptureError> {
+        let Some(device) = &self.device else {
+            return Err(crate::error::CaptureError::NotReady);
+        };
+        let max_dimension = device.limits().max_texture_dimension_2d;
+        if width > max_dimension || height > max_dimension {
+            log::warn!("capture size {width}x{height} exceeds max_texture_dimension_2d {max_dimension}; clamping");
+        }
+        let target = winit::dpi::PhysicalSize::new(width.clamp(1, max_dimension), height.clamp(1, max_dimension));
+
+        // render_at はサーフェスの大きさで描くので、一時的に大きさと投影を差し替える
+        // internal_resolution の引き伸ばしも外して、求められた大きさで直接描く
+        let (size, camera, projection) = (self.size, self.camera, self.projection);
+        let upscaler = self.upscaler.take();
+        self.size = target;
+        self.update_projection();
+        let image = self.render_at(time);
+        self.upscaler = upscaler;
+        self.size = size;
+        self.camera = camera;
+        self.projection = projection;
+        image
+    }
+
+    // 現在の時刻 (set_time で固定しておく) の1フレームをオフスクリーンで描き、画素のハッシュを返す
+    // ドライバごとの丸めの差で値が変わらないよう、各チャンネルを16段階に量子化してから FNV-1a でハッシュする
+    // 固定の seed と時刻で得た値を同じ環境で描き直した値と比べ、描画の回帰を検出する用途を想定している
+    // 描画に失敗した場合は render_at と同じく CaptureError を返す
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn frame_hash(&self) -> Result<u64, crate::error::CaptureError> {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+        let image = self.render_at(self.current_time())?;
+        let mut hash = FNV_OFFSET_BASIS;
+        for value in image.as_raw() {
+            hash ^= (value >> 4) as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+        Ok(hash)
+    }
+
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.apply_pending_resize();
+        if self.skip_frames > 0 {
+            self.skip_frames -= 1;
+            return Ok(());
+        }
+
+        let render_before_time = Instant::now();
+        // Lost / Outdated ならサーフェスを設定し直してこのフレームは飛ばし、次のフレームで取り直す
+        // MAX_SURFACE_RETRIES フレーム続けて失敗したらエラーを返し、数え直す
+        let output = match self.surface.get_current_texture() {
+            Ok(output) => {
+                self.surface_error_count = 0;
+                output
+            }
+            Err(error @ (wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated)) => {
+                self.surface_error_count += 1;
+                if self.surface_error_count > Self::MAX_SURFACE_RETRIES {
+                    log::error!(
+                        "Surface is still {error:?} after {} frames of reconfiguration; giving up",
+                        Self::MAX_SURFACE_RETRIES
+                    );
+                    self.surface_error_count = 0;
+                    return Err(error);
+                }
+                self.recover_surface();
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        };
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // 書き込み側に新しいインスタンスがあれば、このフレームから読み込み側として使う
+        if self.instances_pending {
+            self.instance_read_index = 1 - self.instance_read_index;
+            self.instances_pending = false;
+        }
+
+        // パネルの変更は星を描く前に適用する
+        #[cfg(feature = "egui")]
+        let panel_frame = self.run_tuning_panel();
+        self.advance_animation_time(render_before_time);
+        let time = self.current_time();
+        // コールバックに &self を渡すため、呼び出しの間だけ取り出しておく
+        let mut on_render = self.on_render.take();
+        if let (Some(queue), Some(device)) = (&self.queue, &self.device) {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            self.encode_output(
+                &mut encoder,
+                &view,
+                time,
+                0..self.population(time),
+                self.gpu_timer.as_ref().map(|timer| timer.timestamp_writes()),
+                on_render.as_mut(),
+            );
+            #[cfg(feature = "egui")]
+            let panel_buffers = match (&mut self.tuning_panel, panel_frame) {
+                (Some(panel), Some(frame)) => panel.paint(device, queue, &mut encoder, &view, frame),
+                _ => Vec::new(),
+            };
+            #[cfg(not(feature = "egui"))]
+            let panel_buffers = Vec::new();
+            if let Some(timer) = &self.gpu_timer {
+                timer.resolve(&mut encoder);
+            }
+            queue.submit(panel_buffers.into_iter().chain(std::iter::once(encoder.finish())));
+            // 次のフレームは別の Uniforms のバッファに書き込み、このフレームの読み込みを待たずに済むようにする
+            self.frame_slot = (self.frame_slot + 1) % (self.uniform_ring.len() + 1);
+        }
+        self.on_render = on_render;
+        // 線の頂点が link_buffer に収まらなかった場合は、次のフレームに向けて大きく作り直す
+        if let (Some(device), true) = (&self.device, self.link_pipeline.is_some()) {
+            let required = (self.link_vertex_count.get() * std::mem::size_of::<crate::link::LinkVertex>()) as wgpu::BufferAddress;
+            if self.link_buffer.as_ref().is_none_or(|buffer| buffer.size() < required) {
+                self.link_buffer = Some(crate::link::create_link_buffer(device, self.link_vertex_count.get()));
+            }
+        }
+        let gpu_sampled = match (&self.device, &mut self.gpu_timer) {
+            (Some(device), Some(timer)) => timer.read_back(device),
+            _ => false,
+        };
+
+        output.present();
+        // 落ちたフレームは frame_stats_enabled に関係なく数える (dropped_frame_count で読める)
+        if let Some(last_frame_at) = self.last_frame_at {
+            let interval = render_before_time.duration_since(last_frame_at).as_secs_f64();
+            self.frame_stats.record_interval(interval, self.target_frame_time);
+        }
+        // 次のフレームを続けて描かない (待機に入る) なら、次に描くまでの待ち時間は落ちたフレームとして数えない
+        self.last_frame_at = self.needs_redraw().then_some(render_before_time);
+        if self.state_config.frame_stats_enabled {
+            let render_after_time = Instant::now();
+            let render_time = render_after_time.duration_since(render_before_time).as_secs_f64();
+            self.frame_stats.update(render_time);
+            if self.frame_stats.frame_count.is_multiple_of(60) {
+                self.frame_stats.display_stats(&self.memory_report());
+            }
+        }
+        // 読み戻しは非同期で毎フレームは記録されないので、記録したフレームでだけ 60 回ごとに表示する
+        if let (Some(timer), true) = (&self.gpu_timer, gpu_sampled) {
+            if timer.stats.frame_count > 0 && timer.stats.frame_count.is_multiple_of(60) {
+                timer.display_stats();
+            }
+        }
+        Ok(())
+    }
+}
+
+// indirect_buffer の1組分の描画引数の大きさ
+const INDIRECT_ARGS_SIZE: wgpu::BufferAddress = std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as wgpu::BufferAddress;
+
+// 映らない星が間にこの数以下しかなければ、描画の呼び出しを減らすために前後の範囲とまとめて描く
+const CULLING_MERGE_GAP: u32 = 16;
+
+// set_instances_from_iter でイテレータから一度に取り出してバッファへ書き込むインスタンスの数
+const INSTANCE_STREAM_CHUNK: usize = 4096;
+
+// range の中で visible が true の位置を、連続した (CULLING_MERGE_GAP までの隙間はまとめた) 範囲に分ける
+fn visible_runs(visible: &[bool], range: std::ops::Range<u32>) -> Vec<std::ops::Range<u32>> {
+    let mut runs: Vec<std::ops::Range<u32>> = Vec::new();
+    for slot in range.filter(|slot| visible.get(*slot as usize).is_some_and(|visible| *visible)) {
+        match runs.last_mut() {
+            Some(run) if slot - run.end <= CULLING_MERGE_GAP => run.end = slot + 1,
+            _ => runs.push(slot..slot + 1),
+        }
+    }
+    runs
+}
+
+// 待たずに完了している future の結果を取り出す。まだ完了していなければ None
+#[cfg(not(target_arch = "wasm32"))]
+fn poll_ready<F: std::future::Future>(future: F) -> Option<F::Output> {
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    match std::pin::pin!(future).poll(&mut context) {
+        std::task::Poll::Ready(output) => Some(output),
+        std::task::Poll::Pending => None,
+    }
+}
+
+// IEEE 754 の半精度浮動小数点のビット列を f32 に変換する
+#[cfg(not(target_arch = "wasm32"))]
+fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn linear_to_srgb8(value: f32) -> u8 {
+    let value = if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) };
+    let encoded = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_triangles_face_forward_for_any_skip() {
+        for (points, skip) in [(5, 2), (5, 3), (7, 2), (7, 3), (6, 2), (8, 2), (9, 3)] {
+            for inverted in [false, true] {
+                let state_config = WgpuStateConfig::default().with_star(points, skip).with_inverted(inverted);
+                let (vertices, indices) = WgpuState::create_star_vertices(&state_config, 0);
+                assert_eq!(vertices.len(), points as usize + 1, "{{{points}/{skip}}}");
+                assert_eq!(indices.len(), 3 * points as usize, "{{{points}/{skip}}}");
+                assert!(indices.iter().all(|index| (*index as usize) < vertices.len()));
+                assert_eq!(crate::vertex::validate_winding(&vertices, &indices), Ok(()), "{{{points}/{skip}}}");
+            }
+        }
+    }
+
+    #[test]
+    fn custom_shader_entry_points_come_from_the_parsed_module() {
+        let source = "
+            // fn vertexMain( はコメントの中なので数えない
+            fn fragmentMain() -> vec4<f32> { return vec4<f32>(1.0); }
+            @vertex fn vertexLine() -> @builtin(position) vec4<f32> { return vec4<f32>(0.0); }
+        ";
+        let check = |entry_point, stage| WgpuState::check_entry_point(source, entry_point, stage);
+        assert!(check("vertexLine", naga::ShaderStage::Vertex).is_ok());
+        assert!(matches!(check("vertexMain", naga::ShaderStage::Vertex), Err(WgpuStateError::MissingEntryPoint(_))));
+        assert!(matches!(check("fragmentMain", naga::ShaderStage::Fragment), Err(WgpuStateError::MissingEntryPoint(_))));
+        assert!(matches!(check("vertexLine", naga::ShaderStage::Fragment), Err(WgpuStateError::MissingEntryPoint(_))));
+        assert!(matches!(
+            WgpuState::check_entry_point("fn broken(", "vertexMain", naga::ShaderStage::Vertex),
+            Err(WgpuStateError::ShaderCompilation(_))
+        ));
+        let bundled = include_str!("shader.wgsl");
+        for (entry_point, stage) in [
+            ("vertexMain", naga::ShaderStage::Vertex),
+            ("vertexLine", naga::ShaderStage::Vertex),
+            ("fragmentMain", naga::ShaderStage::Fragment),
+            ("fragmentPoint", naga::ShaderStage::Fragment),
+            ("fragmentLine", naga::ShaderStage::Fragment),
+        ] {
+            assert!(WgpuState::check_entry_point(bundled, entry_point, stage).is_ok(), "{entry_point}");
+        }
+    }
+
+    // 固定の seed と時刻で描いたフレームのハッシュが、同じ入力なら一致し、seed や時刻を変えると変わることを確かめる
+    // ドライバごとに値が違い得るので、期待値のハッシュ自体は置いていない
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore = "needs a GPU and a display"]
+    async fn frame_hash_is_deterministic() {
+        use winit::platform::x11::EventLoopBuilderExtX11;
+        let event_loop = winit::event_loop::EventLoopBuilder::new().with_any_thread(true).build().unwrap();
+        let window = winit::window::WindowBuilder::new()
+            .with_visible(false)
+            .with_inner_size(winit::dpi::PhysicalSize::new(256, 256))
+            .build(&event_loop)
+            .unwrap();
+        let mut state = WgpuState::with_config(&window, WgpuStateConfig::default()).await.unwrap();
+        state.regenerate_field(42);
+        state.set_time(Some(1.5));
+
+        let hash = state.frame_hash().unwrap();
+        assert_eq!(state.frame_hash().unwrap(), hash, "the same frame must hash the same");
+
+        state.set_time(Some(3.0));
+        assert_ne!(state.frame_hash().unwrap(), hash, "a different time must change the hash");
+
+        state.regenerate_field(7);
+        state.set_time(Some(1.5));
+        assert_ne!(state.frame_hash().unwrap(), hash, "a different seed must change the hash");
+
+        state.regenerate_field(42);
+        assert_eq!(state.frame_hash().unwrap(), hash, "regenerating the same seed must restore the hash");
+    }
+}
@@ -0,0 +1,335 @@
+use winit::event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::background::BackgroundMode;
+use crate::physics::PhysicsMode;
+use crate::state::WgpuState;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+// 星空の上に重ねて描く、設定をその場で調整するための egui のパネル
+// WgpuState::enable_tuning_panel で作り、F1 キーで表示を切り替える
+// egui-winit は winit 0.30 向けなので使わず、必要なイベントだけをここで egui の入力に変換する
+pub struct TuningPanel {
+    context: egui::Context,
+    renderer: egui_wgpu::Renderer,
+    // 次のフレームで egui に渡す入力 (ウィンドウイベントから変換したもの)
+    input: egui::RawInput,
+    pointer: egui::Pos2,
+    modifiers: egui::Modifiers,
+    start_time: Instant,
+    pub visible: bool,
+}
+
+// テッセレーション済みの1フレーム分のパネルの描画内容
+pub(crate) struct PanelFrame {
+    primitives: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    screen: egui_wgpu::ScreenDescriptor,
+}
+
+impl TuningPanel {
+    pub(crate) fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        Self {
+            context: egui::Context::default(),
+            renderer: egui_wgpu::Renderer::new(device, format, None, 1, false),
+            input: egui::RawInput::default(),
+            pointer: egui::Pos2::ZERO,
+            modifiers: egui::Modifiers::default(),
+            start_time: Instant::now(),
+            visible: true,
+        }
+    }
+
+    // ウィンドウイベントを egui の入力として溜める。パネルが操作に使った場合は true を返し、星空側には渡さない
+    pub(crate) fn on_window_event(&mut self, event: &WindowEvent, pixels_per_point: f32) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state: ElementState::Pressed,
+                    physical_key: PhysicalKey::Code(KeyCode::F1),
+                    repeat: false,
+                    ..
+                },
+                ..
+            } => {
+                self.visible = !self.visible;
+                true
+            }
+            _ if !self.visible => false,
+            WindowEvent::ModifiersChanged(modifiers) => {
+                let state = modifiers.state();
+                self.modifiers = egui::Modifiers {
+                    alt: state.alt_key(),
+                    ctrl: state.control_key(),
+                    shift: state.shift_key(),
+                    mac_cmd: cfg!(target_os = "macos") && state.super_key(),
+                    command: if cfg!(target_os = "macos") { state.super_key() } else { state.control_key() },
+                };
+                false
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.pointer = egui::pos2(position.x as f32 / pixels_per_point, position.y as f32 / pixels_per_point);
+                self.input.events.push(egui::Event::PointerMoved(self.pointer));
+                // スライダーをドラッグしている間は星空をパンしない
+                self.context.is_using_pointer()
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.input.events.push(egui::Event::PointerGone);
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => egui::PointerButton::Primary,
+                    MouseButton::Right => egui::PointerButton::Secondary,
+                    MouseButton::Middle => egui::PointerButton::Middle,
+                    _ => return false,
+                };
+                let pressed = *state == ElementState::Pressed;
+                self.input.events.push(egui::Event::PointerButton {
+                    pos: self.pointer,
+                    button,
+                    pressed,
+                    modifiers: self.modifiers,
+                });
+                // 離したときは星空側のドラッグの状態も戻す必要があるので、横取りしない
+                pressed && self.context.wants_pointer_input()
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (unit, delta) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (egui::MouseWheelUnit::Line, egui::vec2(*x, *y)),
+                    MouseScrollDelta::PixelDelta(delta) => (
+                        egui::MouseWheelUnit::Point,
+                        egui::vec2(delta.x as f32, delta.y as f32) / pixels_per_point,
+                    ),
+                };
+                self.input.events.push(egui::Event::MouseWheel { unit, delta, modifiers: self.modifiers });
+                self.context.is_pointer_over_area()
+            }
+            _ => false,
+        }
+    }
+
+    // 溜めた入力に画面の大きさと時刻を添えて取り出す
+    fn take_input(&mut self, size_in_pixels: [u32; 2], pixels_per_point: f32) -> egui::RawInput {
+        let mut input = std::mem::take(&mut self.input);
+        input.screen_rect = Some(egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(size_in_pixels[0] as f32, size_in_pixels[1] as f32) / pixels_per_point,
+        ));
+        input.time = Some(self.start_time.elapsed().as_secs_f64());
+        input.modifiers = self.modifiers;
+        input.viewports.entry(input.viewport_id).or_default().native_pixels_per_point = Some(pixels_per_point);
+        input
+    }
+
+    // 星のパスの後に、同じテクスチャへ重ねてパネルを描く
+    // 描画の前に送る必要のあるコマンドバッファを返すので、encoder より先に submit する
+    pub(crate) fn paint(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        frame: PanelFrame,
+    ) -> Vec<wgpu::CommandBuffer> {
+        for (id, delta) in &frame.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        let command_buffers =
+            self.renderer.update_buffers(device, queue, encoder, &frame.primitives, &frame.screen);
+
+        let mut render_pass = encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Tuning Panel Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            })
+            .forget_lifetime();
+        self.renderer.render(&mut render_pass, &frame.primitives, &frame.screen);
+        drop(render_pass);
+
+        for id in &frame.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+        command_buffers
+    }
+}
+
+impl WgpuState<'_> {
+    // 設定を調整するパネルを有効にする (run() は egui フィーチャが有効なら最初から表示する)
+    pub fn enable_tuning_panel(&mut self) {
+        if let (Some(device), Some(config)) = (&self.device, &self.config) {
+            self.tuning_panel = Some(TuningPanel::new(device, config.format));
+        }
+    }
+
+    // パネルの UI を組み立てて変更をその場で適用し、描画内容を返す。パネルが無いか非表示なら None
+    pub(crate) fn run_tuning_panel(&mut self) -> Option<PanelFrame> {
+        // UI の中で self を書き換えるため、組み立てる間だけ取り出しておく
+        let mut panel = self.tuning_panel.take()?;
+        let frame = panel.visible.then(|| {
+            let size_in_pixels = self.config.as_ref().map_or([1, 1], |config| [config.width, config.height]);
+            let input = panel.take_input(size_in_pixels, self.window.scale_factor() as f32);
+            let output = panel.context.clone().run(input, |context| self.tuning_ui(context));
+            PanelFrame {
+                primitives: panel.context.tessellate(output.shapes, output.pixels_per_point),
+                textures_delta: output.textures_delta,
+                screen: egui_wgpu::ScreenDescriptor { size_in_pixels, pixels_per_point: output.pixels_per_point },
+            }
+        });
+        self.tuning_panel = Some(panel);
+        frame
+    }
+
+    fn tuning_ui(&mut self, context: &egui::Context) {
+        egui::Window::new("Pentagrams").default_pos([12.0, 12.0]).show(context, |ui| {
+            let max_count = self.max_instances().min(WgpuState::STAR_INSTANCE_COUNT as usize * 10);
+            let mut count = self.instances.len();
+            if ui.add(egui::Slider::new(&mut count, 0..=max_count).text("stars")).changed() {
+                self.resize_field(count);
+            }
+            let mut spin_bias = self.state_config.spin_bias;
+            ui.add(egui::Slider::new(&mut spin_bias, 0.0..=1.0).text("spin bias"));
+            self.state_config.spin_bias = spin_bias;
+            if ui.button("Regenerate").clicked() {
+                let seed = self.start_time.map_or(0, |start_time| start_time.elapsed().as_nanos() as u64);
+                self.regenerate_field(seed);
+            }
+
+            ui.separator();
+            self.shape_ui(ui);
+
+            ui.separator();
+            let (mut doppler, mut rotation) = (self.doppler_intensity, self.field_rotation_speed);
+            let (mut twinkle_speed, mut twinkle_amount) = (self.twinkle_speed, self.twinkle_amount);
+            ui.add(egui::Slider::new(&mut doppler, 0.0..=1.0).text("doppler"));
+            ui.add(egui::Slider::new(&mut rotation, -1.0..=1.0).text("field rotation"));
+            ui.add(egui::Slider::new(&mut twinkle_speed, 0.0..=5.0).text("twinkle speed"));
+            ui.add(egui::Slider::new(&mut twinkle_amount, 0.0..=1.0).text("twinkle amount"));
+            self.set_doppler_intensity(doppler);
+            self.set_field_rotation_speed(rotation);
+            self.set_twinkle(twinkle_speed, twinkle_amount);
+
+            let mut physics_mode = self.state_config.physics_mode;
+            egui::ComboBox::from_label("edges")
+                .selected_text(format!("{physics_mode:?}"))
+                .show_ui(ui, |ui| {
+                    for mode in [PhysicsMode::Wrap, PhysicsMode::Bounce, PhysicsMode::None] {
+                        ui.selectable_value(&mut physics_mode, mode, format!("{mode:?}"));
+                    }
+                });
+            self.set_physics_mode(physics_mode);
+            let (mut collisions, mut restitution) = (self.state_config.collisions, self.state_config.restitution);
+            ui.checkbox(&mut collisions, "collisions");
+            ui.add(egui::Slider::new(&mut restitution, 0.0..=1.0).text("restitution"));
+            self.set_collisions(collisions, restitution);
+
+            ui.separator();
+            self.color_ui(ui);
+
+            if let Some(current) = self.config.as_ref().map(|config| config.present_mode) {
+                let mut present_mode = current;
+                egui::ComboBox::from_label("present mode")
+                    .selected_text(format!("{present_mode:?}"))
+                    .show_ui(ui, |ui| {
+                        for mode in self.present_modes.clone() {
+                            ui.selectable_value(&mut present_mode, mode, format!("{mode:?}"));
+                        }
+                    });
+                if present_mode != current {
+                    self.set_present_mode(present_mode);
+                }
+            }
+        });
+    }
+
+    // 星の形と描画モード。変わった場合は検証してから頂点バッファとパイプラインを作り直す
+    fn shape_ui(&mut self, ui: &mut egui::Ui) {
+        let mut state_config = self.state_config.clone();
+        ui.add(egui::Slider::new(&mut state_config.star_points, 3..=16).text("points"));
+        state_config.star_skip = state_config.star_skip.clamp(2, state_config.star_points - 1);
+        ui.add(egui::Slider::new(&mut state_config.star_skip, 2..=state_config.star_points - 1).text("skip"));
+        ui.add(egui::Slider::new(&mut state_config.subdivisions, 0..=8).text("subdivisions"));
+        ui.checkbox(&mut state_config.point_mode, "point mode");
+        ui.checkbox(&mut state_config.outline_mode, "outline mode");
+        ui.add(egui::Slider::new(&mut state_config.line_width, 0.5..=10.0).text("line width"));
+        ui.add(egui::Slider::new(&mut state_config.link_distance, 0.0..=0.5).text("link distance"));
+
+        let shape = |config: &crate::config::WgpuStateConfig| {
+            (config.star_points, config.star_skip, config.subdivisions, config.point_mode, config.outline_mode)
+        };
+        let geometry_changed = shape(&state_config) != shape(&self.state_config);
+        let pipeline_changed = geometry_changed
+            || (state_config.link_distance > 0.0) != (self.state_config.link_distance > 0.0);
+        let lines_changed = (state_config.line_width, state_config.link_distance)
+            != (self.state_config.line_width, self.state_config.link_distance);
+        if !(geometry_changed || pipeline_changed || lines_changed) {
+            return;
+        }
+        if let Err(error) = state_config.validate() {
+            log::warn!("Ignoring the panel change: {error}");
+            return;
+        }
+        self.state_config = state_config;
+        if geometry_changed {
+            self.rebuild_geometry();
+        }
+        if pipeline_changed {
+            self.rebuild_pipeline();
+        }
+    }
+
+    // 単色の背景と星を結ぶ線の色
+    fn color_ui(&mut self, ui: &mut egui::Ui) {
+        if let BackgroundMode::Solid(mut color) = self.state_config.background {
+            ui.horizontal(|ui| {
+                ui.color_edit_button_rgba_unmultiplied(&mut color);
+                ui.label("background");
+            });
+            if BackgroundMode::Solid(color) != self.state_config.background {
+                self.set_background_mode(BackgroundMode::Solid(color));
+            }
+        }
+        ui.horizontal(|ui| {
+            ui.color_edit_button_rgba_unmultiplied(&mut self.state_config.link_color);
+            ui.label("links");
+        });
+    }
+
+    // 今の配置を保ったまま星の数を count にする。足りない分は新しく生成して足す
+    fn resize_field(&mut self, count: usize) {
+        let mut rng = crate::instance::field_rng();
+        let mut instances = self.instances.clone();
+        let existing = instances.len().min(count);
+        while instances.len() < count {
+            instances.extend(crate::instance::create_star_instances_with_params(
+                &mut rng,
+                &self.state_config.instance_params,
+                self.state_config.spin_bias,
+                self.state_config.spawn_margin,
+            ));
+        }
+        instances.truncate(count);
+        crate::instance::jitter_star_centers(&mut instances[existing..], &mut rng, self.state_config.center_jitter);
+        if self.selected_instance.is_some_and(|index| index >= count) {
+            self.select_instance(None);
+        }
+        if let Err(error) = self.set_instances(&instances) {
+            log::error!("Failed to resize the star field: {error}");
+        }
+    }
+}
@@ -1,22 +1,196 @@
-use std::num::NonZero;
-
-use wgpu::BufferSize;
-
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Uniforms {
+    // アスペクト比を補正する正射影行列 (列優先)
+    pub projection: [[f32; 4]; 4],
     pub time: f32,
-    #[cfg(target_arch = "wasm32")]
-    pub resolution: [u8; 28],
+    // 画面中心まわりの星空全体の回転角 (ラジアン)
+    // projection でアスペクト比を補正しているので、正方形でないウィンドウでも歪まずに回転する
+    pub field_rotation: f32,
+    // 速度による色変化 (疑似ドップラー効果) の強さ。0.0 で無効
+    pub doppler_intensity: f32,
+    // 輪郭線の太さ (ピクセル)
+    pub line_width: f32,
+    // サーフェスの解像度 (ピクセル)
+    pub resolution: [f32; 2],
+    // 星の瞬きの速さ (1秒あたりの明るさの変化回数の目安)
+    pub twinkle_speed: f32,
+    // 瞬きで明るさを落とす最大の割合。0.0 で無効
+    pub twinkle_amount: f32,
+    // 起動直後のフェードイン中の全体の不透明度 (0.0〜1.0)
+    pub startup_fade: f32,
+    // 画面端での振る舞い (PhysicsMode::as_uniform の値)
+    pub physics_mode: u32,
+    // クリックで選んだ星のインスタンスバッファ上の位置 (NO_SELECTION で選択なし)
+    // この星だけ移動の時刻 selected_time と自転の時刻 selected_rotation_time の時点で止めて描き、色を変える
+    pub selected_index: u32,
+    pub selected_time: f32,
+    // 登場演出の進み具合 (イージング適用済み、1.0 で完了) と、飛んでくる方向 (IntroEdge::as_uniform の値)
+    pub intro_progress: f32,
+    pub intro_edge: u32,
+    // 星形とその補間先の形の間の割合 (0.0 で星形、1.0 で補間先)
+    pub shape_morph: f32,
+    // 星の移動と自転に使う時刻 (freeze_translation / freeze_rotation で別々に止まる)
+    // time は瞬きや星空全体の回転などに使い、これらを止めても進み続ける
+    pub translation_time: f32,
+    pub rotation_time: f32,
+    // 選択中の星を止めた時点の自転の時刻 (selected_time は移動の時刻)
+    pub selected_rotation_time: f32,
+    // 星の形の長さ 1.0 を何ピクセルとして描くか。0.0 の場合は星空の座標のまま描く
+    pub radius_pixels: f32,
+    // 塗りつぶしの上に重ねる縁取り (outline_style) の太さ (ピクセル)
+    pub outline_width: f32,
+    // 縁取りの色 (線形の RGBA)
+    pub outline_color: [f32; 4],
+    // spatial_gradient の色 (線形の RGB、a は使わない) と位置。使わない分は最後の色と位置で埋める
+    pub gradient_colors: [[f32; 4]; crate::gradient::MAX_GRADIENT_STOPS],
+    pub gradient_offsets: [f32; crate::gradient::MAX_GRADIENT_STOPS],
+    // GradientAxis::as_uniform の値と、色の数 (0 で spatial_gradient を使わない)
+    pub gradient_axis: u32,
+    pub gradient_stop_count: u32,
+    // 構造体のサイズを16バイト境界に揃える
+    pub gradient_padding: [u32; 2],
+}
+
+pub const IDENTITY_MATRIX: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+const GROUP_UNIFORMS_SIZE: u64 = std::mem::size_of::<crate::group::GroupUniforms>() as u64;
+
+// 動的オフセットで1グループ分ずつ読むので、バインドする大きさは1グループ分にする
+fn group_binding(group_buffer: &wgpu::Buffer) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding: 1,
+        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: group_buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(GROUP_UNIFORMS_SIZE),
+        }),
+    }
+}
+
+// WgpuStateConfig::uniform_updates で選ぶ、毎フレームの Uniforms の書き込み方
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UniformUpdateMode {
+    // 毎フレーム全体を書き込む
+    EveryFrame,
+    // 前のフレームから変わった範囲だけを書き込み、何も変わっていなければ書き込まない
+    #[default]
+    OnChange,
+}
+
+// Uniforms::selected_index で星を選んでいないことを表す値
+pub const NO_SELECTION: u32 = u32::MAX;
+
+// パイプラインの表面とみなす巻き方向 (裏面はカリングされる)
+pub const FRONT_FACE: wgpu::FrontFace = wgpu::FrontFace::Ccw;
+
+// wgpu の深度範囲 (0.0〜1.0) に合わせた正射影行列
+pub fn orthographic_projection(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> [[f32; 4]; 4] {
+    [
+        [2.0 / (right - left), 0.0, 0.0, 0.0],
+        [0.0, 2.0 / (top - bottom), 0.0, 0.0],
+        [0.0, 0.0, 1.0 / (far - near), 0.0],
+        [
+            -(right + left) / (right - left),
+            -(top + bottom) / (top - bottom),
+            -near / (far - near),
+            1.0,
+        ],
+    ]
+}
+
+// 複数のウィンドウで1枚の仮想キャンバスを分担するときに、キャンバス全体のクリップ座標から
+// offset (左上, ピクセル) から size の大きさの部分をこのウィンドウのクリップ座標に引き伸ばす行列
+pub fn viewport_slice(total: [u32; 2], offset: [u32; 2], size: [u32; 2]) -> [[f32; 4]; 4] {
+    let (total_width, total_height) = (total[0].max(1) as f32, total[1].max(1) as f32);
+    // キャンバスのピクセル座標 (y は下向き) をキャンバス全体のクリップ座標に変換する
+    let clip_x = |x: u32| -1.0 + 2.0 * x as f32 / total_width;
+    let clip_y = |y: u32| 1.0 - 2.0 * y as f32 / total_height;
+    // 深度はそのまま通す
+    orthographic_projection(
+        clip_x(offset[0]),
+        clip_x(offset[0] + size[0].max(1)),
+        clip_y(offset[1] + size[1].max(1)),
+        clip_y(offset[1]),
+        0.0,
+        1.0,
+    )
+}
+
+// 列優先の行列の積 a * b
+pub fn multiply_matrices(a: [[f32; 4]; 4], b: [[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let mut result = [[0.0; 4]; 4];
+    for (column, b_column) in result.iter_mut().zip(b.iter()) {
+        for (row, value) in column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b_column[k]).sum();
+        }
+    }
+    result
+}
+
+// 同梱シェーダが線形の色に sRGB のガンマを掛けてから出力するか
+// sRGB のフォーマットはハードウェアが変換し、HDR の浮動小数点フォーマットは線形のまま表示されるので掛けない
+pub(crate) fn shader_encodes_srgb(format: wgpu::TextureFormat) -> bool {
+    !format.is_srgb() && format != crate::state::WgpuState::HDR_FORMAT
+}
+
+// 星形のパイプラインが読むインスタンスバッファの頂点属性の並びと、不透明度が1未満のインスタンスが含まれるか
+pub(crate) struct InstanceLayout {
+    pub(crate) buffer_layout: wgpu::VertexBufferLayout<'static>,
+    pub(crate) translucent: bool,
+}
+
+// Camera2D::view_matrix で1ピクセルが星空の座標でどれだけの長さになるか
+pub fn world_units_per_pixel(width: u32, height: u32, zoom: f32) -> f32 {
+    2.0 / width.min(height).max(1) as f32 / zoom
+}
+
+#[cfg(feature = "glam")]
+impl Uniforms {
+    pub fn projection_matrix(&self) -> glam::Mat4 {
+        glam::Mat4::from_cols_array_2d(&self.projection)
+    }
+
+    pub fn set_projection_matrix(&mut self, projection: glam::Mat4) {
+        self.projection = projection.to_cols_array_2d();
+    }
 }
 
 impl Uniforms {
     pub fn new(time: f32) -> Self {
-        return Self {
+        Self {
+            projection: IDENTITY_MATRIX,
             time,
-            #[cfg(target_arch = "wasm32")]
-            resolution: [0; 28]
-        };
+            field_rotation: 0.0,
+            doppler_intensity: 0.0,
+            line_width: 1.0,
+            resolution: [1.0, 1.0],
+            twinkle_speed: 1.0,
+            twinkle_amount: 0.0,
+            startup_fade: 1.0,
+            physics_mode: 0,
+            selected_index: NO_SELECTION,
+            selected_time: 0.0,
+            intro_progress: 1.0,
+            intro_edge: 0,
+            shape_morph: 0.0,
+            translation_time: time,
+            rotation_time: time,
+            selected_rotation_time: 0.0,
+            radius_pixels: 0.0,
+            outline_width: 0.0,
+            outline_color: [0.0; 4],
+            gradient_colors: [[0.0; 4]; crate::gradient::MAX_GRADIENT_STOPS],
+            gradient_offsets: [0.0; crate::gradient::MAX_GRADIENT_STOPS],
+            gradient_axis: 0,
+            gradient_stop_count: 0,
+            gradient_padding: [0; 2],
+        }
     }
 
     pub fn get_uniform_buffer(device: &wgpu::Device) -> wgpu::Buffer {
@@ -30,93 +204,208 @@ impl Uniforms {
         )
     }
 
-    pub fn get_uniform_bind_groups(device: &wgpu::Device,uniform_buffer: &wgpu::Buffer) -> (
+    // binding 1 はグループごとの値で、描画ごとに動的オフセットで読む位置を切り替える
+    // どちらもフラグメントシェーダから読めるようにして、時刻や色合いで色を変えるカスタムシェーダにも使えるようにする
+    pub fn get_uniform_bind_groups(device: &wgpu::Device,uniform_buffer: &wgpu::Buffer, group_buffer: &wgpu::Buffer) -> (
         wgpu::BindGroupLayout,
         wgpu::BindGroup
     ){
         let uniform_bind_group_layout = device.create_bind_group_layout(
             &wgpu::BindGroupLayoutDescriptor {
                 label: Some("uniform_bind_group_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None
+                        },
+                        count: None
                     },
-                    count: None
-                }]
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(GROUP_UNIFORMS_SIZE)
+                        },
+                        count: None
+                    }
+                ]
             }
         );
         
-        let uniform_bind_group = device.create_bind_group(
+        let uniform_bind_group =
+            Self::create_uniform_bind_group(device, &uniform_bind_group_layout, uniform_buffer, group_buffer);
+
+        (uniform_bind_group_layout, uniform_bind_group)
+    }
+
+    // get_uniform_bind_groups で作ったレイアウトで、別の Uniforms のバッファを読むバインドグループを作る
+    pub(crate) fn create_uniform_bind_group(
+        device: &wgpu::Device,
+        uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        group_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(
             &wgpu::BindGroupDescriptor {
                 label: None,
-                layout: &uniform_bind_group_layout,
+                layout: uniform_bind_group_layout,
                 #[cfg(not(target_arch = "wasm32"))]
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: uniform_buffer.as_entire_binding()
-                }],
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding()
+                    },
+                    group_binding(group_buffer)
+                ],
                 
                 #[cfg(target_arch = "wasm32")]
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: uniform_buffer,
-                        offset: 0,
-                        size: BufferSize::new(32 as u64)
-                    })
-                }]
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: uniform_buffer,
+                            offset: 0,
+                            size: wgpu::BufferSize::new(std::mem::size_of::<Uniforms>() as u64)
+                        })
+                    },
+                    group_binding(group_buffer)
+                ]
             }
-        );
-
-        (uniform_bind_group_layout, uniform_bind_group)
+        )
     }
 
     pub fn get_render_setting(
         device: &wgpu::Device,
         uniform_bind_group_layout: &wgpu::BindGroupLayout,
-        shader: &wgpu::ShaderModule,
-        config: &wgpu::SurfaceConfiguration
+        vertex_shader: &wgpu::ShaderModule,
+        fragment_shader: &wgpu::ShaderModule,
+        config: &wgpu::SurfaceConfiguration,
+        state_config: &crate::config::WgpuStateConfig
     ) -> wgpu::RenderPipeline {
+        Self::get_render_setting_for::<crate::instance::Instance>(
+            device,
+            uniform_bind_group_layout,
+            vertex_shader,
+            fragment_shader,
+            config,
+            state_config,
+        )
+    }
+
+    // インスタンスバッファのスロット 1 を I::buffer_layout で読むパイプライン
+    // 独自のインスタンスの型で描く場合に、同梱の頂点バッファや uniform とそのまま組み合わせて使う
+    pub fn get_render_setting_for<I: crate::instance::InstanceData>(
+        device: &wgpu::Device,
+        uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        vertex_shader: &wgpu::ShaderModule,
+        fragment_shader: &wgpu::ShaderModule,
+        config: &wgpu::SurfaceConfiguration,
+        state_config: &crate::config::WgpuStateConfig
+    ) -> wgpu::RenderPipeline {
+        Self::get_render_setting_blended(
+            device,
+            uniform_bind_group_layout,
+            vertex_shader,
+            fragment_shader,
+            config,
+            state_config,
+            InstanceLayout { buffer_layout: I::buffer_layout(), translucent: I::TRANSLUCENT },
+        )
+    }
+
+    // get_render_setting_for と同じだが、インスタンスの頂点属性の並びを実行時の値で受け取る
+    // WgpuState は set_instance_data で受け取った型の並びと、描いているインスタンスに半透明のものがあるかを渡す
+    pub(crate) fn get_render_setting_blended(
+        device: &wgpu::Device,
+        uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        vertex_shader: &wgpu::ShaderModule,
+        fragment_shader: &wgpu::ShaderModule,
+        config: &wgpu::SurfaceConfiguration,
+        state_config: &crate::config::WgpuStateConfig,
+        instances: InstanceLayout,
+    ) -> wgpu::RenderPipeline {
+        let blend = if state_config.alpha_blending() || instances.translucent {
+            if state_config.premultiplied_alpha {
+                wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING
+            } else {
+                wgpu::BlendState::ALPHA_BLENDING
+            }
+        } else {
+            wgpu::BlendState::REPLACE
+        };
+        // 塗りつぶしとポイント描画では、形の補間先の頂点をスロット 2 から読む
+        let buffers = if state_config.outline_mode {
+            vec![
+                crate::vertex::LineVertex::get_vertex_buffer_layout(),
+                instances.buffer_layout,
+            ]
+        } else {
+            vec![
+                crate::vertex::Vertex::get_vertex_buffer_layout(),
+                instances.buffer_layout,
+                crate::vertex::Vertex::get_morph_buffer_layout(),
+            ]
+        };
+        // 輪郭線の四角形は辺の向きによって巻き順が変わるのでカリングしない
+        let cull_mode = if state_config.outline_mode { None } else { Some(wgpu::Face::Back) };
+
+        // 同梱シェーダの override 定数。カスタムのフラグメントシェーダは宣言していないことがあるので渡さない
+        let mut constants = std::collections::HashMap::new();
+        if state_config.fragment_shader.is_none() {
+            constants.insert(
+                String::from("premultipliedAlpha"),
+                if state_config.premultiplied_alpha { 1.0 } else { 0.0 },
+            );
+            let hdr_output = state_config.hdr && config.format == crate::state::WgpuState::HDR_FORMAT;
+            constants.insert(String::from("hdrOutput"), if hdr_output { 1.0 } else { 0.0 });
+            constants.insert(
+                String::from("encodeSrgb"),
+                if shader_encodes_srgb(config.format) { 1.0 } else { 0.0 },
+            );
+        }
+
         let render_pipeline_layout = device.create_pipeline_layout(
             &wgpu::PipelineLayoutDescriptor {
                 label: None,
-                bind_group_layouts: &[&uniform_bind_group_layout],
+                bind_group_layouts: &[uniform_bind_group_layout],
                 push_constant_ranges: &[]
             }
         );
 
-        let render_pipeline = device.create_render_pipeline(
+        device.create_render_pipeline(
             &wgpu::RenderPipelineDescriptor {
                 label: None,
                 layout: Some(&render_pipeline_layout),
                 vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: Some("vertexMain"),
+                    module: vertex_shader,
+                    entry_point: Some(state_config.vertex_entry_point()),
                     compilation_options: Default::default(),
-                    buffers: &[
-                        crate::vertex::Vertex::get_vertex_buffer_layout(),
-                        crate::instance::get_instance_buffer_layout()   
-                    ]
+                    buffers: &buffers
                 },
                 fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: Some("fragmentMain"),
+                    module: fragment_shader,
+                    entry_point: Some(state_config.fragment_entry_point()),
                     targets: &[Some(wgpu::ColorTargetState {
                         format: config.format,
-                        blend: Some(wgpu::BlendState::REPLACE),
+                        blend: Some(blend),
                         write_mask: wgpu::ColorWrites::ALL
                     })],
-                    compilation_options: Default::default()
+                    compilation_options: wgpu::PipelineCompilationOptions {
+                        constants: &constants,
+                        ..Default::default()
+                    }
                 }),
                 primitive: wgpu::PrimitiveState {
                     topology: wgpu::PrimitiveTopology::TriangleList,
                     strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: Some(wgpu::Face::Back),
+                    front_face: FRONT_FACE,
+                    cull_mode,
                     polygon_mode: wgpu::PolygonMode::Fill,
                     unclipped_depth: false,
                     conservative: false,
@@ -126,8 +415,7 @@ impl Uniforms {
                 multiview: None,
                 cache: None
             }
-        );
-        return render_pipeline
+        )
     }
 }
 
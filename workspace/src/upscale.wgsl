@@ -0,0 +1,25 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@binding(0) @group(0) var source: texture_2d<f32>;
+@binding(1) @group(0) var sourceSampler: sampler;
+
+// 描画先全体を覆う1枚の三角形。uv はテクスチャの座標 (左上が 0.0、右下が 1.0)
+@vertex
+fn vertexMain(@builtin(vertex_index) vertexIdx: u32) -> VertexOutput {
+    let x = f32(i32(vertexIdx & 1u) * 4 - 1);
+    let y = f32(i32(vertexIdx >> 1u) * 4 - 1);
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+// 内部解像度で描いた星を、サンプラーのフィルタで引き伸ばしてそのまま書き込む
+@fragment
+fn fragmentMain(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(source, sourceSampler, in.uv);
+}
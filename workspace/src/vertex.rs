@@ -5,18 +5,28 @@ pub struct Vertex {
 }
 
 impl Vertex {
-    pub fn get_vertices() -> Vec<Vertex> {
+    // 中心点と、星の外側の num_points 個の頂点
+    pub fn get_vertices(num_points: u32) -> Vec<Vertex> {
+        Self::get_oriented_vertices(num_points, false)
+    }
+
+    // inverted の場合は最初の外側の頂点を -y 側ではなく +y 側に置き、星形を上下反転した向きにする
+    // π 回転しただけなので三角形の巻き方向は変わらず、カリングの設定はそのままでよい
+    pub fn get_oriented_vertices(num_points: u32, inverted: bool) -> Vec<Vertex> {
         let mut vertices = Vec::new();
-        let num_points = 5;
         let radius = 1.0;
+        let start_angle = if inverted {
+            std::f32::consts::FRAC_PI_2
+        } else {
+            -std::f32::consts::FRAC_PI_2
+        };
         
         // 中心点を最初に追加
         vertices.push(Vertex { position: [0.0, 0.0] });
         
         // 外側の頂点を計算
         for i in 0..num_points {
-            let angle = (i as f32 * 2.0 * std::f32::consts::PI / num_points as f32) 
-                - std::f32::consts::FRAC_PI_2;
+            let angle = (i as f32 * 2.0 * std::f32::consts::PI / num_points as f32) + start_angle;
             vertices.push(Vertex {
                 position: [
                     radius * angle.cos(),
@@ -27,6 +37,16 @@ impl Vertex {
         vertices
     }
     
+    // ポイント描画用の四角形 (反時計回り)
+    pub fn get_quad_vertices() -> Vec<Vertex> {
+        vec![
+            Vertex { position: [-1.0, -1.0] },
+            Vertex { position: [1.0, -1.0] },
+            Vertex { position: [1.0, 1.0] },
+            Vertex { position: [-1.0, 1.0] },
+        ]
+    }
+
     pub fn get_vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
@@ -41,16 +61,308 @@ impl Vertex {
         }
     }
 
-    pub fn get_vertex_buffer(device: &wgpu::Device,vertices: &Vec<Self>) -> wgpu::Buffer {
+    // 形の補間先の頂点を読むスロット 2 のレイアウト (頂点バッファと同じ並びで、位置だけを @location(10) に渡す)
+    pub fn get_morph_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                    shader_location: 10,
+                }
+            ]
+        }
+    }
+
+    // {num_points/skip} 星形多角形 (skip が 1 なら正多角形) の輪郭を、中心から等間隔の角度で引いた
+    // samples 本の半直線との交点で表す。先頭は中心点で、fan_indices と組み合わせて塗りつぶす
+    // 形が違っても samples が同じなら頂点が1対1に対応するので、頂点ごとに補間して形を変化させられる
+    // samples が 2 * num_points の倍数なら、外側の頂点と内側のくぼみがそのまま頂点になる
+    pub fn get_radial_vertices(num_points: u32, skip: u32, samples: u32, inverted: bool) -> Vec<Vertex> {
+        Self::get_sharpened_radial_vertices(num_points, skip, samples, inverted, 1.0)
+    }
+
+    // get_radial_vertices の内側のくぼみの半径 r を r^tip_sharpness にずらした形
+    // 1.0 で {num_points/skip} そのまま、大きいほどくぼみが中心に寄って先端が細く、0.0 に近いほど丸く太った形になる
+    pub fn get_sharpened_radial_vertices(
+        num_points: u32,
+        skip: u32,
+        samples: u32,
+        inverted: bool,
+        tip_sharpness: f32,
+    ) -> Vec<Vertex> {
+        use std::f32::consts::{FRAC_PI_2, PI};
+        let start_angle = if inverted { FRAC_PI_2 } else { -FRAC_PI_2 };
+        let skip = skip.min(num_points - skip) as f32;
+        let n = num_points as f32;
+        // 外側の頂点 (半径 1.0) と、隣り合う辺が交わる内側のくぼみが π / n ごとに交互に並ぶ
+        let inner_radius = ((PI * skip / n).cos() / (PI * (skip - 1.0) / n).cos()).powf(tip_sharpness);
+        let corner = |index: u32| {
+            let angle = index as f32 * PI / n;
+            let radius = if index.is_multiple_of(2) { 1.0 } else { inner_radius };
+            [radius * angle.cos(), radius * angle.sin()]
+        };
+
+        let mut vertices = vec![Vertex { position: [0.0, 0.0] }];
+        for sample in 0..samples {
+            let angle = sample as f32 * 2.0 * PI / samples as f32;
+            // この角度を挟む頂点とくぼみを結ぶ辺と、半直線の交点までの距離
+            let sector = (sample as u64 * 2 * num_points as u64 / samples as u64) as u32;
+            let [ax, ay] = corner(sector);
+            let [bx, by] = corner(sector + 1);
+            let (dx, dy) = (angle.cos(), angle.sin());
+            let distance = (ax * (by - ay) - ay * (bx - ax)) / (dx * (by - ay) - dy * (bx - ax));
+            let rotated = angle + start_angle;
+            vertices.push(Vertex { position: [distance * rotated.cos(), distance * rotated.sin()] });
+        }
+        vertices
+    }
+
+    pub fn get_vertex_buffer(device: &wgpu::Device,vertices: &[Self]) -> wgpu::Buffer {
         use wgpu::util::DeviceExt;
-        let vertex_buffer = device.create_buffer_init(
+        device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(&vertices),
+                contents: bytemuck::cast_slice(vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            }
+        )
+    }
+}
+
+// 各三角形の辺を subdivisions + 1 等分して格子状に分割する (三角形の数は (subdivisions + 1)^2 倍になる)
+// 巻き方向は元の三角形と同じに保つ。頂点シェーダで頂点を動かす効果のために使う
+pub fn subdivide_triangles(vertices: &[Vertex], indices: &[u16], subdivisions: u32) -> (Vec<Vertex>, Vec<u16>) {
+    if subdivisions == 0 {
+        return (vertices.to_vec(), indices.to_vec());
+    }
+    let segments = subdivisions as usize + 1;
+
+    // 三角形内の格子点 (i, j) (i + j <= segments) の通し番号
+    let row_starts: Vec<usize> = (0..=segments)
+        .scan(0, |start, i| {
+            let row_start = *start;
+            *start += segments - i + 1;
+            Some(row_start)
+        })
+        .collect();
+    let local = |i: usize, j: usize| row_starts[i] + j;
+
+    let mut new_vertices = Vec::new();
+    let mut new_indices = Vec::new();
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| vertices[i as usize].position);
+        let base = new_vertices.len();
+        for i in 0..=segments {
+            for j in 0..=(segments - i) {
+                let (u, v) = (i as f32 / segments as f32, j as f32 / segments as f32);
+                new_vertices.push(Vertex {
+                    position: [
+                        a[0] + (b[0] - a[0]) * u + (c[0] - a[0]) * v,
+                        a[1] + (b[1] - a[1]) * u + (c[1] - a[1]) * v,
+                    ],
+                });
+            }
+        }
+        for i in 0..segments {
+            for j in 0..(segments - i) {
+                new_indices.extend([local(i, j), local(i + 1, j), local(i, j + 1)].map(|k| (base + k) as u16));
+                if i + j + 1 < segments {
+                    new_indices.extend(
+                        [local(i + 1, j), local(i + 1, j + 1), local(i, j + 1)].map(|k| (base + k) as u16),
+                    );
+                }
+            }
+        }
+    }
+    (new_vertices, new_indices)
+}
+
+// get_radial_vertices の中心と輪郭の samples 個の点を、反時計回りの扇状の三角形で結ぶ
+pub fn fan_indices(samples: u32) -> Vec<u16> {
+    (0..samples)
+        .flat_map(|sample| [0, 1 + sample as u16, 1 + ((sample + 1) % samples) as u16])
+        .collect()
+}
+
+// 2つの星形を補間するときに輪郭に置く点の数 (どちらの外側の頂点とくぼみも含む最小の数)
+pub(crate) fn morph_sample_count(points: u32, other_points: u32) -> u32 {
+    let (a, b) = (2 * points, 2 * other_points);
+    a / gcd(a, b) * b
+}
+
+// subdivide_triangles で triangle_count 個の三角形を分割したときの頂点数
+pub(crate) fn subdivided_vertex_count(triangle_count: u32, subdivisions: u32) -> u64 {
+    let segments = subdivisions as u64 + 1;
+    triangle_count as u64 * (segments + 1) * (segments + 2) / 2
+}
+
+pub(crate) fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// 各三角形の符号付き面積が FRONT_FACE の巻き方向と一致するかを調べ、
+// 一致しない (カリングで消える) 三角形の番号を返す
+pub fn validate_winding(vertices: &[Vertex], indices: &[u16]) -> Result<(), Vec<usize>> {
+    let expected_sign = match crate::uniform::FRONT_FACE {
+        wgpu::FrontFace::Ccw => 1.0,
+        wgpu::FrontFace::Cw => -1.0,
+    };
+
+    let invalid: Vec<usize> = indices
+        .chunks_exact(3)
+        .enumerate()
+        .filter(|(_, triangle)| {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+                .map(|i| vertices[i as usize].position);
+            let area = (b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1]);
+            area * expected_sign <= 0.0
+        })
+        .map(|(i, _)| i)
+        .collect();
+
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(invalid)
+    }
+}
+
+// 輪郭線を太い線として描くための頂点
+// 辺の両端を持たせ、頂点シェーダで画面空間の太さに展開する
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+    pub start: [f32; 2],
+    pub end: [f32; 2],
+    // [辺に沿った位置 (0.0 / 1.0), 辺のどちら側か (-1.0 / 1.0)]
+    pub params: [f32; 2],
+}
+
+impl LineVertex {
+    // 星の外側の頂点を {num_points/skip} の順に結んだ輪郭の各辺を四角形にする
+    pub fn get_outline_vertices(num_points: u32, skip: u32) -> (Vec<LineVertex>, Vec<u16>) {
+        Self::get_oriented_outline_vertices(num_points, skip, false)
+    }
+
+    // inverted は Vertex::get_oriented_vertices と同じ
+    pub fn get_oriented_outline_vertices(num_points: u32, skip: u32, inverted: bool) -> (Vec<LineVertex>, Vec<u16>) {
+        let points = Vertex::get_oriented_vertices(num_points, inverted);
+        let outer = &points[1..];
+        let num_points = outer.len();
+        let skip = skip as usize;
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for i in 0..num_points {
+            let start = outer[i].position;
+            let end = outer[(i + skip) % num_points].position;
+            let base = vertices.len() as u16;
+            for params in [[0.0, -1.0], [0.0, 1.0], [1.0, -1.0], [1.0, 1.0]] {
+                vertices.push(LineVertex { start, end, params });
+            }
+            indices.extend_from_slice(&[base, base + 2, base + 1, base + 1, base + 2, base + 3]);
+        }
+        (vertices, indices)
+    }
+
+    // get_sharpened_radial_vertices の外側の頂点とくぼみを順に結んだ輪郭 (2 * num_points 本の辺) の各辺を四角形にする
+    pub fn get_sharpened_outline_vertices(
+        num_points: u32,
+        skip: u32,
+        inverted: bool,
+        tip_sharpness: f32,
+    ) -> (Vec<LineVertex>, Vec<u16>) {
+        let samples = 2 * num_points;
+        let points = Vertex::get_sharpened_radial_vertices(num_points, skip, samples, inverted, tip_sharpness);
+        let outline = &points[1..];
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for (i, point) in outline.iter().enumerate() {
+            let start = point.position;
+            let end = outline[(i + 1) % outline.len()].position;
+            let base = vertices.len() as u16;
+            for params in [[0.0, -1.0], [0.0, 1.0], [1.0, -1.0], [1.0, 1.0]] {
+                vertices.push(LineVertex { start, end, params });
+            }
+            indices.extend_from_slice(&[base, base + 2, base + 1, base + 1, base + 2, base + 3]);
+        }
+        (vertices, indices)
+    }
+
+    pub fn get_vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+        static ATTRIBUTES: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+            0 => Float32x2,
+            1 => Float32x2,
+            8 => Float32x2
+        ];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+
+    pub fn get_vertex_buffer(device: &wgpu::Device, vertices: &[Self]) -> wgpu::Buffer {
+        use wgpu::util::DeviceExt;
+        device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Line Vertex Buffer"),
+                contents: bytemuck::cast_slice(vertices),
                 usage: wgpu::BufferUsages::VERTEX,
             }
-        );
-        vertex_buffer
+        )
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subdivision_multiplies_triangle_count() {
+        let vertices = Vertex::get_vertices(5);
+        let indices: Vec<u16> = (0..5u16).flat_map(|i| [0, 1 + i, 1 + (i + 2) % 5]).collect();
+        for subdivisions in 0..=4 {
+            let (new_vertices, new_indices) = subdivide_triangles(&vertices, &indices, subdivisions);
+            let segments = subdivisions as usize + 1;
+            assert_eq!(new_indices.len(), indices.len() * segments * segments);
+            if subdivisions > 0 {
+                assert_eq!(new_vertices.len() as u64, subdivided_vertex_count(5, subdivisions));
+            }
+            assert_eq!(validate_winding(&new_vertices, &new_indices), Ok(()));
+        }
+        // 辺の分割数を倍にするごとに三角形は4倍になる
+        let triangle_counts: Vec<usize> = [0, 1, 3, 7]
+            .map(|subdivisions| subdivide_triangles(&vertices, &indices, subdivisions).1.len() / 3)
+            .to_vec();
+        assert!(triangle_counts.windows(2).all(|pair| pair[1] == pair[0] * 4));
+    }
+
+    #[test]
+    fn tip_sharpness_moves_only_inner_vertices() {
+        let radii = |tip_sharpness: f32| -> Vec<f32> {
+            Vertex::get_sharpened_radial_vertices(5, 2, 10, false, tip_sharpness)[1..]
+                .iter()
+                .map(|vertex| vertex.position[0].hypot(vertex.position[1]))
+                .collect()
+        };
+        let base = radii(1.0);
+        for tip_sharpness in [0.5, 2.0, 3.0] {
+            for (sharpened, base) in radii(tip_sharpness).iter().zip(&base).step_by(2) {
+                assert!((sharpened - 1.0).abs() < 1e-5 && (base - 1.0).abs() < 1e-5);
+            }
+            for (sharpened, base) in radii(tip_sharpness).iter().zip(&base).skip(1).step_by(2) {
+                assert!((sharpened - base.powf(tip_sharpness)).abs() < 1e-5, "{sharpened} vs {base}^{tip_sharpness}");
+            }
+        }
+    }
+}